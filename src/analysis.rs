@@ -0,0 +1,214 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{sync::atomic::{AtomicBool, Ordering}, thread::JoinHandle};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+const FFT_SIZE: usize = 2048;
+// Analysis runs every HOP mono samples, so the envelopes advance in fixed steps
+// whatever thread or frame rate drives them
+const HOP: usize = 512;
+pub const BANDS: usize = 8;
+const LOWEST_FREQUENCY: f32 = 40.0;
+const HIGHEST_FREQUENCY: f32 = 16000.0;
+// Band levels are mapped from this many dB below full scale up to 0 dB onto 0..1
+const DYNAMIC_RANGE: f32 = 60.0;
+// Seconds for an envelope to fall to 1/e after a peak
+const ENERGY_RELEASE: f32 = 0.15;
+const ONSET_RELEASE: f32 = 0.1;
+// Seconds the running average that onsets stand out from follows over
+const AVERAGE_TIME: f32 = 0.5;
+// Results kept for looking up what is being heard, longer than any output latency
+const HISTORY: usize = 512;
+
+/// The music's spectrum as every render pipeline sees it, at binding 2 of the
+/// uniform group. Bands are log-spaced from bass up; `energy` follows each band's
+/// level with a quick attack and slower release, `onset` jumps when a band rises
+/// above its recent average. Everything is 0 in silence.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AudioUniform {
+    pub energy: [f32; BANDS],
+    pub onset: [f32; BANDS],
+    pub level: f32,
+    _padding: [f32; 3],
+}
+
+/// Turns the samples that go out to the speakers into an `AudioUniform`.
+pub struct Analyser {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // The last FFT_SIZE mono samples, oldest at `write`
+    history: Vec<f32>,
+    write: usize,
+    since_hop: usize,
+    band_bins: [(usize, usize); BANDS],
+    average: [f32; BANDS],
+    energy_decay: f32,
+    onset_decay: f32,
+    average_rate: f32,
+    buffer: Vec<Complex<f32>>,
+    uniform: AudioUniform,
+    // Frames pushed so far, and the result as of each hop
+    frames: u64,
+    results: VecDeque<(u64, AudioUniform)>,
+}
+
+impl Analyser {
+    pub fn new(sample_rate: u32) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let highest = HIGHEST_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let edge = |band: usize| {
+            let frequency = LOWEST_FREQUENCY * (highest / LOWEST_FREQUENCY).powf(band as f32 / BANDS as f32);
+            ((frequency / bin_width).round() as usize).clamp(1, FFT_SIZE / 2)
+        };
+        // Every band gets at least one bin, even where the low ones are narrower
+        let band_bins = std::array::from_fn(|band| {
+            let start = edge(band);
+            (start, edge(band + 1).max(start + 1))
+        });
+        let hop_time = HOP as f32 / sample_rate as f32;
+        Analyser {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            history: vec![0.0; FFT_SIZE],
+            write: 0,
+            since_hop: 0,
+            band_bins,
+            average: [0.0; BANDS],
+            energy_decay: (-hop_time / ENERGY_RELEASE).exp(),
+            onset_decay: (-hop_time / ONSET_RELEASE).exp(),
+            average_rate: 1.0 - (-hop_time / AVERAGE_TIME).exp(),
+            buffer: vec![Complex::default(); FFT_SIZE],
+            uniform: AudioUniform::default(),
+            frames: 0,
+            results: VecDeque::new(),
+        }
+    }
+
+    /// Takes interleaved stereo samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(2) {
+            self.history[self.write] = (frame[0] + frame[1]) * 0.5;
+            self.write = (self.write + 1) % FFT_SIZE;
+            self.frames += 1;
+            self.since_hop += 1;
+            if self.since_hop == HOP {
+                self.since_hop = 0;
+                self.analyse();
+                if self.results.len() == HISTORY {
+                    self.results.pop_front();
+                }
+                self.results.push_back((self.frames, self.uniform));
+            }
+        }
+    }
+
+    /// The latest result, or with `frame`, the one for the music at that frame
+    /// counting from the first pushed.
+    pub fn uniform(&self, frame: Option<u64>) -> AudioUniform {
+        match frame {
+            Some(frame) => self.results.iter().rev()
+                .find(|(at, _)| *at <= frame)
+                .map_or_else(AudioUniform::default, |&(_, uniform)| uniform),
+            None => self.uniform,
+        }
+    }
+
+    fn analyse(&mut self) {
+        let (newer, older) = self.history.split_at(self.write);
+        let samples = older.iter().chain(newer);
+        for ((out, sample), window) in self.buffer.iter_mut().zip(samples).zip(&self.window) {
+            *out = Complex::new(sample * window, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        // A full scale sine comes out at FFT_SIZE / 4 with the Hann window
+        let scale = 4.0 / FFT_SIZE as f32;
+        let uniform = &mut self.uniform;
+        for (band, &(start, end)) in self.band_bins.iter().enumerate() {
+            let power = self.buffer[start..end].iter().map(|bin| bin.norm_sqr()).sum::<f32>() * scale * scale;
+            let level = ((10.0 * power.max(1e-12).log10() + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0.0, 1.0);
+            uniform.energy[band] = level.max(uniform.energy[band] * self.energy_decay);
+            let rise = (level - self.average[band]).max(0.0);
+            uniform.onset[band] = rise.max(uniform.onset[band] * self.onset_decay);
+            self.average[band] += (level - self.average[band]) * self.average_rate;
+        }
+        let rms = (self.history.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+        uniform.level = ((20.0 * rms.max(1e-6).log10() + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0.0, 1.0);
+    }
+}
+
+/// An `Analyser` fed from the audio callback. The callback only copies its
+/// samples in; `run` does the analysis, on an `AnalysisThread` when playing live
+/// natively, every frame on the web, or right after the samples are pulled when
+/// rendering offline.
+pub struct Analysis {
+    pending: Mutex<Vec<f32>>,
+    analyser: Mutex<Analyser>,
+}
+
+impl Analysis {
+    pub fn new(sample_rate: u32) -> Self {
+        Analysis {
+            pending: Mutex::new(vec![]),
+            analyser: Mutex::new(Analyser::new(sample_rate)),
+        }
+    }
+
+    pub fn feed(&self, samples: &[f32]) {
+        self.pending.lock().unwrap().extend_from_slice(samples);
+    }
+
+    pub fn run(&self) {
+        let samples = std::mem::take(&mut *self.pending.lock().unwrap());
+        self.analyser.lock().unwrap().push(&samples);
+    }
+
+    /// See [`Analyser::uniform`].
+    pub fn uniform(&self, frame: Option<u64>) -> AudioUniform {
+        self.analyser.lock().unwrap().uniform(frame)
+    }
+}
+
+/// Keeps analysing whatever the callback feeds an `Analysis`, until dropped.
+/// Not on the web, where there are no threads and `run` is called every frame
+/// instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct AnalysisThread {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AnalysisThread {
+    pub fn spawn(analysis: Arc<Analysis>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    analysis.run();
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                }
+            })
+        };
+        AnalysisThread {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for AnalysisThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector4};
+use serde::Deserialize;
+use web_time::{Duration, Instant};
+
+use crate::{scene::Laser, timeline::Scene};
+
+// A channel mapping file looks like
+//
+//   (
+//       universe: 0,
+//       channels: [
+//           (1, Red(1)),        // laser 1's colour, 0-255 per component
+//           (2, Green(1)),
+//           (3, Blue(1)),
+//           (4, Pan(1)),        // its heading around the grid, 0-360 degrees
+//           (5, Tilt(1)),       // and how far it leans from vertical, 0-90 degrees
+//           (10, Scene),        // Slide 0, Black 1, CDs 2, StarWars 3, Ocean 4, Smoke 5
+//           (11, SceneNumber),  // the scene's number, clamped to 0-255
+//           (12, Beat),         // jumps to 255 on every beat and fades
+//           (13, Transition),   // how far the transition has got
+//           (14, Fixed(255)),   // e.g. a fixture's master dimmer
+//       ],
+//   )
+//
+// Channels count from 1 to 512, unmapped ones stay at 0. Lasers count from 1 and
+// are 0 while the scene doesn't show them.
+
+pub const PORT: u16 = 6454;
+// A full universe takes about 22.7 ms on the DMX line, so fixtures can't follow
+// anything faster than 44 frames a second
+const DMX_INTERVAL: Duration = Duration::from_micros(22_727);
+// Seconds for a beat pulse to fade to 1/e
+const BEAT_FADE: f32 = 0.1;
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+
+/// What a DMX channel follows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum DmxSource {
+    Red(usize),
+    Green(usize),
+    Blue(usize),
+    Pan(usize),
+    Tilt(usize),
+    Scene,
+    SceneNumber,
+    Beat,
+    Transition,
+    Fixed(u8),
+}
+
+/// Which channels of which universe the show state goes to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DmxMapping {
+    /// The 15-bit Art-Net port address: net, sub-net and universe
+    #[serde(default)]
+    pub universe: u16,
+    pub channels: Vec<(u16, DmxSource)>,
+}
+
+impl Default for DmxMapping {
+    /// Lasers 1-4 with five channels each from channel 1, then the scene, its
+    /// number, the beat and the transition on 21-24.
+    fn default() -> Self {
+        let lasers = (1..=4).flat_map(|laser| [
+            DmxSource::Red(laser),
+            DmxSource::Green(laser),
+            DmxSource::Blue(laser),
+            DmxSource::Pan(laser),
+            DmxSource::Tilt(laser),
+        ]);
+        let show = [DmxSource::Scene, DmxSource::SceneNumber, DmxSource::Beat, DmxSource::Transition];
+        DmxMapping {
+            universe: 0,
+            channels: lasers.chain(show).zip(1..).map(|(source, channel)| (channel, source)).collect(),
+        }
+    }
+}
+
+impl DmxMapping {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        Self::parse(&path.display().to_string(), &source)
+    }
+
+    pub fn parse(name: &str, source: &str) -> Result<Self> {
+        let mapping: DmxMapping = ron::from_str(source)
+            .map_err(|e| anyhow!("{}:{}", name, e))?;
+        if mapping.universe > 0x7fff {
+            bail!("{}: universe {} is beyond the 15 bits Art-Net has", name, mapping.universe);
+        }
+        for &(channel, source) in &mapping.channels {
+            if !(1..=512).contains(&channel) {
+                bail!("{}: channel {} isn't between 1 and 512", name, channel);
+            }
+            if let DmxSource::Red(0) | DmxSource::Green(0) | DmxSource::Blue(0) | DmxSource::Pan(0) | DmxSource::Tilt(0) = source {
+                bail!("{}: lasers count from 1", name);
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+/// A laser as a lighting fixture would show it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LaserLight {
+    /// 0-1 per component
+    pub color: [f32; 3],
+    /// Degrees around the grid's vertical axis, counterclockwise from +x
+    pub pan: f32,
+    /// Degrees from vertical
+    pub tilt: f32,
+}
+
+impl LaserLight {
+    pub fn from_laser(laser: &Laser) -> Self {
+        let to_grid = Matrix4::from(laser.transform).invert().unwrap_or_else(Matrix4::identity);
+        let direction = (to_grid*Vector4::new(0.0, 0.0, 1.0, 0.0)).truncate();
+        LaserLight {
+            color: [laser.color[0], laser.color[1], laser.color[2]],
+            pan: direction.y.atan2(direction.x).to_degrees().rem_euclid(360.0),
+            tilt: (direction.z/direction.magnitude().max(1e-6)).clamp(-1.0, 1.0).acos().to_degrees(),
+        }
+    }
+}
+
+/// The part of the show that is mirrored onto the lights.
+#[derive(Clone, Debug)]
+pub struct ShowState {
+    pub lasers: Vec<LaserLight>,
+    pub scene: Scene,
+    /// Seconds since the last beat
+    pub since_beat: f32,
+    /// 0 as the transition into the scene starts, 1 once it's over
+    pub transition: f32,
+}
+
+/// Sends the show state as Art-Net DMX packets over UDP, at most as often as a
+/// DMX line refreshes, without ever blocking the frame.
+pub struct ArtNet {
+    socket: UdpSocket,
+    target: SocketAddr,
+    mapping: DmxMapping,
+    // Bytes of DMX data sent, the highest mapped channel rounded up to even
+    length: usize,
+    sequence: u8,
+    last_sent: Option<Instant>,
+    failing: bool,
+}
+
+impl ArtNet {
+    /// `address` is a node's or a broadcast IP address, on the Art-Net port
+    /// unless another one is given.
+    pub fn connect(address: &str, mapping: DmxMapping) -> io::Result<Self> {
+        let target = match address.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, PORT),
+            Err(_) => address.to_socket_addrs()?.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", address)))?,
+        };
+        let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        log::info!("sending Art-Net to {}, universe {}", target, mapping.universe);
+        let highest = mapping.channels.iter().map(|&(channel, _)| channel as usize).max().unwrap_or(0);
+        Ok(ArtNet {
+            socket,
+            target,
+            length: (highest + highest % 2).max(2),
+            mapping,
+            sequence: 0,
+            last_sent: None,
+            failing: false,
+        })
+    }
+
+    /// Sends `state`, unless the last packet went out less than a DMX frame ago.
+    /// Returns whether it was sent.
+    pub fn update(&mut self, state: &ShowState) -> bool {
+        let now = Instant::now();
+        if self.last_sent.is_some_and(|last| now.duration_since(last) < DMX_INTERVAL) {
+            return false;
+        }
+        self.last_sent = Some(now);
+        // 0 would tell the node not to reorder packets
+        self.sequence = self.sequence % 255 + 1;
+        let packet = self.packet(&self.dmx(state));
+        match self.socket.send_to(&packet, self.target) {
+            Ok(_) => self.failing = false,
+            // Only the first of a run of errors is worth seeing
+            Err(e) if !self.failing && e.kind() != io::ErrorKind::WouldBlock => {
+                log::error!("Art-Net to {}: {}", self.target, e);
+                self.failing = true;
+            }
+            Err(_) => {}
+        }
+        true
+    }
+
+    /// The universe's channel values for `state`, channel 1 first.
+    pub fn dmx(&self, state: &ShowState) -> [u8; 512] {
+        let mut data = [0; 512];
+        let byte = |value: f32| (value.clamp(0.0, 1.0)*255.0).round() as u8;
+        for &(channel, source) in &self.mapping.channels {
+            let laser = |number: usize| state.lasers.get(number - 1);
+            data[channel as usize - 1] = match source {
+                DmxSource::Red(number) => laser(number).map_or(0, |laser| byte(laser.color[0])),
+                DmxSource::Green(number) => laser(number).map_or(0, |laser| byte(laser.color[1])),
+                DmxSource::Blue(number) => laser(number).map_or(0, |laser| byte(laser.color[2])),
+                DmxSource::Pan(number) => laser(number).map_or(0, |laser| byte(laser.pan/360.0)),
+                DmxSource::Tilt(number) => laser(number).map_or(0, |laser| byte(laser.tilt/90.0)),
+                DmxSource::Scene => match state.scene {
+                    Scene::Slide(_) => 0,
+                    Scene::Black => 1,
+                    Scene::CDs(_) => 2,
+                    Scene::StarWars(_) => 3,
+                    Scene::Ocean(_) => 4,
+                    Scene::Smoke(_) => 5,
+                },
+                DmxSource::SceneNumber => match state.scene {
+                    Scene::Slide(number) | Scene::CDs(number) | Scene::StarWars(number)
+                    | Scene::Ocean(number) | Scene::Smoke(number) => number.clamp(0, 255) as u8,
+                    Scene::Black => 0,
+                },
+                DmxSource::Beat => byte((-state.since_beat.max(0.0)/BEAT_FADE).exp()),
+                DmxSource::Transition => byte(state.transition),
+                DmxSource::Fixed(value) => value,
+            };
+        }
+        data
+    }
+
+    fn packet(&self, data: &[u8; 512]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(18 + self.length);
+        packet.extend_from_slice(b"Art-Net\0");
+        packet.extend_from_slice(&OP_DMX.to_le_bytes());
+        packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        packet.push(self.sequence);
+        // Physical input port, only informative
+        packet.push(0);
+        // SubUni then Net
+        packet.extend_from_slice(&self.mapping.universe.to_le_bytes());
+        packet.extend_from_slice(&(self.length as u16).to_be_bytes());
+        packet.extend_from_slice(&data[..self.length]);
+        packet
+    }
+}
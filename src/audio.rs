@@ -0,0 +1,340 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, FromSample, SampleFormat, SizedSample, StreamError, SupportedBufferSize, SupportedStreamConfig,
+};
+use web_time::{Duration, Instant};
+
+use crate::{analysis::Analysis, clock::AudioCounter, music::Player};
+
+// Frames per callback, when the device allows it
+const BUFFER_FRAMES: u32 = 256;
+// Sample rate when there's no device to ask
+const NULL_SAMPLE_RATE: u32 = 48000;
+
+/// What the audio callback plays from, and who it tells about it.
+#[derive(Clone)]
+pub struct Source {
+    pub player: Arc<Mutex<Box<dyn Player>>>,
+    /// Silence while set, without moving the music or the clock on
+    pub paused: Arc<AtomicBool>,
+    pub analysis: Arc<Analysis>,
+    pub counter: AudioCounter,
+    pub mute: bool,
+}
+
+impl Source {
+    // Fills `out` with interleaved stereo samples, which start being heard
+    // `latency` from now if the device can tell
+    fn render(&self, out: &mut [f32], latency: Option<Duration>) {
+        if self.paused.load(Ordering::Relaxed) {
+            out.fill(0.0);
+            return;
+        }
+        let mut player = self.player.lock().unwrap();
+        let mut rows = vec![];
+        let mut events = vec![];
+        for (i, frame) in out.chunks_exact_mut(2).enumerate() {
+            frame[0] = player.next().unwrap_or(0.0);
+            frame[1] = player.next().unwrap_or(0.0);
+            let position = player.position();
+            if rows.last().map(|&(_, last)| last) != Some(position) {
+                rows.push((i, position));
+            }
+            events.extend(player.take_events().into_iter().map(|event| (i, event)));
+        }
+        drop(player);
+        self.analysis.feed(out);
+        self.counter.advance((out.len() / 2) as u64, latency, &rows, &events);
+        // Keep pulling samples when muted so the music still keeps time
+        if self.mute {
+            out.fill(0.0);
+        }
+    }
+}
+
+/// The names of the output devices of the default host, the default one first.
+pub fn output_devices() -> Result<Vec<String>> {
+    let host = cpal::default_host();
+    let default = host.default_output_device().and_then(|device| device.name().ok());
+    let mut names = host.output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect::<Vec<_>>();
+    names.sort_by_key(|name| Some(name) != default.as_ref());
+    Ok(names)
+}
+
+/// Where the music is heard. Plays through an output device when there is one
+/// it can play to, otherwise, or once the device goes away, through a null sink
+/// that pulls the music in real time so the demo keeps its timing. Playback stops
+/// when this is dropped.
+pub struct AudioOutput {
+    device: Option<(cpal::Device, SupportedStreamConfig)>,
+    sample_rate: u32,
+    sink: Option<Sink>,
+    errors: Option<mpsc::Receiver<StreamError>>,
+    source: Option<Source>,
+}
+
+// Only held on to, playback runs until it's dropped
+enum Sink {
+    Device { _stream: cpal::Stream },
+    Null { _sink: NullSink },
+}
+
+impl AudioOutput {
+    /// Opens the first output device whose name contains `name`, or the default
+    /// one. Not finding a named device is an error; having no devices at all, or
+    /// one whose formats can't be played, isn't: the null sink plays instead.
+    pub fn open(name: Option<&str>) -> Result<Self> {
+        let host = cpal::default_host();
+        let device = match name {
+            Some(name) => Some(host.output_devices()?
+                .find(|device| device.name().is_ok_and(|device_name| device_name.contains(name)))
+                .ok_or_else(|| anyhow!("no audio output device named \"{}\"", name))?),
+            None => host.default_output_device(),
+        };
+        let Some(device) = device else {
+            log::warn!("no audio output device, playing silently");
+            return Ok(AudioOutput::null());
+        };
+        let config = match stream_config(&device) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("can't play to {}: {:#}, playing silently", device.name().unwrap_or_default(), e);
+                return Ok(AudioOutput::null());
+            }
+        };
+        log::info!(
+            "audio output: {} at {} Hz, {} channels of {}",
+            device.name().unwrap_or_default(), config.sample_rate().0, config.channels(), config.sample_format(),
+        );
+        Ok(AudioOutput {
+            sample_rate: config.sample_rate().0,
+            device: Some((device, config)),
+            sink: None,
+            errors: None,
+            source: None,
+        })
+    }
+
+    // Plays through the null sink only
+    fn null() -> Self {
+        AudioOutput {
+            device: None,
+            sample_rate: NULL_SAMPLE_RATE,
+            sink: None,
+            errors: None,
+            source: None,
+        }
+    }
+
+    /// The rate the music has to be rendered at.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Starts playing. If the device can't be started, the error is returned
+    /// and the null sink plays instead.
+    pub fn start(&mut self, source: Source) -> Result<()> {
+        self.source = Some(source.clone());
+        let Some((device, config)) = &self.device else {
+            self.sink = Some(Sink::Null { _sink: NullSink::start(source, self.sample_rate) });
+            return Ok(());
+        };
+        let (sender, receiver) = mpsc::channel();
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(device, config, source.clone(), sender),
+            SampleFormat::F64 => build_stream::<f64>(device, config, source.clone(), sender),
+            SampleFormat::I16 => build_stream::<i16>(device, config, source.clone(), sender),
+            SampleFormat::I32 => build_stream::<i32>(device, config, source.clone(), sender),
+            SampleFormat::U16 => build_stream::<u16>(device, config, source.clone(), sender),
+            SampleFormat::U8 => build_stream::<u8>(device, config, source.clone(), sender),
+            format => Err(anyhow!("unsupported sample format {}", format)),
+        }.and_then(|stream| {
+            stream.play()?;
+            Ok(stream)
+        });
+        match stream {
+            Ok(stream) => {
+                self.sink = Some(Sink::Device { _stream: stream });
+                self.errors = Some(receiver);
+                Ok(())
+            }
+            Err(e) => {
+                self.fall_back();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns what went wrong with the stream since the last call, if anything.
+    /// A device that went away is replaced by the null sink. On the web, where
+    /// the null sink has no thread to run on, this is also what moves it along,
+    /// so it has to be called every frame.
+    pub fn check(&mut self) -> Result<(), StreamError> {
+        #[cfg(target_arch = "wasm32")]
+        if let Some(Sink::Null { _sink: sink }) = &mut self.sink {
+            sink.pull();
+        }
+        let Some(error) = self.errors.as_ref().and_then(|errors| errors.try_recv().ok()) else {
+            return Ok(());
+        };
+        if matches!(error, StreamError::DeviceNotAvailable) {
+            self.fall_back();
+        }
+        Err(error)
+    }
+
+    fn fall_back(&mut self) {
+        log::warn!("audio output lost, playing silently");
+        // Stop the stream first so the two never both pull the music
+        self.sink = None;
+        self.errors = None;
+        if let Some(source) = self.source.clone() {
+            self.sink = Some(Sink::Null { _sink: NullSink::start(source, self.sample_rate) });
+        }
+    }
+}
+
+// The device's preferred configuration, in stereo if it can be
+fn stream_config(device: &cpal::Device) -> Result<SupportedStreamConfig> {
+    let default = device.default_output_config()?;
+    let stereo = (default.channels() != 2).then(|| device.supported_output_configs().ok()).flatten()
+        .and_then(|mut configs| configs.find(|range| {
+            range.channels() == 2
+                && range.sample_format() == default.sample_format()
+                && (range.min_sample_rate()..=range.max_sample_rate()).contains(&default.sample_rate())
+        }))
+        .map(|range| range.with_sample_rate(default.sample_rate()));
+    Ok(stereo.unwrap_or(default))
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    supported: &SupportedStreamConfig,
+    source: Source,
+    errors: mpsc::Sender<StreamError>,
+) -> Result<cpal::Stream> {
+    let mut config = supported.config();
+    if let SupportedBufferSize::Range { min, max } = supported.buffer_size() {
+        if (*min..=*max).contains(&BUFFER_FRAMES) {
+            config.buffer_size = BufferSize::Fixed(BUFFER_FRAMES);
+        }
+    }
+    let channels = config.channels as usize;
+    if channels == 0 {
+        bail!("the device has no channels");
+    }
+    let mut stereo = vec![];
+    Ok(device.build_output_stream(
+        &config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            stereo.resize(data.len() / channels * 2, 0.0);
+            source.render(&mut stereo, timestamp.playback.duration_since(&timestamp.callback));
+            for (frame, sample) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                match frame {
+                    [mono] => *mono = T::from_sample((sample[0] + sample[1]) * 0.5),
+                    [left, right, rest @ ..] => {
+                        *left = T::from_sample(sample[0]);
+                        *right = T::from_sample(sample[1]);
+                        rest.fill(T::EQUILIBRIUM);
+                    }
+                    [] => {}
+                }
+            }
+        },
+        move |error| {
+            let _ = errors.send(error);
+        },
+        None,
+    )?)
+}
+
+// Pulls the music at the rate a device would, without playing it anywhere. It
+// runs on a thread of its own, except on the web where `pull` is called every frame.
+struct NullSink {
+    #[cfg(not(target_arch = "wasm32"))]
+    stop: Arc<AtomicBool>,
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<JoinHandle<()>>,
+    #[cfg(target_arch = "wasm32")]
+    pacer: Pacer,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl NullSink {
+    fn start(source: Source, sample_rate: u32) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let mut pacer = Pacer::new(source, sample_rate);
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    pacer.pull();
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+        NullSink {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for NullSink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl NullSink {
+    fn start(source: Source, sample_rate: u32) -> Self {
+        NullSink { pacer: Pacer::new(source, sample_rate) }
+    }
+
+    fn pull(&mut self) {
+        self.pacer.pull();
+    }
+}
+
+// Renders whatever is due since it was created, as though it was being played
+struct Pacer {
+    source: Source,
+    sample_rate: u32,
+    started: Instant,
+    played: u64,
+    buffer: Vec<f32>,
+}
+
+impl Pacer {
+    fn new(source: Source, sample_rate: u32) -> Self {
+        Pacer {
+            source,
+            sample_rate,
+            started: Instant::now(),
+            played: 0,
+            buffer: vec![],
+        }
+    }
+
+    fn pull(&mut self) {
+        let due = (self.started.elapsed().as_secs_f64() * self.sample_rate as f64) as u64;
+        // Never more than a second at once, should it have stalled
+        let frames = (due - self.played).min(self.sample_rate as u64) as usize;
+        self.buffer.resize(frames * 2, 0.0);
+        self.source.render(&mut self.buffer, None);
+        self.played = due;
+    }
+}
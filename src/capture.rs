@@ -0,0 +1,220 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}, sync::mpsc, thread};
+
+use web_time::Duration;
+
+use crate::{
+    config::{Config, WavFormat},
+    demo,
+    music,
+    headless::Headless,
+    ilda::{self, IldaFrame},
+    rocket::Rocket,
+    scene::Choreography,
+    timeline::{Scene, Timeline},
+};
+
+/// Renders the whole demo at a fixed frame rate to numbered PNG files. The music is
+/// advanced by sample count rather than played, so the output doesn't depend on how
+/// fast the machine is.
+pub async fn render_to_disk(config: &Config) -> anyhow::Result<()> {
+    let dir = config.render_dir.clone().unwrap();
+    std::fs::create_dir_all(&dir)?;
+
+    let mut headless = Headless::new(config).await?;
+    let mut wav = match &config.wav {
+        Some(path) => Some(WavExport::create(path, config.sample_rate, config.wav_format)?),
+        None => None,
+    };
+
+    // PNG encoding is slow, so it happens on another thread while the next frame renders
+    let (sender, receiver) = mpsc::sync_channel::<(PathBuf, image::RgbaImage)>(4);
+    let writer = thread::spawn(move || -> anyhow::Result<()> {
+        for (path, image) in receiver {
+            image.save(&path)?;
+        }
+        Ok(())
+    });
+
+    let fps = config.fps as u64;
+    let sample_rate = config.sample_rate as u64;
+    let mut samples_done = 0;
+    let mut audio = vec![];
+    let mut last_pattern = headless.position().0;
+    let mut frame = 0;
+    while config.frames.is_none_or(|frames| frame < frames) {
+        let image = headless.render_frame(Duration::from_secs_f64(frame as f64 / fps as f64))?;
+        if sender.send((dir.join(format!("{:06}.png", frame)), image)).is_err() {
+            // The writer thread hit an error, which join() below reports
+            break;
+        }
+        frame += 1;
+        if frame % fps == 0 {
+            log::info!("rendered {} frames", frame);
+        }
+
+        // Advance the music to where the next frame starts. Rounding down each time
+        // from the absolute frame number keeps the sample count from drifting, so the
+        // WAV is always frame_count * sample_rate / fps samples long.
+        let samples_end = frame * sample_rate / fps;
+        audio.resize(2 * (samples_end - samples_done) as usize, 0.0);
+        samples_done = samples_end;
+        let playing = headless.render_audio(&mut audio);
+        if let Some(wav) = &mut wav {
+            wav.write(&audio)?;
+        }
+        if !playing {
+            log::info!("the music ended");
+            break;
+        }
+        let pattern = headless.position().0;
+        if pattern < last_pattern {
+            log::info!("the music looped");
+            break;
+        }
+        last_pattern = pattern;
+    }
+
+    drop(sender);
+    writer.join().unwrap()?;
+    log::info!("rendered {} frames to {}", frame, dir.display());
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+    Ok(())
+}
+
+/// Writes the music to `config.wav` without rendering any video, from the start
+/// step until the music ends or loops back.
+pub async fn export_audio(config: &Config) -> anyhow::Result<()> {
+    let path = config.wav.clone().unwrap();
+    let timeline = Timeline::load(config.timeline.as_deref()).await?;
+    let start = timeline.steps.get(config.start_step)
+        .ok_or_else(|| anyhow::anyhow!("start step {} is beyond the end of the timeline", config.start_step))?
+        .0;
+
+    let mut player = music::load_asset(config.sample_rate)?;
+    player.goto(start.0, start.1);
+    let mut wav = WavExport::create(&path, config.sample_rate, config.wav_format)?;
+    // A tenth of a second at a time
+    let mut audio = vec![0.0; 2 * (config.sample_rate as usize / 10).max(1)];
+    let mut last_pattern = player.position().0;
+    loop {
+        let playing = demo::fill_audio(player.as_mut(), &mut audio);
+        wav.write(&audio)?;
+        let pattern = player.position().0;
+        if !playing || pattern < last_pattern {
+            break;
+        }
+        last_pattern = pattern;
+    }
+    wav.finish()?;
+    log::info!("wrote {}", path.display());
+    Ok(())
+}
+
+/// Writes the smoke scene's lasers to `config.ilda`, one ILDA frame per frame at
+/// `config.fps`, from the start step until the music ends or loops back. Other
+/// scenes come out as blank frames so the show keeps time with the music. The
+/// music is only pulled to find the position and the tracker events, as in
+/// `render_to_disk`.
+pub async fn export_ilda(config: &Config) -> anyhow::Result<()> {
+    let path = config.ilda.clone().unwrap();
+    let timeline = Timeline::load(config.timeline.as_deref()).await?;
+    let start = timeline.steps.get(config.start_step)
+        .ok_or_else(|| anyhow::anyhow!("start step {} is beyond the end of the timeline", config.start_step))?
+        .0;
+
+    let mut player = music::load_asset(config.sample_rate)?;
+    player.goto(start.0, start.1);
+    let pattern_rows = player.pattern_rows();
+    let mut rocket = Rocket::load()?;
+    let mut choreography = Choreography::new(config.fluid_size);
+
+    let fps = config.fps as u64;
+    let sample_rate = config.sample_rate as u64;
+    let mut samples_done = 0;
+    let mut audio = vec![];
+    let mut last_pattern = player.position().0;
+    let mut frames = vec![];
+    while config.frames.is_none_or(|count| (frames.len() as u64) < count) {
+        let frame = frames.len() as u64;
+        let time = frame as f64 / fps as f64;
+        let (pattern, row) = player.position();
+        let song_row = pattern_rows.iter().take(pattern).sum::<usize>() + row;
+        rocket.update(song_row as u32, Duration::from_secs_f64(time));
+        let events = player.take_events();
+        let lasers = match timeline.steps[timeline.step_index_at((pattern, row))].1 {
+            Scene::Smoke(number) => choreography.update(number, time, 1.0 / fps as f64, &events, &mut rocket, &[]),
+            _ => &[],
+        };
+        frames.push(IldaFrame {
+            name: format!("{:08}", frame),
+            company: String::new(),
+            projector: 0,
+            points: ilda::draw_lasers(lasers),
+        });
+
+        let samples_end = (frame + 1) * sample_rate / fps;
+        audio.resize(2 * (samples_end - samples_done) as usize, 0.0);
+        samples_done = samples_end;
+        let playing = demo::fill_audio(player.as_mut(), &mut audio);
+        let pattern = player.position().0;
+        if !playing || pattern < last_pattern {
+            break;
+        }
+        last_pattern = pattern;
+    }
+
+    let mut file = BufWriter::new(File::create(&path)?);
+    ilda::write_ilda(&mut file, config.ilda_format, &frames)?;
+    file.flush()?;
+    log::info!("wrote {} ILDA frames to {}", frames.len(), path.display());
+    Ok(())
+}
+
+/// A stereo WAV file at the music's sample rate.
+pub struct WavExport {
+    writer: hound::WavWriter<BufWriter<File>>,
+    format: WavFormat,
+}
+
+impl WavExport {
+    pub fn create(path: &Path, sample_rate: u32, format: WavFormat) -> anyhow::Result<Self> {
+        let spec = match format {
+            WavFormat::F32 => hound::WavSpec {
+                channels: 2,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            },
+            WavFormat::I16 => hound::WavSpec {
+                channels: 2,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            },
+        };
+        Ok(WavExport {
+            writer: hound::WavWriter::create(path, spec)?,
+            format,
+        })
+    }
+
+    /// Appends interleaved stereo samples.
+    pub fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        for &sample in samples {
+            match self.format {
+                WavFormat::F32 => self.writer.write_sample(sample)?,
+                WavFormat::I16 => self.writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the header. Dropping the writer would do it too, but without
+    /// reporting errors.
+    pub fn finish(self) -> anyhow::Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::{cell::Cell, collections::VecDeque, rc::Rc, sync::{Arc, Mutex}};
+
+use web_time::{Duration, Instant};
+
+use crate::music::TrackerEvent;
+
+/// Where the demo gets its time from. `now` is the time since the demo started.
+pub trait Clock {
+    fn now(&self) -> Duration;
+
+    /// Stops or restarts the clock. Clocks that are driven from outside ignore this.
+    fn set_paused(&mut self, _paused: bool) {}
+}
+
+/// Real time, for when nothing better is available.
+pub struct WallClock {
+    started: Instant,
+    paused_at: Option<Instant>,
+}
+
+impl WallClock {
+    pub fn new() -> Self {
+        WallClock {
+            started: Instant::now(),
+            paused_at: None,
+        }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        self.paused_at.unwrap_or_else(Instant::now).duration_since(self.started)
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        match (self.paused_at, paused) {
+            (None, true) => self.paused_at = Some(Instant::now()),
+            (Some(paused_at), false) => {
+                self.started += paused_at.elapsed();
+                self.paused_at = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct AudioPosition {
+    sample_rate: f64,
+    frames: u64,
+    // Size of the last buffer the callback filled, and when
+    chunk: u64,
+    at: Option<Instant>,
+    // How long after the callback its first sample is heard, as the device
+    // reports it plus whatever was configured on top
+    latency: Duration,
+    extra_latency: Duration,
+    // The frames where the music moved to a new (pattern, row), for the last
+    // few seconds
+    rows: VecDeque<(u64, (usize, usize))>,
+    // Tracker events that haven't been heard yet, with the frame they're heard at
+    events: VecDeque<(u64, TrackerEvent)>,
+}
+
+// How much of the row history is kept, well beyond any output latency
+const ROW_HISTORY: f64 = 4.0;
+
+impl AudioPosition {
+    // The frame being heard right now. The callback only runs once per buffer,
+    // so between calls this carries on with the wall clock, up to the end of the
+    // buffer it was last handed.
+    fn audible(&self, paused: bool) -> f64 {
+        let buffer_start = self.frames.saturating_sub(self.chunk) as f64;
+        let since_callback = match self.at {
+            Some(at) if !paused => at.elapsed().as_secs_f64().min(self.chunk as f64 / self.sample_rate),
+            _ => 0.0,
+        };
+        (buffer_start + (since_callback - self.latency.as_secs_f64()) * self.sample_rate).max(0.0)
+    }
+}
+
+/// Time as the number of sample frames the audio device has played, so the
+/// visuals can't drift from what is heard. The device's output latency, as it
+/// reports it plus any configured on top, is taken off so cues land when their
+/// sound comes out rather than when it was generated.
+pub struct AudioClock {
+    position: Arc<Mutex<AudioPosition>>,
+    paused: bool,
+}
+
+/// The audio callback's end of an [`AudioClock`].
+#[derive(Clone)]
+pub struct AudioCounter(Arc<Mutex<AudioPosition>>);
+
+impl AudioClock {
+    pub fn new(sample_rate: u32, extra_latency: Duration) -> Self {
+        AudioClock {
+            position: Arc::new(Mutex::new(AudioPosition {
+                sample_rate: sample_rate as f64,
+                extra_latency,
+                latency: extra_latency,
+                ..Default::default()
+            })),
+            paused: false,
+        }
+    }
+
+    pub fn counter(&self) -> AudioCounter {
+        AudioCounter(self.position.clone())
+    }
+}
+
+impl Clock for AudioClock {
+    fn now(&self) -> Duration {
+        let position = self.position.lock().unwrap();
+        Duration::from_secs_f64(position.audible(self.paused) / position.sample_rate)
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        // The callback stops counting on its own while paused
+        self.paused = paused;
+    }
+}
+
+impl AudioCounter {
+    /// Records that `frames` more sample frames were handed to the device, which
+    /// will start playing them after `latency` if it knows. `rows` holds the
+    /// (pattern, row) the music was at from each frame offset into the buffer on,
+    /// and `events` the tracker events that came with the frame at each offset.
+    pub fn advance(
+        &self,
+        frames: u64,
+        latency: Option<Duration>,
+        rows: &[(usize, (usize, usize))],
+        events: &[(usize, TrackerEvent)],
+    ) {
+        let mut state = self.0.lock().unwrap();
+        for &(offset, event) in events {
+            let frame = state.frames + offset as u64;
+            state.events.push_back((frame, event));
+        }
+        for &(offset, position) in rows {
+            if state.rows.back().map(|&(_, last)| last) != Some(position) {
+                let frame = state.frames + offset as u64;
+                state.rows.push_back((frame, position));
+            }
+        }
+        let keep_from = state.frames.saturating_sub((ROW_HISTORY * state.sample_rate) as u64);
+        while state.rows.len() > 1 && state.rows[1].0 <= keep_from {
+            state.rows.pop_front();
+        }
+        state.frames += frames;
+        state.chunk = frames;
+        state.at = Some(Instant::now());
+        state.latency = latency.unwrap_or_default() + state.extra_latency;
+    }
+
+    /// Forgets where the music was, and the events still to be heard, after a
+    /// jump, so the position comes from the player again until the new rows are
+    /// heard.
+    pub fn clear(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.rows.clear();
+        state.events.clear();
+    }
+
+    /// The frame being heard, counting every frame handed to the device.
+    pub fn audible_frame(&self) -> u64 {
+        self.0.lock().unwrap().audible(false) as u64
+    }
+
+    /// The tracker events heard since the last call, in the order they were played.
+    pub fn take_events(&self) -> Vec<TrackerEvent> {
+        let mut state = self.0.lock().unwrap();
+        let audible = state.audible(false) as u64;
+        let heard = state.events.iter().take_while(|(frame, _)| *frame <= audible).count();
+        state.events.drain(..heard).map(|(_, event)| event).collect()
+    }
+
+    /// The (pattern, row) being heard, or None before anything was played.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        let state = self.0.lock().unwrap();
+        let audible = state.audible(false) as u64;
+        let heard = state.rows.iter().rev().find(|(frame, _)| *frame <= audible);
+        heard.or(state.rows.front()).map(|&(_, position)| position)
+    }
+}
+
+/// A clock that only moves when told to, for rendering to files and for tests.
+/// Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock(Rc<Cell<Duration>>);
+
+impl ManualClock {
+    pub fn set(&self, time: Duration) {
+        self.0.set(time);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.0.get()
+    }
+}
@@ -0,0 +1,324 @@
+use std::path::PathBuf;
+
+use web_time::Duration;
+
+use crate::{rocket::DEFAULT_EDITOR, scene::MIN_FLUID_SIZE, FLUID_SIZE};
+
+const USAGE: &str = "\
+usage: laser-demo [options]
+
+  --windowed               run in a window instead of borderless fullscreen
+  --monitor <index>        monitor to open on (default: the primary one)
+  --resolution <WxH>       window size (default: 1920x1080)
+  --render-resolution <WxH>
+                           fixed internal resolution, scaled to fit the window
+                           (default: the largest 16:9 size that fits the window,
+                           or 1920x1080 for --render)
+  --backend <list>         comma-separated wgpu backends: vulkan, metal, dx12, gl
+                           (default: vulkan,metal)
+  --fallback-adapter       use a software renderer such as lavapipe (--render only)
+  --present-mode <mode>    fifo, fifo-relaxed, mailbox, immediate, auto-vsync or
+                           auto-no-vsync (default: the surface's preferred mode)
+  --vsync, --no-vsync      shorthands for auto-vsync and auto-no-vsync
+  --start-step <index>     timeline step to start from (default: 0)
+  --fluid-size <n|XxYxZ>   smoke simulation grid size, one number for a cube
+                           (default: 100)
+  --pressure <solver>      how the smoke solves for its pressure: jacobi (a sweep
+                           in every simulation step) or multigrid (V-cycles before
+                           each projection, slower but less mushy) (default: jacobi)
+  --pressure-cycles <n>    V-cycles per solve for --pressure multigrid (default: 2)
+  --pressure-residual      log how far the pressure is from removing the divergence
+                           with jacobi too (multigrid always logs it)
+  --timeline <path>        load the timeline from this file instead of the built-in one
+  --mute                   don't output any sound
+  --audio-device <name>    play through the first output device whose name
+                           contains <name> (default: the system's default)
+  --list-audio-devices     print the names of the audio output devices and exit
+  --audio-latency <ms>     output latency to allow for on top of what the device
+                           reports, e.g. for Bluetooth (default: 0)
+  --clock <source>         audio (follow the samples played) or wall (default: audio)
+  --rocket <address>       sync editor to connect to (default: 127.0.0.1:1338 in
+                           debug builds, none in release builds)
+  --no-rocket              play back the saved sync tracks even in debug builds
+  --osc <port|address>     listen for OSC remote control messages over UDP
+  --artnet <address>       send the lasers, scene, beat and transition to a
+                           lighting rig as Art-Net DMX (port 6454 unless given)
+  --artnet-map <path>      RON file assigning them to DMX channels (default:
+                           lasers 1-4 as RGB, pan, tilt from channel 1, then
+                           scene, scene number, beat and transition)
+  --seed <n>               random seed for the scene layouts
+  --render <dir>           render every frame to numbered PNG files in <dir> instead
+                           of opening a window
+  --fps <n>                frame rate for --render and --ilda (default: 60)
+  --frames <n>             stop --render after this many frames (default: when the
+                           music ends)
+  --sample-rate <hz>       sample rate of the music for --render and --wav
+                           (default: 48000)
+  --wav <path>             write the music to a WAV file; with --render it holds
+                           exactly the samples of the rendered frames
+  --wav-format <format>    f32 or i16 (default: f32)
+  --ilda <path>            write the smoke scene's lasers to an ILDA file, one
+                           frame per --fps, for playing on laser projectors
+  --ilda-format <n>        4 (3D) or 5 (2D), both true colour (default: 5)
+  --help                   show this message
+";
+
+/// Startup options, from the command line on native builds.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub windowed: bool,
+    pub monitor: Option<usize>,
+    pub resolution: (u32, u32),
+    pub render_resolution: Option<(u32, u32)>,
+    pub backends: wgpu::Backends,
+    pub fallback_adapter: bool,
+    pub present_mode: Option<wgpu::PresentMode>,
+    pub start_step: usize,
+    pub fluid_size: (u32, u32, u32),
+    pub pressure: PressureSolver,
+    pub pressure_residual: bool,
+    pub timeline: Option<PathBuf>,
+    pub mute: bool,
+    pub audio_device: Option<String>,
+    pub list_audio_devices: bool,
+    pub audio_latency: Duration,
+    pub clock: ClockSource,
+    pub rocket: Option<String>,
+    pub osc: Option<String>,
+    pub artnet: Option<String>,
+    pub artnet_map: Option<PathBuf>,
+    pub seed: u64,
+    pub render_dir: Option<PathBuf>,
+    pub fps: u32,
+    pub frames: Option<u64>,
+    pub sample_rate: u32,
+    pub wav: Option<PathBuf>,
+    pub wav_format: WavFormat,
+    pub ilda: Option<PathBuf>,
+    pub ilda_format: IldaFormat,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    Audio,
+    Wall,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WavFormat {
+    F32,
+    I16,
+}
+
+/// How the smoke works out the pressure that keeps it from compressing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PressureSolver {
+    /// One Jacobi sweep in each step of the simulation
+    Jacobi,
+    /// This many multigrid V-cycles before each projection
+    Multigrid(u32),
+}
+
+/// The V-cycles a multigrid solve does when not told otherwise.
+pub const DEFAULT_PRESSURE_CYCLES: u32 = 2;
+
+/// The true colour formats of the ILDA Image Data Transfer Format, the ones
+/// that can carry the demo's colours without a palette.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IldaFormat {
+    /// Format 4, x, y and z
+    ThreeD,
+    /// Format 5, x and y only
+    TwoD,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            windowed: false,
+            monitor: None,
+            resolution: (1920, 1080),
+            render_resolution: None,
+            backends: wgpu::Backends::VULKAN | wgpu::Backends::METAL,
+            fallback_adapter: false,
+            present_mode: None,
+            start_step: 0,
+            fluid_size: FLUID_SIZE,
+            pressure: PressureSolver::Jacobi,
+            pressure_residual: false,
+            timeline: None,
+            mute: false,
+            audio_device: None,
+            list_audio_devices: false,
+            audio_latency: Duration::ZERO,
+            clock: ClockSource::Audio,
+            rocket: cfg!(debug_assertions).then(|| DEFAULT_EDITOR.to_owned()),
+            osc: None,
+            artnet: None,
+            artnet_map: None,
+            seed: 0x4375746552616363,
+            render_dir: None,
+            fps: 60,
+            frames: None,
+            sample_rate: 48000,
+            wav: None,
+            wav_format: WavFormat::F32,
+            ilda: None,
+            ilda_format: IldaFormat::TwoD,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the process arguments, printing usage and exiting on `--help` or an error.
+    pub fn from_args() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(Some(config)) => config,
+            Ok(None) => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprint!("error: {}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Returns `None` if help was requested.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        let mut pressure_cycles = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--windowed" => config.windowed = true,
+                "--monitor" => config.monitor = Some(value()?.parse()?),
+                "--resolution" => config.resolution = parse_resolution(&value()?)?,
+                "--render-resolution" => config.render_resolution = Some(parse_resolution(&value()?)?),
+                "--backend" => {
+                    let list = value()?;
+                    config.backends = wgpu::util::parse_backends_from_comma_list(&list);
+                    if config.backends.is_empty() {
+                        anyhow::bail!("no known backends in \"{}\"", list);
+                    }
+                }
+                "--fallback-adapter" => config.fallback_adapter = true,
+                "--present-mode" => config.present_mode = Some(parse_present_mode(&value()?)?),
+                "--vsync" => config.present_mode = Some(wgpu::PresentMode::AutoVsync),
+                "--no-vsync" => config.present_mode = Some(wgpu::PresentMode::AutoNoVsync),
+                "--start-step" => config.start_step = value()?.parse()?,
+                "--fluid-size" => config.fluid_size = parse_fluid_size(&value()?)?,
+                "--pressure" => config.pressure = match value()?.as_str() {
+                    "jacobi" => PressureSolver::Jacobi,
+                    "multigrid" => PressureSolver::Multigrid(DEFAULT_PRESSURE_CYCLES),
+                    other => anyhow::bail!("unknown pressure solver \"{}\"", other),
+                },
+                "--pressure-cycles" => pressure_cycles = Some(parse_nonzero(&arg, &value()?)?),
+                "--pressure-residual" => config.pressure_residual = true,
+                "--timeline" => config.timeline = Some(value()?.into()),
+                "--mute" => config.mute = true,
+                "--audio-device" => config.audio_device = Some(value()?),
+                "--list-audio-devices" => config.list_audio_devices = true,
+                "--audio-latency" => {
+                    let milliseconds: f64 = value()?.parse()?;
+                    if !(0.0..=10000.0).contains(&milliseconds) {
+                        anyhow::bail!("audio latency should be between 0 and 10000 ms");
+                    }
+                    config.audio_latency = Duration::from_secs_f64(milliseconds / 1000.0);
+                }
+                "--clock" => config.clock = match value()?.as_str() {
+                    "audio" => ClockSource::Audio,
+                    "wall" => ClockSource::Wall,
+                    other => anyhow::bail!("unknown clock \"{}\"", other),
+                },
+                "--rocket" => config.rocket = Some(value()?),
+                "--no-rocket" => config.rocket = None,
+                "--osc" => config.osc = Some(value()?),
+                "--artnet" => config.artnet = Some(value()?),
+                "--artnet-map" => config.artnet_map = Some(value()?.into()),
+                "--seed" => config.seed = parse_u64(&value()?)?,
+                "--render" => config.render_dir = Some(value()?.into()),
+                "--fps" => config.fps = parse_nonzero(&arg, &value()?)?,
+                "--frames" => config.frames = Some(value()?.parse()?),
+                "--sample-rate" => config.sample_rate = parse_nonzero(&arg, &value()?)?,
+                "--wav" => config.wav = Some(value()?.into()),
+                "--wav-format" => config.wav_format = match value()?.as_str() {
+                    "f32" => WavFormat::F32,
+                    "i16" => WavFormat::I16,
+                    other => anyhow::bail!("unknown WAV format \"{}\"", other),
+                },
+                "--ilda" => config.ilda = Some(value()?.into()),
+                "--ilda-format" => config.ilda_format = match value()?.as_str() {
+                    "4" => IldaFormat::ThreeD,
+                    "5" => IldaFormat::TwoD,
+                    other => anyhow::bail!("unknown ILDA format \"{}\", only 4 and 5 are supported", other),
+                },
+                "--help" | "-h" => return Ok(None),
+                _ => anyhow::bail!("unknown option {}", arg),
+            }
+        }
+        if let Some(cycles) = pressure_cycles {
+            match &mut config.pressure {
+                PressureSolver::Multigrid(default) => *default = cycles,
+                PressureSolver::Jacobi => anyhow::bail!("--pressure-cycles needs --pressure multigrid"),
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Whether the demo is rendered to files instead of played back live.
+    pub fn offline(&self) -> bool {
+        self.render_dir.is_some() || self.wav.is_some() || self.ilda.is_some()
+    }
+}
+
+fn parse_resolution(value: &str) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| anyhow::anyhow!("resolution should look like 1920x1080, got \"{}\"", value))?;
+    let (width, height) = (width.parse()?, height.parse()?);
+    if width == 0 || height == 0 {
+        anyhow::bail!("resolution can't be zero");
+    }
+    Ok((width, height))
+}
+
+fn parse_fluid_size(value: &str) -> anyhow::Result<(u32, u32, u32)> {
+    let size = match value.split('x').map(str::parse).collect::<Result<Vec<u32>, _>>()?[..] {
+        [n] => (n, n, n),
+        [x, y, z] => (x, y, z),
+        _ => anyhow::bail!("fluid size should be one number or look like 128x64x96, got \"{}\"", value),
+    };
+    if size.0.min(size.1).min(size.2) < MIN_FLUID_SIZE {
+        anyhow::bail!("fluid size must be at least {} in every direction", MIN_FLUID_SIZE);
+    }
+    Ok(size)
+}
+
+fn parse_present_mode(value: &str) -> anyhow::Result<wgpu::PresentMode> {
+    Ok(match value {
+        "fifo" => wgpu::PresentMode::Fifo,
+        "fifo-relaxed" => wgpu::PresentMode::FifoRelaxed,
+        "mailbox" => wgpu::PresentMode::Mailbox,
+        "immediate" => wgpu::PresentMode::Immediate,
+        "auto-vsync" => wgpu::PresentMode::AutoVsync,
+        "auto-no-vsync" => wgpu::PresentMode::AutoNoVsync,
+        _ => anyhow::bail!("unknown present mode \"{}\"", value),
+    })
+}
+
+fn parse_nonzero(option: &str, value: &str) -> anyhow::Result<u32> {
+    match value.parse()? {
+        0 => anyhow::bail!("{} can't be zero", option),
+        value => Ok(value),
+    }
+}
+
+fn parse_u64(value: &str) -> anyhow::Result<u64> {
+    Ok(match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => value.parse()?,
+    })
+}
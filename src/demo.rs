@@ -1,4 +1,4 @@
-use std::{f32::consts::PI, sync::{Arc, Mutex}};
+use std::{collections::HashMap, iter, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
@@ -6,163 +6,259 @@ use wasm_bindgen::JsCast;
 use web_sys::HtmlInputElement;
 use web_time::{Instant, Duration};
 
-use cgmath::{Deg, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3, Zero};
-use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, BufferSize};
-use rand::{Rng, SeedableRng};
+use cgmath::{Rotation3, SquareMatrix};
+use rand::SeedableRng;
 use wgpu::{
-    util::DeviceExt, BindGroup, Buffer, CommandEncoder, ComputePipeline, Extent3d, RenderPipeline, TextureFormat, TextureView
+    util::DeviceExt, BindGroup, Buffer, CommandEncoder, ComputePipeline, RenderPipeline, TextureFormat, TextureView
 };
-use xmrs::xm::xmmodule::XmModule;
-use xmrsplayer::xmrsplayer::XmrsPlayer;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::analysis::AnalysisThread;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::artnet::{ArtNet, DmxMapping, LaserLight, ShowState};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hotreload;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::remote::{Command, FinalParam, Remote};
 use crate::{
-    model::{Model,Vertex,DrawModel},
-    resources::{self, ASSETS, QUAD_INDICES, QUAD_VERTICES},
-    texture::{self, Texture}, Instance, FLUID_SIZE, OPENGL_TO_WGPU_MATRIX
+    analysis::{Analysis, AudioUniform},
+    audio::{AudioOutput, Source},
+    model::Vertex,
+    music::{self, Player},
+    resources::{ASSETS, QUAD_INDICES, QUAD_VERTICES},
+    scene::{self, Frame, Init, Layouts, PressurePipelines, RenderTargets, Scenes, Shared},
+    texture::{self, Texture},
+    timeline::{Scene, Timeline, Transition, TIMELINE_FILE},
+    config::{ClockSource, Config, PressureSolver},
+    clock::{AudioClock, AudioCounter, Clock, ManualClock, WallClock},
+    rocket::{EditorEvent, Rocket},
+    Instance, OPENGL_TO_WGPU_MATRIX
 };
 
-const COMPUTE_PASSES: i32 = 6;
-const COMPUTE_EXTRAS: i32 = 2;
-
-const NUM_CDS: usize = 300;
-const NUM_STARWARS: usize = 100;
-
 // This file is where the fun happens! It's also the worst spaghetti ever devised.
 // 
 // 2024 update: it's even worse now
 
-fn compute_work_group_count(
-    (width, height, depth): (u32, u32, u32),
-    (workgroup_width, workgroup_height, workgroup_depth): (u32, u32, u32),
-) -> (u32, u32, u32) {
-    let x = (width + workgroup_width - 1) / workgroup_width;
-    let y = (height + workgroup_height - 1) / workgroup_height;
-    let z = (depth + workgroup_depth - 1) / workgroup_depth;
+/// The largest 16:9 size that fits in `size`.
+fn fit_aspect((width, height): (u32, u32)) -> (u32, u32) {
+    let width = width.min(height * 16 / 9).max(16);
+    (width, width * 9 / 16)
+}
+
+/// Viewport (x, y, width, height) that shows `content` as large as possible in the
+/// middle of `surface` without changing its aspect ratio.
+fn letterbox(content: (u32, u32), surface: (u32, u32)) -> (f32, f32, f32, f32) {
+    let scale = (surface.0 as f32 / content.0 as f32).min(surface.1 as f32 / content.1 as f32);
+    let (width, height) = (content.0 as f32 * scale, content.1 as f32 * scale);
+    ((surface.0 as f32 - width) / 2.0, (surface.1 as f32 - height) / 2.0, width, height)
+}
 
-    (x, y, z)
+// Values forced by the operator over OSC. They win over whatever the scene would
+// set until released.
+#[derive(Default)]
+struct Overrides {
+    laser_colors: Vec<Option<[f32; 4]>>,
+    final_x: Option<f32>,
+    final_x2: Option<f32>,
+    final_transition: Option<f32>,
 }
 
-const STEPS: [((usize,usize), Scene, Transition); 33] = [
-    ((0x00,0x00), Scene::Black,        Transition::None),
-    ((0x00,0x18), Scene::Slide(0),     Transition::None),
-    ((0x01,0x16), Scene::Black,        Transition::None),
-    ((0x01,0x1b), Scene::Slide(1),     Transition::None),
-    ((0x01,0x3b), Scene::Slide(2),     Transition::Slide),
-    ((0x02,0x0f), Scene::Slide(3),     Transition::Slide),
-    ((0x02,0x1b), Scene::Slide(4),     Transition::Fade(1.)),
-    ((0x02,0x34), Scene::Slide(5),     Transition::Fade(1.)),
-    ((0x03,0x0f), Scene::Slide(6),     Transition::Fade(1.)),
-    ((0x03,0x23), Scene::Slide(7),     Transition::Fade(1.)),
-    ((0x03,0x2d), Scene::CDs(8),       Transition::Blink),
-    ((0x04,0x30), Scene::CDs(9),       Transition::None),
-    ((0x05,0x10), Scene::CDs(10),       Transition::None),
-    ((0x07,0x00), Scene::StarWars(11), Transition::Slide),
-    ((0x07,0x30), Scene::StarWars(12), Transition::None),
-    ((0x08,0x10), Scene::StarWars(13), Transition::None),
-    ((0x0a,0x00), Scene::Ocean(14),    Transition::Slide),
-    ((0x0b,0x00), Scene::Ocean(15),    Transition::None),
-    ((0x0c,0x00), Scene::Ocean(16),    Transition::None),
-    ((0x0e,0x00), Scene::Slide(17),    Transition::Slide),
-    ((0x0e,0x20), Scene::Black,        Transition::Fade(0.2)),
-    ((0x0f,0x00), Scene::Slide(18),    Transition::Fade(0.3)),
-    ((0x0f,0x10), Scene::Slide(19),    Transition::Fade(1.)),
-    ((0x0f,0x20), Scene::Slide(20),    Transition::Fade(1.)),
-    ((0x10,0x00), Scene::Black,        Transition::Fade(0.5)),
-    ((0x10,0x08), Scene::Smoke(1),     Transition::None),
-    ((0x11,0x00), Scene::Smoke(2),     Transition::None),
-    ((0x12,0x00), Scene::Smoke(3),     Transition::None),
-    ((0x13,0x00), Scene::Smoke(4),     Transition::None),
-    ((0x18,0x00), Scene::Slide(20),    Transition::Fade(0.5)),
-    ((0x18,0x08), Scene::Slide(21),    Transition::Slide),
-    ((0x19,0x08), Scene::Slide(22),    Transition::Fade(1.)),
-    ((0x19,0x3d), Scene::Slide(23),    Transition::Blink2),
+// Every shader file that ends up in a pipeline, embedded at build time. On native
+// builds the files in src/ are also watched and reloaded when they change.
+const SHADERS: [(&str, &str); 10] = [
+    ("shaderpassfinal.wgsl", include_str!("shaderpassfinal.wgsl")),
+    ("shaderpass_smokerender.wgsl", include_str!("shaderpass_smokerender.wgsl")),
+    ("shaderpass_cdrender.wgsl", include_str!("shaderpass_cdrender.wgsl")),
+    ("shaderpass_starwars1.wgsl", include_str!("shaderpass_starwars1.wgsl")),
+    ("shaderpass_starwars2.wgsl", include_str!("shaderpass_starwars2.wgsl")),
+    ("shaderpass_ocean.wgsl", include_str!("shaderpass_ocean.wgsl")),
+    ("shaderpass_simple.wgsl", include_str!("shaderpass_simple.wgsl")),
+    ("shaderpass_blit.wgsl", include_str!("shaderpass_blit.wgsl")),
+    ("smoke_compute.wgsl", include_str!("smoke_compute.wgsl")),
+    ("smoke_pressure.wgsl", include_str!("smoke_pressure.wgsl")),
 ];
-const START_FROM: usize = 0;
+
+// Built into `PressurePipelines` rather than a single pipeline
+const PRESSURE_SHADER: &str = "smoke_pressure.wgsl";
+
+fn create_shader(device: &wgpu::Device, file_name: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(file_name),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
+
+// The pipeline layouts are kept around so the pipelines can be rebuilt when a
+// shader is reloaded.
+struct PipelineLayouts {
+    final_pass: wgpu::PipelineLayout,
+    smokerender: wgpu::PipelineLayout,
+    cdrender: wgpu::PipelineLayout,
+    simple: wgpu::PipelineLayout,
+    blit: wgpu::PipelineLayout,
+    compute: wgpu::PipelineLayout,
+    pressure: wgpu::PipelineLayout,
+    format: TextureFormat,
+}
+
+impl PipelineLayouts {
+    /// Builds the render pipeline that uses the shader from `file_name`, or `None` if
+    /// no render pipeline uses that file.
+    fn create_render_pipeline(
+        &self,
+        device: &wgpu::Device,
+        file_name: &str,
+        shader: &wgpu::ShaderModule
+    ) -> Option<RenderPipeline> {
+        let alpha_replace = wgpu::BlendState {
+            color: wgpu::BlendState::ALPHA_BLENDING.color,
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        // (label, layout, blend, (depth write, depth compare))
+        let (label, layout, blend, depth) = match file_name {
+            "shaderpassfinal.wgsl" =>
+                ("Render Pipeline final", &self.final_pass, wgpu::BlendState::ALPHA_BLENDING, None),
+            "shaderpass_smokerender.wgsl" =>
+                ("Render pipeline smoke render", &self.smokerender, alpha_replace, Some((true, wgpu::CompareFunction::Less))),
+            "shaderpass_cdrender.wgsl" =>
+                ("Render pipeline CD render", &self.cdrender, wgpu::BlendState::ALPHA_BLENDING, Some((true, wgpu::CompareFunction::Less))),
+            "shaderpass_starwars1.wgsl" =>
+                ("Render pipeline star wars pass 1", &self.cdrender, wgpu::BlendState::ALPHA_BLENDING, Some((false, wgpu::CompareFunction::Always))),
+            "shaderpass_starwars2.wgsl" =>
+                ("Render pipeline star wars pass 2", &self.simple, wgpu::BlendState::ALPHA_BLENDING, Some((false, wgpu::CompareFunction::Always))),
+            "shaderpass_ocean.wgsl" =>
+                ("Render pipeline ocean", &self.simple, wgpu::BlendState::REPLACE, Some((false, wgpu::CompareFunction::Always))),
+            "shaderpass_simple.wgsl" =>
+                ("Render pipeline simple", &self.simple, wgpu::BlendState::ALPHA_BLENDING, Some((true, wgpu::CompareFunction::Always))),
+            "shaderpass_blit.wgsl" =>
+                ("Render pipeline blit", &self.blit, wgpu::BlendState::REPLACE, None),
+            _ => return None,
+        };
+
+        Some(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: depth.map(|(depth_write_enabled, depth_compare)| wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        }))
+    }
+
+    fn create_compute_pipeline(&self, device: &wgpu::Device, shader: &wgpu::ShaderModule) -> ComputePipeline {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Smoke compute pipeline"),
+            layout: Some(&self.compute),
+            module: shader,
+            entry_point: "fluid_main",
+        })
+    }
+}
 
 pub struct Demo {
+    timeline: Timeline,
     current_step: i32,
     scene: Scene,
     transition: Transition,
-    transitioned_at: Instant,
-    full_quad_vertex_buffer: Buffer,
-    full_quad_index_buffer: Buffer,
+    transitioned_at: Duration,
     pub instances: Vec<Instance>,
-    pub cd_instances: Vec<Instance>,
-    pub starwars_instances: Vec<Instance>,
-    pub instance_buffer: Buffer,
-    pub cd_instance_buffer: Buffer,
-    pub starwars_instance_buffer: Buffer,
     camera_buffer: wgpu::Buffer,
-    pub texture_bind_group_layout: wgpu::BindGroupLayout,
-    pub uniform_bind_group_layout: wgpu::BindGroupLayout,
     bg_function_buffer: wgpu::Buffer,
-    bg_uniform_bind_group: wgpu::BindGroup,
-    object_uniform_bind_group: wgpu::BindGroup,
+    audio_buffer: wgpu::Buffer,
     final_function_buffer: wgpu::Buffer,
-    final_function_bindgroup: BindGroup,
-    start_time: Instant,
-    last_time: Instant,
+    clock: Box<dyn Clock>,
+    last_time: Duration,
     last_row: usize,
-    beat: Instant,
+    beat: Duration,
     // last_pattern: usize,
     // pattern: Instant,
     next_step: (usize, usize),
     rng: rand::rngs::SmallRng,
-    player: Arc<Mutex<XmrsPlayer>>,
+    player: Arc<Mutex<Box<dyn Player>>>,
+    // Shared with the audio callback, which outputs silence while set
+    paused: Arc<AtomicBool>,
+    analysis: Arc<Analysis>,
+    // Both None when rendering offline
+    #[cfg(not(target_arch = "wasm32"))]
+    _analysis_thread: Option<AnalysisThread>,
+    audio: Option<AudioOutput>,
+    // What is being heard, as opposed to what the player has got to
+    audio_counter: Option<AudioCounter>,
+    pending_seek: Option<(usize, usize)>,
+    pending_rebuild: bool,
+    fluid_size: (u32, u32, u32),
+    // Applied where there's a device to recreate the grid with
+    pending_fluid_size: Option<(u32, u32, u32)>,
+    pending_pressure: Option<PressureSolver>,
+    // Asks the smoke for a residual to go with the next log line
+    measure_pressure: bool,
+    rocket: Rocket,
     bg_shader_params: ShaderParamsUniform,
     final_shader_params: ShaderParamsUniform,
     pub camera: Camera,
     camera_uniform: CameraUniform,
-    
-    lasers_uniform: LasersUniform,
-    lasers_uniform_buffer: Buffer,
-    lasers_uniform_bindgroup: BindGroup,
-    
-    
-    slide_textures: Vec<Texture>,
-    slide_texture_bindgroups: Vec<BindGroup>,
-    ocean_texture_bindgroup: BindGroup,
-    
-    pewpew_model: Model,
-    
-    render_pipeline_smokerender: RenderPipeline,
-    render_pipeline_cdrender: RenderPipeline,
-    render_pipeline_starwars1: RenderPipeline,
-    render_pipeline_starwars2: RenderPipeline,
-    render_pipeline_ocean: RenderPipeline,
-    render_pipeline_simple: RenderPipeline,
-    
-    texture_pass1: texture::Texture,
-    texture_pass1_bindgroup: wgpu::BindGroup,
-    texture_pass2: texture::Texture,
-    texture_pass2_bindgroup: BindGroup,
-    texture_pass_window: texture::Texture,
-    texture_pass_window_bindgroup: BindGroup,
-    previous_pass_texture: texture::Texture,
-    previous_pass_texture_bind_group: BindGroup,
-
-    pub smoke_render_bind_group_layout: wgpu::BindGroupLayout,
-    smoke_render_bind_group: wgpu::BindGroup,
-
-    compute_pipeline: ComputePipeline,
-    pub smoke_texture_bind_group_layout: wgpu::BindGroupLayout,
-    smoke_compute_bindgroup1: BindGroup,
-    smoke_compute_bindgroup2: BindGroup,
-    smoke_shader_params: Vec<ComputeParamsUniform>,
-    smoke_shader_params_buffer: Vec<wgpu::Buffer>,
-    smoke_shader_params_bindgroup: Vec<BindGroup>,
-    current_size: usize,
+
+    shared: Shared,
+    scenes: Scenes,
+    pipeline_layouts: PipelineLayouts,
+    targets: RenderTargets,
+    // Fixed internal resolution, or None to follow the window
+    render_resolution: Option<(u32, u32)>,
 
     frame_log: (Instant, i32),
+
+    pattern_rows: Vec<usize>,
+    overrides: Overrides,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: hotreload::FileWatcher,
+    #[cfg(not(target_arch = "wasm32"))]
+    remote: Option<Remote>,
+    #[cfg(not(target_arch = "wasm32"))]
+    artnet: Option<ArtNet>,
 }
 
 impl Demo {
     pub async fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        surface_format: TextureFormat
+        surface_format: TextureFormat,
+        surface_size: (u32, u32),
+        config: &Config,
+        offline: bool,
     ) -> Self {
-        let mut rng = rand::rngs::SmallRng::seed_from_u64(0x4375746552616363);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(config.seed);
                 
         let instances = vec![
             Instance {
@@ -184,29 +280,6 @@ impl Demo {
                 tex_offset: cgmath::Vector2::new(0.0, 0.0)
             },
         ];
-        let mut cd_instances = vec![];
-        for i in 0..NUM_CDS+1 {
-            cd_instances.push(Instance {
-                position: cgmath::Vector3::new(rng.gen_range(-30.0..30.0), rng.gen_range(-16.0..16.0), rng.gen_range(-25.0..0.0)),
-                rotation: cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(i as f32*3.0)),
-                scale: cgmath::Vector3::new(1.0,1.0,1.0),
-                tex_offset: cgmath::Vector2::new(0.0, 0.0)
-            });
-        }
-        let mut starwars_instances = vec![];
-        for i in 0..NUM_STARWARS*2 {
-            let side = i as i32%2*2-1;
-            starwars_instances.push(Instance {
-                position: cgmath::Vector3::new(
-                    side as f32*40.0+rng.gen_range(-20.0..20.0),
-                    rng.gen_range(-10.0..10.0),
-                    rng.gen_range(-10.0..0.0)
-                ),
-                rotation: cgmath::Quaternion::zero(),
-                scale: cgmath::Vector3::new(1.0,1.0,1.0),
-                tex_offset: cgmath::Vector2::new(0.0,0.0)
-            });
-        }
 
         let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -214,20 +287,6 @@ impl Demo {
             contents: bytemuck::cast_slice(&instance_data),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
-        
-        let instance_data = cd_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let cd_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("CD instances Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-        
-        let instance_data = starwars_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let starwars_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Starwars instances Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
 
         let camera = Camera {
             eye: (0.0, 0.0, 10.0).into(),
@@ -294,428 +353,41 @@ impl Demo {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
         
+        let audio_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Audio analysis buffer"),
+            contents: bytemuck::cast_slice(&[AudioUniform::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let final_function_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Final pass params buffer"),
             contents: bytemuck::cast_slice(&[final_shader_params]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let uniform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("uniform_bind_group_layout"),
-            });
-
-
-        let bg_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: none_camera_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: bg_function_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("uniform_bind_group_1"),
-        });
-
-        let object_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &uniform_bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: camera_buffer.as_entire_binding(),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: bg_function_buffer.as_entire_binding(),
-                        },
-                    ],
-                    label: Some("uniform_bind_group_2"),
-                });
-        let final_function_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &uniform_bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: none_camera_buffer.as_entire_binding(),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: final_function_buffer.as_entire_binding(),
-                        },
-                    ],
-                    label: Some("uniform_bind_group_2"),
-                });
-        
-        let previous_pass_texture = Texture::from_texture(&device, device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1080,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format,
-            usage: wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-            view_formats: &vec![]
-        }), wgpu::FilterMode::Linear);
-        let previous_pass_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&previous_pass_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&previous_pass_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        // Smoke simulation compute stuff:
-
-        let smoke_texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba32Float,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::R32Float,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba32Uint,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("smoke_texture_bind_group_layout"),
-            });
-        let smoke_shader_params_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("uniform_bind_group_layout"),
-            });
-
-        let shader_smoke_compute = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Smoke compute shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("smoke_compute.wgsl").into()),
-        });
-
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Compute pipeline layout"),
-                bind_group_layouts: &[
-                    &smoke_texture_bind_group_layout,
-                    &smoke_shader_params_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Smoke compute pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &shader_smoke_compute,
-            entry_point: "fluid_main",
-        });
-
-        let smoke_texture1 = texture::Texture::from_texture(
-            device,
-            device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("smoke texture 1"),
-                size: wgpu::Extent3d {
-                    width: FLUID_SIZE.0 as u32,
-                    height: FLUID_SIZE.0 as u32,
-                    depth_or_array_layers: FLUID_SIZE.0 as u32,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::Rgba32Float,
-                usage: wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &vec![],
-            }),
-            wgpu::FilterMode::Linear,
-        );
-
-        let smoke_texture2 = texture::Texture::from_texture(
-            device,
-            device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("smoke texture 2"),
-                size: wgpu::Extent3d {
-                    width: FLUID_SIZE.0 as u32,
-                    height: FLUID_SIZE.0 as u32,
-                    depth_or_array_layers: FLUID_SIZE.0 as u32,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::Rgba32Float,
-                usage: wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &vec![],
-            }),
-            wgpu::FilterMode::Linear,
-        );
-
-        let packed_smoke_texture = texture::Texture::from_texture(
-            device,
-            device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("packed smoke texture"),
-                size: wgpu::Extent3d {
-                    width: FLUID_SIZE.0 as u32,
-                    height: FLUID_SIZE.0 as u32,
-                    depth_or_array_layers: FLUID_SIZE.0 as u32,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::Rgba32Uint,
-                usage: wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &vec![],
-            }),
-            wgpu::FilterMode::Linear,
-        );
-
-        let poisson_texture1 = texture::Texture::from_texture(
-            device,
-            device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("poisson texture 1"),
-                size: wgpu::Extent3d {
-                    width: FLUID_SIZE.0 as u32,
-                    height: FLUID_SIZE.0 as u32,
-                    depth_or_array_layers: FLUID_SIZE.0 as u32,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::R32Float,
-                usage: wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &vec![],
-            }),
-            wgpu::FilterMode::Linear,
-        );
-
-        let poisson_texture2 = texture::Texture::from_texture(
-            device,
-            device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("poisson texture 2"),
-                size: wgpu::Extent3d {
-                    width: FLUID_SIZE.0 as u32,
-                    height: FLUID_SIZE.0 as u32,
-                    depth_or_array_layers: FLUID_SIZE.0 as u32,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D3,
-                format: wgpu::TextureFormat::R32Float,
-                usage: wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &vec![],
-            }),
-            wgpu::FilterMode::Linear,
-        );
+        let layouts = Layouts::new(device);
 
-        let smoke_compute_bindgroup1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Smoke compute bind group 1"),
-            layout: &compute_pipeline.get_bind_group_layout(0),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&smoke_texture1.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&poisson_texture1.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&smoke_texture2.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::TextureView(&poisson_texture2.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(&packed_smoke_texture.view),
-                },
-            ],
-        });
-        let smoke_compute_bindgroup2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Smoke compute bind group 2"),
-            layout: &compute_pipeline.get_bind_group_layout(0),
+        let uniform_bind_group = |label, camera_buffer: &Buffer, function_buffer: &Buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.uniform,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&smoke_texture2.view),
+                    resource: camera_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&poisson_texture2.view),
+                    resource: function_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&smoke_texture1.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::TextureView(&poisson_texture1.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(&packed_smoke_texture.view),
+                    resource: audio_buffer.as_entire_binding(),
                 },
             ],
+            label: Some(label),
         });
-
-        let smoke_shader_params: Vec<ComputeParamsUniform> = (0..COMPUTE_PASSES + COMPUTE_EXTRAS)
-            .map(|i| ComputeParamsUniform {
-                step: i,
-                delta_time: 0.0,
-                time: 0.0,
-                x: 0.0,
-            })
-            .collect();
-        let smoke_shader_params_buffer: Vec<wgpu::Buffer> = smoke_shader_params
-            .iter()
-            .map(|p| {
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&"Smoke shader params buffer"),
-                    contents: bytemuck::cast_slice(&[*p]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                })
-            })
-            .collect();
-        let smoke_shader_params_bindgroup: Vec<BindGroup> = smoke_shader_params_buffer
-            .iter()
-            .map(|b| {
-                device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("Smoke shader params bind group"),
-                    layout: &compute_pipeline.get_bind_group_layout(1),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: b.as_entire_binding(),
-                    }],
-                })
-            })
-            .collect();
+        let bg_uniform_bind_group = uniform_bind_group("uniform_bind_group_1", &none_camera_buffer, &bg_function_buffer);
+        let object_uniform_bind_group = uniform_bind_group("uniform_bind_group_2", &camera_buffer, &bg_function_buffer);
+        let final_function_bindgroup = uniform_bind_group("uniform_bind_group_2", &none_camera_buffer, &final_function_buffer);
         
         let slide_textures: Vec<Texture> = ASSETS.get_dir("slides").unwrap().entries().iter().filter_map(
             |entry| {
@@ -731,7 +403,7 @@ impl Demo {
         ).collect();
         let slide_texture_bindgroups = slide_textures.iter().map(|t| device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout: &texture_bind_group_layout,
+            layout: &layouts.texture,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -744,624 +416,273 @@ impl Demo {
             ]
         })).collect();
         
-        let shader_smokerender = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_smokerender.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_smokerender.wgsl").into()),
-        });
-        let shader_cdrender = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_cdrender.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_cdrender.wgsl").into()),
-        });
-        let shader_starwars1 = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_starwars1.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_starwars1.wgsl").into()),
-        });
-        let shader_starwars2 = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_starwars2.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_starwars2.wgsl").into()),
-        });
-        let shader_ocean = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_ocean.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_ocean.wgsl").into()),
-        });
-        let shader_simple = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpass_simple.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpass_simple.wgsl").into()),
-        });
-        
-        let smoke_render_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Texture {
-                    multisampled: false,
-                    view_dimension: wgpu::TextureViewDimension::D3,
-                    sample_type: wgpu::TextureSampleType::Uint,
-                },
-                count: None,
-            }],
-            label: Some("Smoke render bind group layout"),
-        });
-        
-        let lasers_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-           entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-           }],
-           label: Some("Lasers bind group layout")
-        });
-        
-        let smoke_render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Smoke render bind group"),
-            layout: &smoke_render_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&packed_smoke_texture.view),
-            }],
-        });
-        
-        let render_pipeline_layout_smokerender =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render layout smoke render"),
-                bind_group_layouts: &[&smoke_render_bind_group_layout, &uniform_bind_group_layout, &lasers_bind_group_layout],
+        let pipeline_layouts = PipelineLayouts {
+            final_pass: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout final"),
+                bind_group_layouts: &[&layouts.texture, &layouts.uniform, &layouts.texture],
                 push_constant_ranges: &[],
-            });
-
-        let render_pipeline_smokerender = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline smoke render"),
-            layout: Some(&render_pipeline_layout_smokerender),
-            vertex: wgpu::VertexState {
-                module: &shader_smokerender,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_smokerender,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::REPLACE
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+            smokerender: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render layout smoke render"),
+                bind_group_layouts: &[&layouts.smoke_render, &layouts.uniform, &layouts.lasers],
+                push_constant_ranges: &[],
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        let render_pipeline_layout_cdrender =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            cdrender: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render layout CD render"),
-                bind_group_layouts: &[&uniform_bind_group_layout],
+                bind_group_layouts: &[&layouts.uniform],
                 push_constant_ranges: &[],
-            });
-
-        let render_pipeline_cdrender = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline CD render"),
-            layout: Some(&render_pipeline_layout_cdrender),
-            vertex: wgpu::VertexState {
-                module: &shader_cdrender,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_cdrender,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+            simple: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render layout simple"),
+                bind_group_layouts: &[&layouts.texture, &layouts.uniform, &layouts.texture],
+                push_constant_ranges: &[],
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        let render_pipeline_starwars1 = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline star wars pass 1"),
-            layout: Some(&render_pipeline_layout_cdrender),
-            vertex: wgpu::VertexState {
-                module: &shader_starwars1,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_starwars1,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Always,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        let render_pipeline_layout_simple =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render layout simple"),
-                bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout, &texture_bind_group_layout],
+            blit: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render layout blit"),
+                bind_group_layouts: &[&layouts.texture],
                 push_constant_ranges: &[],
-            });
-        
-        let render_pipeline_starwars2 = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline star wars pass 2"),
-            layout: Some(&render_pipeline_layout_simple),
-            vertex: wgpu::VertexState {
-                module: &shader_starwars2,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_starwars2,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Always,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-
-        let render_pipeline_ocean = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline ocean"),
-            layout: Some(&render_pipeline_layout_simple),
-            vertex: wgpu::VertexState {
-                module: &shader_ocean,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_ocean,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::One,
-                            dst_factor: wgpu::BlendFactor::Zero,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::REPLACE
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Always,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        let render_pipeline_simple = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline simple"),
-            layout: Some(&render_pipeline_layout_simple),
-            vertex: wgpu::VertexState {
-                module: &shader_simple,
-                entry_point: "vs_main",
-                buffers: &[crate::model::ModelVertex::desc(), crate::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_simple,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
+            compute: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compute pipeline layout"),
+                bind_group_layouts: &[&layouts.smoke_texture, &layouts.smoke_params],
+                push_constant_ranges: &[],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Always,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+            pressure: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Pressure pipeline layout"),
+                bind_group_layouts: &[&layouts.pressure, &layouts.smoke_params],
+                push_constant_ranges: &[],
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        let texture_pass1 = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1080,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
             format: surface_format.add_srgb_suffix(),
-            usage: wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-            view_formats: &vec![]
-        });
-        let texture_pass1 = Texture::from_texture(&device, texture_pass1, wgpu::FilterMode::Linear);
-        
-        let texture_pass2 = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1080,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format.add_srgb_suffix(),
-            usage: wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-            view_formats: &vec![]
-        });
-        let texture_pass2 = Texture::from_texture(&device, texture_pass2, wgpu::FilterMode::Linear);
-        
-        let texture_pass_window = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1080,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format.add_srgb_suffix(),
-            usage: wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-            view_formats: &vec![]
-        });
-        let texture_pass_window = Texture::from_texture(&device, texture_pass_window, wgpu::FilterMode::Linear);
-        let texture_pass_window_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_pass_window.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture_pass_window.sampler),
-                },
-            ],
-            label: None,
-        });
+        };
 
-        let texture_pass1_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_pass1.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture_pass1.sampler),
-                },
-            ],
-            label: None,
-        });
-        
-        let texture_pass2_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_pass2.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture_pass2.sampler),
-                },
-            ],
-            label: None,
-        });
-        
-        let pewpew_model = resources::load_model("pewpew.obj", device, 0.15).await.unwrap();
+        // Every shader that isn't for a render pipeline is for a compute one, and
+        // the pressure solve's has several
+        let mut pipelines = HashMap::new();
+        let mut compute_pipelines = HashMap::new();
+        let mut pressure_pipelines = None;
+        for (file_name, source) in SHADERS {
+            let shader = create_shader(device, file_name, source);
+            match pipeline_layouts.create_render_pipeline(device, file_name, &shader) {
+                Some(pipeline) => {
+                    pipelines.insert(file_name, pipeline);
+                }
+                None if file_name == PRESSURE_SHADER => {
+                    pressure_pipelines = Some(PressurePipelines::new(device, &pipeline_layouts.pressure, &shader));
+                }
+                None => {
+                    compute_pipelines.insert(file_name, pipeline_layouts.create_compute_pipeline(device, &shader));
+                }
+            }
+        }
+
+        let shared = Shared {
+            layouts,
+            pipelines,
+            compute_pipelines,
+            pressure_pipelines: pressure_pipelines.expect("the pressure shader is in SHADERS"),
+            full_quad_vertex_buffer,
+            full_quad_index_buffer,
+            instance_buffer,
+            bg_uniform_bind_group,
+            object_uniform_bind_group,
+            final_function_bindgroup,
+            slide_textures,
+            slide_texture_bindgroups,
+        };
         
-        let ocean_texture = Texture::from_bytes(
+        let targets = RenderTargets::new(
             device,
-            queue,
-            ASSETS.get_file("environment.jpg").unwrap().contents(),
-            "Ocean texture",
+            &shared.layouts.texture,
             surface_format.add_srgb_suffix(),
-            (wgpu::AddressMode::Repeat, wgpu::AddressMode::MirrorRepeat)).unwrap();
-        let ocean_texture_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&ocean_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&ocean_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-        
-        let lasers_uniform = LasersUniform::new();
-        let lasers_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&"Smoke lasers buffer"),
-            contents: bytemuck::cast_slice(&[lasers_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-        let lasers_uniform_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &lasers_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: lasers_uniform_buffer.as_entire_binding(),
-            }],
-            label: Some("Lasers uniform bind group")
-        });
+            config.render_resolution.unwrap_or_else(|| fit_aspect(surface_size)),
+        );
+        let camera = Camera {
+            aspect: targets.size.0 as f32 / targets.size.1 as f32,
+            ..camera
+        };
+
+        let scenes = Scenes::new(&Init {
+            device,
+            queue,
+            config,
+            shared: &shared,
+            format: surface_format.add_srgb_suffix(),
+        }, &mut rng).await;
         
         // The music:
 
-        let mut host = cpal::default_host();
-        let mut device = host.default_output_device().unwrap();
-        // #[cfg(target_os = "windows")]
-        // {
-        //     let wasapi_host = cpal::platform::WasapiHost::new();
-        //     if let Ok(wasapi_host) = wasapi_host {
-        //         let wasapi_device = wasapi_host.default_output_device().unwrap();
-        //         host = wasapi_host.into();
-        //         device = wasapi_device.into();
-        //     }
-        // }
-    
-        let config = device.default_output_config().unwrap();
-        
-        let xm = XmModule::load(ASSETS.get_file("music.xm").unwrap().contents()).unwrap();
-        let player = Arc::new(Mutex::new(XmrsPlayer::new(
-            xm.to_module().into(),
-            config.sample_rate().0 as f32,
-        )));
+        // When rendering offline no audio device is involved at all, the player is
+        // pulled one video frame's worth of samples at a time instead.
+        let mut audio = (!offline).then(|| {
+            AudioOutput::open(config.audio_device.as_deref()).expect("failed to open the audio output")
+        });
+        let sample_rate = audio.as_ref().map_or(config.sample_rate, AudioOutput::sample_rate);
+
+        let player = music::load_asset(sample_rate).expect("failed to load the music");
+        let pattern_rows = player.pattern_rows();
+
+        let timeline = Timeline::load(config.timeline.as_deref()).await.expect("failed to load timeline");
+        timeline.validate(shared.slide_textures.len(), &pattern_rows).expect("invalid timeline");
+        let start_step = config.start_step;
+        assert!(start_step < timeline.steps.len(),
+            "start step {} is beyond the end of the timeline ({} steps)", start_step, timeline.steps.len());
+
+        let player = Arc::new(Mutex::new(player));
         {
             let mut player_lock = player.lock().unwrap();
-            player_lock.goto(STEPS[START_FROM].0.0, STEPS[START_FROM].0.1);
+            player_lock.goto(timeline.steps[start_step].0.0, timeline.steps[start_step].0.1);
         }
-        start_audio_player(player.clone()).expect("failed to start player");
+        let paused = Arc::new(AtomicBool::new(false));
+        let analysis = Arc::new(Analysis::new(sample_rate));
+        #[cfg(not(target_arch = "wasm32"))]
+        let analysis_thread = (!offline).then(|| AnalysisThread::spawn(analysis.clone()));
+        let mut audio_counter = None;
+        let clock: Box<dyn Clock> = if offline {
+            // Whoever renders sets the time with set_clock()
+            Box::new(ManualClock::default())
+        } else {
+            let audio_clock = AudioClock::new(sample_rate, config.audio_latency);
+            audio_counter = Some(audio_clock.counter());
+            let source = Source {
+                player: player.clone(),
+                paused: paused.clone(),
+                analysis: analysis.clone(),
+                counter: audio_clock.counter(),
+                mute: config.mute,
+            };
+            if let Err(e) = audio.as_mut().unwrap().start(source) {
+                log::error!("can't play audio: {:#}", e);
+            }
+            match config.clock {
+                ClockSource::Audio => Box::new(audio_clock),
+                ClockSource::Wall => Box::new(WallClock::new()),
+            }
+        };
+
+        // Animation tracks. They come live from the editor when one is running,
+        // otherwise from the ones saved to ASSETS.
+        let rocket = Rocket::load().expect("failed to load sync tracks");
+        #[cfg(not(target_arch = "wasm32"))]
+        let rocket = match &config.rocket {
+            Some(address) if !offline => Rocket::connect(address).unwrap_or_else(|e| {
+                log::info!("no sync editor at {} ({}), playing back the saved tracks", address, e);
+                rocket
+            }),
+            _ => rocket,
+        };
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let remote = match &config.osc {
+            Some(address) if !offline => Some(Remote::bind(address).expect("failed to open the OSC port")),
+            _ => None,
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let artnet = match &config.artnet {
+            Some(address) if !offline => {
+                let mapping = match &config.artnet_map {
+                    Some(path) => DmxMapping::load(path).expect("failed to load the DMX channel mapping"),
+                    None => DmxMapping::default(),
+                };
+                Some(ArtNet::connect(address, mapping).expect("failed to open the Art-Net socket"))
+            }
+            _ => None,
+        };
+
+        let now = clock.now();
         Demo {
-            current_step: START_FROM as i32,
-            next_step: STEPS[START_FROM+1].0,
-            scene: STEPS[START_FROM].1,
-            transition: STEPS[START_FROM].2,
-            transitioned_at: Instant::now(),
-            full_quad_vertex_buffer,
-            full_quad_index_buffer,
+            current_step: start_step as i32,
+            next_step: timeline.next_position(start_step),
+            scene: timeline.steps[start_step].1,
+            transition: timeline.steps[start_step].2,
+            timeline,
+            transitioned_at: now,
             instances,
-            cd_instances,
-            starwars_instances,
-            instance_buffer,
-            cd_instance_buffer,
-            starwars_instance_buffer,
-            start_time: Instant::now(),
-            last_time: Instant::now(),
+            clock,
+            last_time: now,
             last_row: 0,
-            beat: Instant::now(),
+            beat: now,
             // last_pattern: 0,
             // pattern: Instant::now(),
             rng,
             player,
+            paused,
+            analysis,
+            #[cfg(not(target_arch = "wasm32"))]
+            _analysis_thread: analysis_thread,
+            audio,
+            audio_counter,
+            pending_seek: None,
+            pending_rebuild: false,
+            fluid_size: scene::fit_fluid_size(config.fluid_size, &device.limits()),
+            pending_fluid_size: None,
+            pending_pressure: None,
+            measure_pressure: true,
+            rocket,
             bg_shader_params,
             final_shader_params,
-            texture_bind_group_layout,
-            uniform_bind_group_layout,
-            bg_uniform_bind_group,
-            object_uniform_bind_group,
             camera,
             camera_uniform,
             camera_buffer,
             bg_function_buffer,
+            audio_buffer,
             final_function_buffer,
-            final_function_bindgroup,
-            texture_pass1,
-            texture_pass1_bindgroup,
-            texture_pass2,
-            texture_pass2_bindgroup,
-            texture_pass_window,
-            texture_pass_window_bindgroup,
-            previous_pass_texture,
-            previous_pass_texture_bind_group,
-            slide_textures,
-            slide_texture_bindgroups,
-            ocean_texture_bindgroup,
-            
-            lasers_uniform,
-            lasers_uniform_buffer,
-            lasers_uniform_bindgroup,
-
-            smoke_render_bind_group_layout,
-            smoke_render_bind_group,
-            
-            pewpew_model,
-            
-            render_pipeline_smokerender,
-            render_pipeline_cdrender,
-            render_pipeline_simple,
-            render_pipeline_starwars1,
-            render_pipeline_starwars2,
-            render_pipeline_ocean,
-
-            compute_pipeline,
-            smoke_texture_bind_group_layout,
-            smoke_compute_bindgroup1,
-            smoke_compute_bindgroup2,
-            smoke_shader_params,
-            smoke_shader_params_buffer,
-            smoke_shader_params_bindgroup,
-            current_size: FLUID_SIZE.0,
+            shared,
+            scenes,
+            pipeline_layouts,
+            targets,
+            render_resolution: config.render_resolution,
 
             frame_log: (Instant::now(), 0),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: hotreload::FileWatcher::new(
+                SHADERS.iter()
+                    .map(|(name, _)| hotreload::source_dir().join("src").join(name))
+                    .chain(iter::once(config.timeline.clone()
+                        .unwrap_or_else(|| hotreload::source_dir().join("assets").join(TIMELINE_FILE))))
+            ),
+            pattern_rows,
+            overrides: Overrides::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            remote,
+            #[cfg(not(target_arch = "wasm32"))]
+            artnet,
         }
     }
 
     pub fn update(&mut self, queue: &wgpu::Queue, encoder: &mut CommandEncoder) {
-        let now = Instant::now();
-        let time = now.duration_since(self.start_time).as_secs_f64();
-        let delta_time = now.duration_since(self.last_time).as_secs_f64();
+        #[cfg(not(target_arch = "wasm32"))]
+        for command in self.remote.as_mut().map(Remote::receive).unwrap_or_default() {
+            self.remote_command(encoder, command);
+        }
+
+        if let Some(Err(e)) = self.audio.as_mut().map(AudioOutput::check) {
+            log::error!("audio output: {}", e);
+        }
+
+        if let Some(position) = self.pending_seek.take() {
+            self.seek(encoder, position);
+        } else if std::mem::take(&mut self.pending_rebuild) {
+            let previous = (self.current_step as usize).checked_sub(1).map(|i| self.timeline.steps[i].1);
+            self.rebuild_previous(encoder, previous);
+        }
+
+        let now = self.clock.now();
+        let time = now.as_secs_f64();
+        let delta_time = now.saturating_sub(self.last_time).as_secs_f64();
         self.last_time = now;
         
-        let (pattern, row) = {
-            let player = self.player.lock().unwrap();
-            let row = player.get_current_row();
-            let pattern = player.get_current_table_index();
-            (pattern, row)
-        };
+        let (pattern, row) = self.position();
+        for event in self.rocket.update(self.song_row((pattern, row)), now) {
+            match event {
+                EditorEvent::Seek(row) => self.pending_seek = Some(self.song_position(row)),
+                EditorEvent::Pause(paused) => {
+                    if paused != self.paused.load(Ordering::Relaxed) {
+                        self.toggle_pause();
+                    }
+                }
+            }
+        }
         
         self.step(encoder, pattern, row);
         let row_beats = match pattern {
@@ -1369,7 +690,7 @@ impl Demo {
             _ => 4,
         };
         if row % row_beats == 0 && row != self.last_row {
-            self.beat = Instant::now();
+            self.beat = now;
         }
         self.last_row = row;
         // if pattern != self.last_pattern && pattern <= 9 {
@@ -1377,157 +698,28 @@ impl Demo {
         // }
         // self.last_pattern = pattern;
 
-        match &self.scene {
-            Scene::Slide(_) => {}
-            Scene::Black => {
-                queue.write_texture(
-                    self.texture_pass1.texture.as_image_copy(),
-                    vec![0; 4*1920*1080].as_slice(),
-                    wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(4*1920),
-                        rows_per_image: Some(1080)
-                    },
-                    self.texture_pass1.texture.size());
-            }
-            Scene::CDs(_) => {
-                for i in 0..NUM_CDS {
-                    self.cd_instances[i].position.z += 10.*delta_time as f32;
-                    if self.cd_instances[i].position.z >= 0. {
-                        self.cd_instances[i].position.z = self.rng.gen_range(-40.0..-20.0);
-                    }
-                    self.cd_instances[i].rotation = 
-                        Quaternion::from_angle_x(cgmath::Rad(i as f32+time as f32))
-                        *Quaternion::from_angle_y(cgmath::Rad(i as f32-time as f32));
-                }
-                self.cd_instances[NUM_CDS].position = Vector3::new(0.0,0.0,7.5);
-                self.cd_instances[NUM_CDS].rotation = 
-                    Quaternion::from_angle_x(cgmath::Rad(1.*(time as f32).cos()))
-                    *Quaternion::from_angle_y(cgmath::Rad(1.*(time as f32).sin()));
-                    
-                let instance_data = self.cd_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-                queue.write_buffer(&self.cd_instance_buffer, 0, bytemuck::cast_slice(&instance_data));
-            }
-            Scene::StarWars(_) => {
-                for i in 0..NUM_STARWARS*2 {
-                    let side = i as i32%2*2-1;
-                    self.starwars_instances[i].position.x += side as f32*-10.0*delta_time as f32;
-                    if side as f32*self.starwars_instances[i].position.x < -20.0 {
-                        self.starwars_instances[i].position = cgmath::Vector3::new(
-                            side as f32*30.0+self.rng.gen_range(-10.0..10.0),
-                            self.rng.gen_range(-10.0..10.0),
-                            self.rng.gen_range(-10.0..0.0)
-                        );
-                    }
-                }
-                let instance_data = self.starwars_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-                queue.write_buffer(&self.starwars_instance_buffer, 0, bytemuck::cast_slice(&instance_data));
-                
-                self.camera.eye = (-6.0, -6.0, 10.0).into();
-                self.camera.target = (-5.0, -1.0, 0.0).into();
-                
-            }
-            Scene::Ocean(_) => {}
-            Scene::Smoke(number) => {
-                let t = time as f32*7.;
-                for (i, params) in self.smoke_shader_params.iter_mut().enumerate() {
-                    params.delta_time = delta_time as f32;
-                    params.time = time as f32;
-                    params.x = match (row/16)%2 {
-                        0 => 0.0,
-                        1 => 1.0,
-                        _ => panic!()
-                    };
-                    queue.write_buffer(
-                        &self.smoke_shader_params_buffer[i],
-                        0,
-                        bytemuck::cast_slice(&[*params]),
-                    );
-                }
-                
-                let angle = PI*0.75+0.2*t.sin();
-                let axis = Vector3::new(angle.cos(),angle.sin(),0.0);
-                self.lasers_uniform.laser1_transform = 
-                    (//Matrix4::from_translation(Vector3::new(0.0,0.0,0.0))
-                    Matrix4::from(Quaternion::from_axis_angle(axis, Deg(50.+10.*t.cos()))))
-                    .invert().unwrap().into();
-                self.lasers_uniform.laser1_color = [1.0,0.3,0.3,0.0];
-                
-                if *number > 1 {
-                    let angle = PI*0.25+0.2*t.sin();
-                    let axis = Vector3::new(angle.cos(),angle.sin(),0.0);
-                    self.lasers_uniform.laser2_transform = 
-                        (Matrix4::from_translation(Vector3::new(0.0,100.0,0.0))
-                        *Matrix4::from(Quaternion::from_axis_angle(axis, Deg(50.+10.*t.sin()))))
-                        .invert().unwrap().into();
-                    self.lasers_uniform.laser2_color = [0.3,1.0,0.3,0.0];
-                }
-                
-                if *number > 2 {
-                    let angle = PI*1.25+0.2*t.sin();
-                    let axis = Vector3::new(angle.cos(),angle.sin(),0.0);
-                    self.lasers_uniform.laser3_transform = 
-                        (Matrix4::from_translation(Vector3::new(100.0,0.0,0.0))
-                        *Matrix4::from(Quaternion::from_axis_angle(axis, Deg(50.-10.*t.sin()))))
-                        .invert().unwrap().into();
-                    self.lasers_uniform.laser3_color = [0.3,0.3,1.0,0.0];
-                }
-                
-                if *number > 3 {
-                    let angle = PI*1.75+0.2*t.sin();
-                    let axis = Vector3::new(angle.cos(),angle.sin(),0.0);
-                    self.lasers_uniform.laser4_transform = 
-                        (Matrix4::from_translation(Vector3::new(100.0,100.0,0.0))
-                        *Matrix4::from(Quaternion::from_axis_angle(axis, Deg(50.-10.*t.cos()))))
-                        .invert().unwrap().into();
-                    self.lasers_uniform.laser4_color = [1.0,1.0,0.3,0.0];
-                }
-                
-                queue.write_buffer(&self.lasers_uniform_buffer, 0, bytemuck::cast_slice(&[self.lasers_uniform]));
-                {
-                    let (dispatch_width, dispatch_height, dispatch_depth) = compute_work_group_count(
-                        (
-                            self.current_size as u32,
-                            self.current_size as u32,
-                            self.current_size as u32,
-                        ),
-                        (8, 8, 4),
-                    );
-                    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                        label: Some("Smoke pass"),
-                        ..Default::default()
-                    });
-                    compute_pass.set_pipeline(&self.compute_pipeline);
-        
-                    let cube_time = (0x14..=0x17).contains(&pattern) && (row%16 <= 2);
-                    if cube_time {
-                        compute_pass.set_bind_group(0, &self.smoke_compute_bindgroup1, &[]);
-                        compute_pass.set_bind_group(1, &self.smoke_shader_params_bindgroup[6], &[]);
-                        compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
-                        compute_pass.set_bind_group(0, &self.smoke_compute_bindgroup2, &[]);
-                        compute_pass.set_bind_group(1, &self.smoke_shader_params_bindgroup[7], &[]);
-                        compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
-                    }
-                    for i in 0..COMPUTE_PASSES {
-                        let texture_bindgroup = match i % 2 {
-                            1 => &self.smoke_compute_bindgroup2,
-                            _ => &self.smoke_compute_bindgroup1,
-                        };
-                        compute_pass.set_bind_group(0, &texture_bindgroup, &[]);
-                        compute_pass.set_bind_group(
-                            1,
-                            &self.smoke_shader_params_bindgroup[i as usize],
-                            &[],
-                        );
-                        compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
-                    }
-                }
-            }
-        }
-
-        let beat_time = now.duration_since(self.beat).as_secs_f32();
+        let (scene, number) = self.scenes.get_mut(self.scene);
+        let events = match &self.audio_counter {
+            // Held back until they're heard, as the rows are
+            Some(counter) => counter.take_events(),
+            None => self.player.lock().unwrap().take_events(),
+        };
+        scene.update(number, &mut Frame {
+            time,
+            delta_time,
+            pattern,
+            row,
+            events: &events,
+            rocket: &mut self.rocket,
+            camera: &mut self.camera,
+            rng: &mut self.rng,
+            laser_colors: &self.overrides.laser_colors,
+            measure_pressure: std::mem::take(&mut self.measure_pressure),
+        }, &self.shared, queue, encoder);
+
+        let beat_time = now.saturating_sub(self.beat).as_secs_f32();
         // let pattern_time = now.duration_since(self.pattern).as_secs_f64();
-        let transition = now.duration_since(self.transitioned_at).as_secs_f32();
+        let transition = now.saturating_sub(self.transitioned_at).as_secs_f32();
         
         self.bg_shader_params.t = time as f32;
         
@@ -1539,16 +731,16 @@ impl Demo {
             Transition::Blink2 => 3,
         };
         self.final_shader_params.t = time as f32;
-        self.final_shader_params.x = match self.scene {
-            Scene::Slide(0) => -(1.0/transition),
-            Scene::Slide(1) => (1.0/(transition+1.)-0.2).max(0.0),
-            _ => 0.0
-        };
-        self.final_shader_params.x2 = match pattern {
-            0x04..=0x0f | 0x18..=0x19 => (1./(beat_time+0.01))/5000.0,
-            _ => 0.0
-        };
-        self.final_shader_params.transition = match self.transition {
+        self.final_shader_params.x = self.overrides.final_x.unwrap_or_else(|| {
+            let (scene, number) = self.scenes.get(self.scene);
+            scene.final_x(number, transition)
+        });
+        self.final_shader_params.x2 = self.overrides.final_x2.unwrap_or(match (self.rocket.value("final:blur"), pattern) {
+            (Some(blur), _) => blur,
+            (None, 0x04..=0x0f | 0x18..=0x19) => (1./(beat_time+0.01))/5000.0,
+            (None, _) => 0.0
+        });
+        self.final_shader_params.transition = self.overrides.final_transition.unwrap_or(match self.transition {
             Transition::Blink => {
                 let t = transition*0.2;
                 (0.526*t).max(10.*t-9.)
@@ -1556,9 +748,20 @@ impl Demo {
             Transition::Blink2 => transition*2.,
             Transition::Fade(duration) => transition/duration,
             _ => transition
-        };
+        });
 
-        if (now.duration_since(self.frame_log.0)).as_secs_f64() > 0.5 {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(artnet) = &mut self.artnet {
+            let (scene, _) = self.scenes.get(self.scene);
+            artnet.update(&ShowState {
+                lasers: scene.lasers().iter().map(LaserLight::from_laser).collect(),
+                scene: self.scene,
+                since_beat: beat_time,
+                transition: self.final_shader_params.transition.clamp(0.0, 1.0),
+            });
+        }
+
+        if self.frame_log.0.elapsed().as_secs_f64() > 0.5 {
             self.frame_log.0 += Duration::from_millis(500);
             let fps = self.frame_log.1*2;
             #[cfg(target_arch = "wasm32")]
@@ -1572,10 +775,12 @@ impl Demo {
                     })
                     .unwrap();
             }
-            log::info!(
-                "{} frames per second",
-                fps
-            );
+            let (scene, _) = self.scenes.get(self.scene);
+            match scene.pressure_residual() {
+                Some(residual) => log::info!("{} frames per second, pressure residual {:.4}", fps, residual),
+                None => log::info!("{} frames per second", fps),
+            }
+            self.measure_pressure = true;
             self.frame_log.1 = 0;
         }
         self.frame_log.1 += 1;
@@ -1585,521 +790,401 @@ impl Demo {
         queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
         queue.write_buffer(&self.final_function_buffer, 0, bytemuck::cast_slice(&[self.final_shader_params]));
         queue.write_buffer(&self.bg_function_buffer, 0, bytemuck::cast_slice(&[self.bg_shader_params]));
+        // With no threads on the web, the analysis keeps up with the callback here
+        #[cfg(target_arch = "wasm32")]
+        self.analysis.run();
+        let audible_frame = self.audio_counter.as_ref().map(AudioCounter::audible_frame);
+        queue.write_buffer(&self.audio_buffer, 0, bytemuck::cast_slice(&[self.analysis.uniform(audible_frame)]));
         let raw_instance = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw_instance));
+        queue.write_buffer(&self.shared.instance_buffer, 0, bytemuck::cast_slice(&raw_instance));
     }
     
+    /// Replaces the clock the demo runs from, e.g. with a [`ManualClock`] when
+    /// rendering frame by frame.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        let now = clock.now();
+        self.clock = clock;
+        self.last_time = now;
+        self.beat = now;
+        self.transitioned_at = now;
+    }
+
+    // Rows counted from the start of the song, which is how the sync tracks are
+    // indexed
+    fn song_row(&self, (pattern, row): (usize, usize)) -> u32 {
+        (self.pattern_rows.iter().take(pattern).sum::<usize>() + row) as u32
+    }
+
+    fn song_position(&self, song_row: u32) -> (usize, usize) {
+        let mut row = song_row as usize;
+        for (pattern, &rows) in self.pattern_rows.iter().enumerate() {
+            if row < rows {
+                return (pattern, row);
+            }
+            row -= rows;
+        }
+        (self.pattern_rows.len() - 1, 0)
+    }
+
+    /// The music's current (pattern, row). When it's playing on a device, that
+    /// is the row being heard, which the player is ahead of by the latency.
+    pub fn position(&self) -> (usize, usize) {
+        self.audio_counter.as_ref().and_then(AudioCounter::position)
+            .unwrap_or_else(|| self.player.lock().unwrap().position())
+    }
+
+    /// Pulls interleaved stereo samples from the player, for when no audio stream is
+    /// running. Returns false once the music has ended, with the rest of `out` silent.
+    pub fn render_audio(&mut self, out: &mut [f32]) -> bool {
+        let playing = fill_audio(self.player.lock().unwrap().as_mut(), out);
+        self.analysis.feed(out);
+        self.analysis.run();
+        playing
+    }
+
+    pub fn toggle_pause(&mut self) {
+        let paused = !self.paused.load(Ordering::Relaxed);
+        self.paused.store(paused, Ordering::Relaxed);
+        self.clock.set_paused(paused);
+        log::info!("{}", if paused { "paused" } else { "resumed" });
+    }
+
+    /// Jumps `offset` steps forward or back in the timeline.
+    pub fn seek_step(&mut self, offset: i32) {
+        let current = match self.pending_seek {
+            Some(position) => self.timeline.step_index_at(position) as i32,
+            None => self.current_step,
+        };
+        let last = self.timeline.steps.len() as i32 - 1;
+        let index = (current + offset).clamp(0, last);
+        self.pending_seek = Some(self.timeline.steps[index as usize].0);
+    }
+
+    /// Jumps to the start of the pattern `offset` patterns forward or back.
+    pub fn seek_pattern(&mut self, offset: i32) {
+        let (pattern, _) = self.pending_seek.unwrap_or_else(|| self.position());
+        let last = self.pattern_rows.len() as i32 - 1;
+        self.pending_seek = Some(((pattern as i32 + offset).clamp(0, last) as usize, 0));
+    }
+
+    pub fn restart(&mut self) {
+        self.pending_seek = Some(self.timeline.steps[0].0);
+    }
+
+    fn seek(&mut self, encoder: &mut CommandEncoder, position: (usize, usize)) {
+        log::info!("seeking to {:02x}/{:02x}", position.0, position.1);
+        self.player.lock().unwrap().goto(position.0, position.1);
+        if let Some(counter) = &self.audio_counter {
+            counter.clear();
+        }
+        let index = self.timeline.step_index_at(position);
+        self.current_step = index as i32;
+        (_, self.scene, self.transition) = self.timeline.steps[index];
+        self.next_step = self.timeline.next_position(index);
+        self.transitioned_at = self.clock.now();
+        self.last_row = position.1;
+
+        let previous = index.checked_sub(1).map(|i| self.timeline.steps[i].1);
+        self.rebuild_previous(encoder, previous);
+    }
+
+    // After a jump the previous pass texture still holds whatever was on screen
+    // before, so refill it with the best image we have of the scene before the new
+    // step: its still, or black.
+    fn rebuild_previous(&mut self, encoder: &mut CommandEncoder, previous: Option<Scene>) {
+        let still = previous.and_then(|previous| {
+            let (scene, number) = self.scenes.get(previous);
+            scene.still(number, &self.shared)
+        });
+        match still {
+            Some(still) => self.blit_to_previous(encoder, still),
+            None => {
+                scene::clear_pass(encoder, "Clear previous pass", &self.targets.previous_pass_texture.view, None);
+            }
+        }
+    }
+
     fn step(&mut self, encoder: &mut CommandEncoder, pattern: usize, row: usize) {
-        if (self.current_step as usize) < STEPS.len() && pattern > self.next_step.0 || (pattern == self.next_step.0 && row >= self.next_step.1) {
+        if (self.current_step as usize) < self.timeline.steps.len() && pattern > self.next_step.0 || (pattern == self.next_step.0 && row >= self.next_step.1) {
             self.current_step += 1;
-            self.copy_to_previous(encoder);
-            self.transitioned_at = Instant::now();
-            (_, self.scene, self.transition) = STEPS[self.current_step as usize];
-            self.next_step = STEPS.get(self.current_step as usize+1)
-                .map(|step| step.0)
-                .unwrap_or((0xff,0xff));
+            let (_, scene, transition) = self.timeline.steps[self.current_step as usize];
+            self.cut_to(encoder, scene, transition);
+            self.next_step = self.timeline.next_position(self.current_step as usize);
         }
     }
-    
-    fn copy_to_previous(&mut self, encoder: &mut CommandEncoder) {
-        let from_texture = match self.scene {
-            Scene::Slide(number) => &self.slide_textures[number as usize],
-            Scene::Black => &self.texture_pass1,
-            Scene::CDs(_) => &self.texture_pass1,
-            Scene::StarWars(_) => &self.texture_pass1,
-            Scene::Ocean(_) => &self.texture_pass1,
-            Scene::Smoke(_) => &self.texture_pass1
-        }.texture.as_image_copy();
-        encoder.copy_texture_to_texture(from_texture,
-            self.previous_pass_texture.texture.as_image_copy(),
-            Extent3d{
-                width: 1920,
-                height: 1080,
-                depth_or_array_layers: 1
-            }
-        );
+
+    // Switches scene, keeping the last frame of the old one to transition from
+    fn cut_to(&mut self, encoder: &mut CommandEncoder, scene: Scene, transition: Transition) {
+        self.copy_to_previous(encoder);
+        self.transitioned_at = self.clock.now();
+        self.scene = scene;
+        self.transition = transition;
     }
 
-    pub fn render(
-        &mut self,
-        view_final: &TextureView,
-        depth_view: &TextureView,
-        pipeline_final: &RenderPipeline,
-        encoder: &mut CommandEncoder,
-    ) {
-        match self.scene {
-            Scene::Slide(number) => {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass final"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view_final,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    ..Default::default()
-                });
-                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                render_pass.set_pipeline(pipeline_final);
-    
-                render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                render_pass.set_index_buffer(
-                    self.full_quad_index_buffer.slice(..),
-                    wgpu::IndexFormat::Uint32,
-                );
-                render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                render_pass.set_bind_group(0, &self.slide_texture_bindgroups[number as usize], &[]);
-                render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                render_pass.draw_indexed(0..6, 0, 0..1);
+    /// Applies a command from the OSC remote, if it makes sense for what is loaded.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn remote_command(&mut self, encoder: &mut CommandEncoder, command: Command) {
+        log::info!("remote: {:?}", command);
+        let result = match command {
+            Command::Scene(scene, transition) => scene.check(self.shared.slide_textures.len())
+                .and_then(|_| transition.check())
+                .map(|_| self.cut_to(encoder, scene, transition)),
+            Command::Transition(transition) => transition.check()
+                .map(|_| self.cut_to(encoder, self.scene, transition)),
+            Command::LaserColor(laser, color) => {
+                let colors = &mut self.overrides.laser_colors;
+                if colors.len() <= laser {
+                    colors.resize(laser + 1, None);
+                }
+                colors[laser] = color;
+                Ok(())
             }
-            Scene::Black => {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass final"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view_final,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(pipeline_final);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass1_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+            Command::FinalParam(param, value) => {
+                *match param {
+                    FinalParam::X => &mut self.overrides.final_x,
+                    FinalParam::X2 => &mut self.overrides.final_x2,
+                    FinalParam::Transition => &mut self.overrides.final_transition,
+                } = value;
+                Ok(())
             }
-            Scene::CDs(number) => {
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 1"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass_window.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_cdrender);
-        
-                    render_pass.set_vertex_buffer(1, self.cd_instance_buffer.slice(..));
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(0, &self.object_uniform_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..NUM_CDS as u32+1);
-                }
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 2"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass1.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_simple);
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    // render background (slide)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.slide_texture_bindgroups[number as usize], &[]);
-                    render_pass.set_bind_group(2, &self.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
-                    // render window (CDs)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.draw_indexed(0..6, 0, 1..2);
-                }
-                // Final pass (to screen)
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass final"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view_final,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(pipeline_final);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass1_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+            Command::Pause(paused) => {
+                if paused.is_none_or(|paused| paused != self.paused.load(Ordering::Relaxed)) {
+                    self.toggle_pause();
                 }
+                Ok(())
             }
-            Scene::StarWars(number) => {
-                { // Pass 1: 
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 1"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass2.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_starwars1);
-        
-                    render_pass.set_vertex_buffer(1, self.starwars_instance_buffer.slice(..));
-                    render_pass.set_bind_group(0, &self.object_uniform_bind_group, &[]);
-                    render_pass.draw_model_instanced(&self.pewpew_model, 0..NUM_STARWARS as u32*2);
+            Command::Seek(pattern, row) => match self.pattern_rows.get(pattern) {
+                Some(&rows) if row < rows => {
+                    self.pending_seek = Some((pattern, row));
+                    Ok(())
                 }
-                { // Pass 2: blur it
-                        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: Some("Render Pass 2"),
-                            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                view: &self.texture_pass_window.view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                    store: wgpu::StoreOp::Store,
-                                },
-                            })],
-                            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                                view: depth_view,
-                                depth_ops: Some(wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(1.0),
-                                    store: wgpu::StoreOp::Store,
-                                }),
-                                stencil_ops: None,
-                            }),
-                            ..Default::default()
-                        });
-                        render_pass.set_pipeline(&self.render_pipeline_starwars2);
-                        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                        render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                        render_pass.set_index_buffer(
-                            self.full_quad_index_buffer.slice(..),
-                            wgpu::IndexFormat::Uint32,
-                        );
-                        render_pass.set_bind_group(0, &self.texture_pass2_bindgroup, &[]);
-                        render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                        render_pass.set_bind_group(2, &self.texture_pass2_bindgroup, &[]);
-                        render_pass.draw_indexed(0..6, 0, 0..1);
-                    }
-                { // Pass 3: render the window
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 3"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass1.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_simple);
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    // render background (slide)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.slide_texture_bindgroups[number as usize], &[]);
-                    render_pass.set_bind_group(2, &self.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
-                    // render window (lasers)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.draw_indexed(0..6, 0, 2..3);
-                }
-                { // Final pass (to screen)
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass final"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view_final,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(pipeline_final);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass1_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+                Some(&rows) => Err(format!("pattern {:02x} only has {} rows", pattern, rows)),
+                None => Err(format!("pattern {:02x} is beyond the order table ({} entries)", pattern, self.pattern_rows.len())),
+            },
+            Command::Step(index) => match self.timeline.steps.get(index) {
+                Some(step) => {
+                    self.pending_seek = Some(step.0);
+                    Ok(())
                 }
+                None => Err(format!("the timeline only has {} steps", self.timeline.steps.len())),
+            },
+            Command::FluidSize(size) => {
+                self.set_fluid_size(size);
+                Ok(())
             }
-            Scene::Ocean(number) => {
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 1"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass_window.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_ocean);
-        
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.ocean_texture_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.ocean_texture_bindgroup, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
-                }
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 2"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass1.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_pipeline(&self.render_pipeline_simple);
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    // render background (slide)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.slide_texture_bindgroups[number as usize], &[]);
-                    render_pass.set_bind_group(2, &self.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
-                    // render window (ocean)
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.texture_pass_window_bindgroup, &[]);
-                    render_pass.draw_indexed(0..6, 0, 1..2);
-                }
-                // Final pass (to screen)
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass final"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view_final,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(pipeline_final);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass1_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+            Command::Pressure(solver) => {
+                log::info!("pressure solver {:?}", solver);
+                self.pending_pressure = Some(solver);
+                Ok(())
+            }
+            Command::Release => {
+                self.overrides = Overrides::default();
+                // Back to whatever the timeline has at this point
+                let index = (self.current_step as usize).min(self.timeline.steps.len() - 1);
+                let (_, scene, transition) = self.timeline.steps[index];
+                self.cut_to(encoder, scene, transition);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("remote: {}", e);
+        }
+    }
+    
+    /// Rebuilds pipelines and reloads the timeline for any watched files that changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn hot_reload(&mut self, device: &wgpu::Device) {
+        for path in self.watcher.poll() {
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("couldn't read {}: {}", path.display(), e);
+                    continue;
                 }
+            };
+            let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if file_name.ends_with(".wgsl") {
+                self.reload_shader(device, file_name, &source);
+            } else {
+                self.reload_timeline(&path.display().to_string(), &source);
             }
-            Scene::Smoke(_) => {
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass 1"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.texture_pass1.view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(&self.render_pipeline_smokerender);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.bg_uniform_bind_group, &[]);
-                    render_pass.set_bind_group(0, &self.smoke_render_bind_group, &[]);
-                    render_pass.set_bind_group(2, &self.lasers_uniform_bindgroup, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shader(&mut self, device: &wgpu::Device, file_name: &str, source: &str) {
+        log::info!("reloading {}", file_name);
+        // Build the new pipeline first and only swap it in if it validated.
+        if file_name == PRESSURE_SHADER {
+            let result = hotreload::validated(device, || {
+                let shader = create_shader(device, file_name, source);
+                PressurePipelines::new(device, &self.pipeline_layouts.pressure, &shader)
+            });
+            match result {
+                Ok(pipelines) => self.shared.pressure_pipelines = pipelines,
+                Err(e) => log::error!("{} failed to compile, keeping the old pipelines:\n{}", file_name, e),
+            }
+            return;
+        }
+        let result = hotreload::validated(device, || {
+            let shader = create_shader(device, file_name, source);
+            if file_name == "smoke_compute.wgsl" {
+                (None, Some(self.pipeline_layouts.create_compute_pipeline(device, &shader)))
+            } else {
+                (self.pipeline_layouts.create_render_pipeline(device, file_name, &shader), None)
+            }
+        });
+        match result {
+            Ok((_, Some(compute_pipeline))) => {
+                if let Some(old) = self.shared.compute_pipelines.get_mut(file_name) {
+                    *old = compute_pipeline;
                 }
-                // Final pass (to screen)
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass final"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view_final,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                        ..Default::default()
-                    });
-                    render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                    render_pass.set_pipeline(pipeline_final);
-        
-                    render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        self.full_quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(1, &self.final_function_bindgroup, &[]);
-                    render_pass.set_bind_group(0, &self.texture_pass1_bindgroup, &[]);
-                    render_pass.set_bind_group(2, &self.previous_pass_texture_bind_group, &[]);
-                    render_pass.draw_indexed(0..6, 0, 0..1);
+            }
+            Ok((Some(pipeline), _)) => {
+                if let Some(old) = self.shared.pipelines.get_mut(file_name) {
+                    *old = pipeline;
                 }
             }
+            Ok((None, None)) => {}
+            Err(e) => log::error!("{} failed to compile, keeping the old pipeline:\n{}", file_name, e),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_timeline(&mut self, name: &str, source: &str) {
+        log::info!("reloading {}", name);
+        let timeline = Timeline::parse(name, source)
+            .and_then(|timeline| timeline.validate(self.shared.slide_textures.len(), &self.pattern_rows).map(|_| timeline));
+        match timeline {
+            Ok(timeline) => {
+                let index = timeline.step_index_at(self.position());
+                self.current_step = index as i32;
+                (_, self.scene, self.transition) = timeline.steps[index];
+                self.next_step = timeline.next_position(index);
+                self.timeline = timeline;
+            }
+            Err(e) => log::error!("{:#}, keeping the old timeline", e),
         }
     }
     
-}
+    // Keeps the current frame of the scene for the next one to transition from.
+    // Slides are always 1920x1080, so they're scaled on the way.
+    fn copy_to_previous(&mut self, encoder: &mut CommandEncoder) {
+        let (scene, number) = self.scenes.get(self.scene);
+        self.blit_to_previous(encoder, scene.output(number, &self.shared, &self.targets));
+    }
 
-#[derive(Copy,Clone,Debug)]
-enum Transition {
-    None,
-    Fade(f32),
-    Slide,
-    Blink,
-    Blink2
-}
+    /// Renders the frame at the internal resolution, then scales it onto `view_final`
+    /// (a target of `surface_size`) with black bars to keep the aspect ratio.
+    pub fn render(
+        &mut self,
+        view_final: &TextureView,
+        surface_size: (u32, u32),
+        encoder: &mut CommandEncoder,
+    ) {
+        self.render_scene(encoder);
+
+        let (x, y, width, height) = letterbox(self.targets.size, surface_size);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass scale"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: view_final,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        self.blit(&mut render_pass, &self.targets.output_bindgroup);
+    }
+
+    fn blit<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, texture: &'a BindGroup) {
+        render_pass.set_pipeline(self.shared.pipeline("shaderpass_blit.wgsl"));
+        self.shared.set_quad(render_pass);
+        render_pass.set_bind_group(0, texture, &[]);
+        render_pass.draw_indexed(0..6, 0, 0..1);
+    }
+
+    // Draws `texture` over the whole previous pass texture, whatever the sizes
+    fn blit_to_previous(&self, encoder: &mut CommandEncoder, texture: &BindGroup) {
+        let mut render_pass = scene::clear_pass(encoder, "Blit to previous pass", &self.targets.previous_pass_texture.view, None);
+        self.blit(&mut render_pass, texture);
+    }
+
+    /// Follows a new window size: the frame is scaled to fit either way, but
+    /// unless the internal resolution is fixed the render targets follow the window
+    /// too.
+    pub fn resize(&mut self, device: &wgpu::Device, surface_size: (u32, u32)) {
+        let size = self.render_resolution.unwrap_or_else(|| fit_aspect(surface_size));
+        if size == self.targets.size {
+            return;
+        }
+        log::info!("render resolution {}x{}", size.0, size.1);
+        self.targets = RenderTargets::new(
+            device,
+            &self.shared.layouts.texture,
+            self.pipeline_layouts.format,
+            size,
+        );
+        self.camera.aspect = size.0 as f32 / size.1 as f32;
+        // The new previous pass texture is blank, so refill it like after a seek
+        self.pending_rebuild = true;
+    }
+
+    /// Asks for the smoke grid to be recreated at `size`, on the next
+    /// `apply_fluid_settings`. The smoke starts over.
+    pub fn set_fluid_size(&mut self, size: (u32, u32, u32)) {
+        self.pending_fluid_size = Some(size);
+    }
+
+    /// Recreates the smoke grid if a new size was asked for, as large as the
+    /// device allows, and switches to a new pressure solver once the smoke has
+    /// what it needs for it.
+    pub fn apply_fluid_settings(&mut self, device: &wgpu::Device) {
+        if let Some(solver) = self.pending_pressure.take() {
+            self.scenes.set_pressure_solver(device, &self.shared.layouts, solver);
+        }
+        let Some(size) = self.pending_fluid_size.take() else {
+            return;
+        };
+        let size = scene::fit_fluid_size(size, &device.limits());
+        if size == self.fluid_size {
+            return;
+        }
+        log::info!("fluid grid {}x{}x{}", size.0, size.1, size.2);
+        self.fluid_size = size;
+        self.scenes.resize_fluid(device, &self.shared.layouts, size);
+    }
 
-#[derive(Copy,Clone,Debug)]
-enum Scene {
-    Slide(i32),
-    Black,
-    CDs(i32),
-    StarWars(i32),
-    Ocean(i32),
-    Smoke(i32),
+    // Draws the scene, and composites it with the previous one in the final pass
+    fn render_scene(&mut self, encoder: &mut CommandEncoder) {
+        let (scene, number) = self.scenes.get(self.scene);
+        scene.render(number, &self.shared, &self.targets, encoder);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass final"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.targets.output.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        render_pass.set_pipeline(self.shared.pipeline("shaderpassfinal.wgsl"));
+        self.shared.set_quad(&mut render_pass);
+        render_pass.set_bind_group(1, &self.shared.final_function_bindgroup, &[]);
+        render_pass.set_bind_group(0, scene.output(number, &self.shared, &self.targets), &[]);
+        render_pass.set_bind_group(2, &self.targets.previous_pass_texture_bind_group, &[]);
+        render_pass.draw_indexed(0..6, 0, 0..1);
+    }
 }
 
 pub struct Camera {
@@ -2138,43 +1223,6 @@ impl CameraUniform {
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct LasersUniform {
-    pub laser1_transform: [[f32;4];4],
-    pub laser2_transform: [[f32;4];4],
-    pub laser3_transform: [[f32;4];4],
-    pub laser4_transform: [[f32;4];4],
-    pub laser1_color: [f32;4],
-    pub laser2_color: [f32;4],
-    pub laser3_color: [f32;4],
-    pub laser4_color: [f32;4]
-}
-
-impl LasersUniform {
-    fn new() -> Self {
-        LasersUniform {
-            laser1_transform: Matrix4::identity().into(),
-            laser2_transform: Matrix4::identity().into(),
-            laser3_transform: Matrix4::identity().into(),
-            laser4_transform: Matrix4::identity().into(),
-            laser1_color: [0.;4],
-            laser2_color: [0.;4],
-            laser3_color: [0.;4],
-            laser4_color: [0.;4],
-        }
-    }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct ComputeParamsUniform {
-    pub step: i32,
-    pub delta_time: f32,
-    pub time: f32,
-    pub x: f32,
-}
-
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ShaderParamsUniform {
@@ -2185,43 +1233,16 @@ pub struct ShaderParamsUniform {
     pub x2: f32,
 }
 
-fn start_audio_player(player: Arc<Mutex<XmrsPlayer>>) -> Result<(), cpal::StreamError> {
-    let mut host = cpal::default_host();
-    let mut device = host.default_output_device().unwrap();
-    // #[cfg(target_os = "windows")]
-    // {
-    //     let wasapi_host = cpal::platform::WasapiHost::new();
-    //     if let Ok(wasapi_host) = wasapi_host {
-    //         let wasapi_device = wasapi_host.default_output_device().unwrap();
-    //         host = wasapi_host.into();
-    //         device = wasapi_device.into();
-    //     }
-    // }
-
-    let config = device
-        .default_output_config()
-        .expect("failed to get default output config");
-    let mut config = config.config();
-    config.buffer_size = BufferSize::Fixed(256);
-    
-    std::thread::spawn(move || {
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    let mut player_lock = player.lock().unwrap();
-                    for sample in data.iter_mut() {
-                        *sample = player_lock.next().unwrap_or(0.0);
-                    }
-                },
-                |_: cpal::StreamError| {},
-                None,
-            )
-            .expect("failed to build output stream");
-
-        stream.play().expect("failed to play stream");
-        std::thread::sleep(std::time::Duration::from_secs_f32(300.0));
-    });
-
-    Ok(())
+/// Fills `out` with interleaved stereo samples, returning false if the music ended.
+pub fn fill_audio(player: &mut dyn Player, out: &mut [f32]) -> bool {
+    for (i, sample) in out.iter_mut().enumerate() {
+        match player.next() {
+            Some(value) => *sample = value,
+            None => {
+                out[i..].fill(0.0);
+                return false;
+            }
+        }
+    }
+    true
 }
@@ -0,0 +1,456 @@
+use std::f32::consts::PI;
+
+use crate::scene::{pressure_levels, ComputeParamsUniform, COARSEST_SMOOTHING, POST_SMOOTHING, PRE_SMOOTHING};
+
+// These have to match smoke_compute.wgsl
+const DIFFUSION: f32 = 5.0;
+const SCALE: f32 = 1.0;
+// Every velocity read has at least this much upwards
+const MIN_RISE: f32 = 5.0;
+// Has to match smoke_pressure.wgsl. Weighted Jacobi damps the shortest waves
+// fastest with this in three dimensions.
+const OMEGA: f32 = 6.0/7.0;
+
+// The heart shape step 6 adds smoke in, a row of 9 for each of 10 heights
+#[rustfmt::skip]
+const LETTER: [i32; 90] = [
+    0,0,1,0,0,0,1,0,0,
+    0,1,1,1,0,1,1,1,0,
+    0,1,1,1,1,1,1,1,0,
+    1,1,1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,1,1,
+    0,1,1,1,1,1,1,1,0,
+    0,1,1,1,1,1,1,1,0,
+    0,0,1,1,1,1,1,0,0,
+    0,0,0,1,1,1,0,0,0,
+    0,0,0,0,1,0,0,0,0,
+];
+
+/// The smoke simulation's state on the CPU: a velocity and density for each
+/// cell, like the smoke textures, and the pressure the Poisson texture holds.
+/// Cells are stored x first, then y, then z, as they are laid out in a texture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FluidGrid {
+    pub size: (u32, u32, u32),
+    /// Velocity in xyz, smoke density in w
+    pub cells: Vec<[f32; 4]>,
+    pub pressure: Vec<f32>,
+}
+
+impl FluidGrid {
+    /// A grid of still air with no smoke.
+    pub fn new(size: (u32, u32, u32)) -> Self {
+        let count = size.0 as usize*size.1 as usize*size.2 as usize;
+        FluidGrid {
+            size,
+            cells: vec![[0.0; 4]; count],
+            pressure: vec![0.0; count],
+        }
+    }
+
+    pub fn index(&self, coords: (i32, i32, i32)) -> usize {
+        index(self.size, coords)
+    }
+
+    /// Every cell's coordinates, in storage order.
+    pub fn coords(&self) -> impl Iterator<Item = (i32, i32, i32)> {
+        coords(self.size)
+    }
+
+    /// Whether the shader simulates the cell. The outermost layer is a wall that
+    /// always reads as still air.
+    pub fn is_interior(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        let inside = |c: i32, size: u32| c >= 1 && c < size as i32 - 1;
+        inside(x, self.size.0) && inside(y, self.size.1) && inside(z, self.size.2)
+    }
+
+    // What the shader's load() returns
+    fn load(&self, coords: (i32, i32, i32)) -> [f32; 4] {
+        let [x, y, z, w] = if self.is_interior(coords) {
+            self.cells[self.index(coords)]
+        } else {
+            [0.0; 4]
+        };
+        [x, y, z.max(MIN_RISE), w]
+    }
+
+    fn load_pressure(&self, coords: (i32, i32, i32)) -> f32 {
+        if self.is_interior(coords) {
+            self.pressure[self.index(coords)]
+        } else {
+            0.0
+        }
+    }
+
+    // The cell and its six neighbours, in the shader's order
+    fn neighbourhood<T>(&self, (x, y, z): (i32, i32, i32), load: impl Fn((i32, i32, i32)) -> T) -> [T; 7] {
+        [(0, 0, 0), (1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+            .map(|(dx, dy, dz)| load((x + dx, y + dy, z + dz)))
+    }
+
+    /// The divergence of the velocity at `coords` as the pressure solve sees it,
+    /// a third of the sum of central differences.
+    pub fn divergence(&self, coords: (i32, i32, i32)) -> f32 {
+        divergence(&self.neighbourhood(coords, |c| self.load(c)))
+    }
+
+    /// The root mean square divergence over the cells at least `margin` cells
+    /// from the outside of the grid. The walls read as still air, which always
+    /// adds some divergence next to them.
+    pub fn rms_divergence(&self, margin: i32) -> f32 {
+        let inside = |c: i32, size: u32| c >= margin && c < size as i32 - margin;
+        let (sum, count) = self.coords()
+            .filter(|&(x, y, z)| inside(x, self.size.0) && inside(y, self.size.1) && inside(z, self.size.2))
+            .fold((0.0, 0), |(sum, count), c| (sum + self.divergence(c).powi(2) as f64, count + 1));
+        (sum/count.max(1) as f64).sqrt() as f32
+    }
+
+    /// Solves for the pressure that cancels the divergence with `cycles`
+    /// multigrid V-cycles, the way smoke_pressure.wgsl does, starting from the
+    /// grid's pressure. Only the pressure of the grid returned differs.
+    pub fn solve_pressure(&self, cycles: u32) -> FluidGrid {
+        let mut levels: Vec<Level> = pressure_levels(self.size).into_iter().enumerate()
+            .map(|(level, size)| Level::new(level, size))
+            .collect();
+        for coords in self.coords() {
+            let i = self.index(coords);
+            levels[0].pressure[i] = self.load_pressure(coords);
+            levels[0].rhs[i] = self.pressure_rhs(coords);
+        }
+        for _ in 0..cycles {
+            v_cycle(&mut levels);
+        }
+        let mut out = self.clone();
+        out.pressure = levels.swap_remove(0).pressure;
+        out
+    }
+
+    /// How far the grid's pressure is from cancelling the divergence: the root
+    /// of the summed squared residuals over the summed squared divergence.
+    pub fn pressure_residual(&self) -> f32 {
+        let mut level = Level::new(0, self.size);
+        for coords in self.coords() {
+            let i = self.index(coords);
+            level.pressure[i] = self.load_pressure(coords);
+            level.rhs[i] = self.pressure_rhs(coords);
+        }
+        let (residual, rhs) = self.coords().fold((0.0, 0.0), |(residual, rhs), coords| {
+            let b = level.rhs[level.index(coords)] as f64;
+            (residual + (level.residual(coords) as f64).powi(2), rhs + b*b)
+        });
+        (residual/rhs.max(f64::MIN_POSITIVE)).sqrt() as f32
+    }
+
+    // The multigrid solve's right hand side: the divergence as the projection
+    // sees it, half the sum of central differences
+    fn pressure_rhs(&self, coords: (i32, i32, i32)) -> f32 {
+        if self.is_interior(coords) {
+            self.divergence(coords)*1.5
+        } else {
+            0.0
+        }
+    }
+
+    /// Runs the compute shader's step `params.step` over the whole grid and
+    /// returns what it writes. The packed density texture step 5 also writes
+    /// for rendering isn't modelled.
+    pub fn step(&self, params: &ComputeParamsUniform) -> FluidGrid {
+        if !(0..=7).contains(&params.step) {
+            // The shader writes nothing, leaving the old contents
+            return self.clone();
+        }
+        let mut out = FluidGrid::new(self.size);
+        for coords in self.coords() {
+            let i = self.index(coords);
+            let loaded = self.neighbourhood(coords, |c| self.load(c));
+            let loaded_pressure = self.neighbourhood(coords, |c| self.load_pressure(c));
+            out.pressure[i] = poisson(&loaded, &loaded_pressure);
+            out.cells[i] = match params.step {
+                0 => self.add_smoke(coords, &loaded, params),
+                1 | 2 => diffuse(&loaded, params.delta_time),
+                3 | 5 => project_velocity(&loaded, &loaded_pressure),
+                4 => self.advect(coords, params.delta_time),
+                6 => {
+                    let [x, y, z, w] = loaded[0];
+                    [x, y, z, w + self.emit(coords, params)]
+                }
+                _ => loaded[0],
+            };
+        }
+        out
+    }
+
+    // Step 0 blows smoke up from three points around the middle of the floor,
+    // as far out as the grid is wide
+    fn add_smoke(&self, (x, y, z): (i32, i32, i32), loaded: &[[f32; 4]; 7], params: &ComputeParamsUniform) -> [f32; 4] {
+        let center = [self.size.0 as f32/2.0, self.size.1 as f32/2.0, 1.0];
+        let position = [x as f32, y as f32, z as f32];
+        let floor_size = self.size.0.min(self.size.1);
+        let offset = scaled(30, floor_size) as f32;
+        let distance = |angle: f32| {
+            let point = [center[0] + offset*angle.cos(), center[1] + offset*angle.sin(), center[2]];
+            (0..3).map(|i| (position[i] - point[i]).powi(2)).sum::<f32>().sqrt()
+        };
+        let nearest = distance(2.0).min(distance(2.0/3.0*PI + 2.0)).min(distance(4.0/3.0*PI + 2.0));
+        let d = if nearest >= scaled(3, floor_size) as f32 { 1.0 } else { 0.0 };
+        let wobble = 10.0*(10.0*params.time).sin();
+        let replace = [
+            (center[0] - position[0])*10.0 + wobble,
+            (center[1] - position[1])*10.0 + wobble,
+            (center[2] - position[2])*10.0 + 150.0 + wobble,
+            1.5,
+        ];
+        [0, 1, 2, 3].map(|i| loaded[0][i]*d + replace[i]*(1.0 - d))
+    }
+
+    // Follows the velocity back to where the cell's contents came from
+    fn advect(&self, (x, y, z): (i32, i32, i32), delta_time: f32) -> [f32; 4] {
+        let current = self.load((x, y, z));
+        let back = |c: i32, v: f32| c as f32 - v*delta_time*SCALE;
+        self.trilinear_sample([back(x, current[0]), back(y, current[1]), back(z, current[2])])
+    }
+
+    fn trilinear_sample(&self, coords: [f32; 3]) -> [f32; 4] {
+        let c0 = coords.map(|c| c.floor() as i32);
+        let fraction = coords.map(|c| c - c.floor());
+        let corner = |dx, dy, dz| self.load((c0[0] + dx, c0[1] + dy, c0[2] + dz));
+        let s00 = mix(corner(0, 0, 0), corner(1, 0, 0), fraction[0]);
+        let s01 = mix(corner(0, 0, 1), corner(1, 0, 1), fraction[0]);
+        let s10 = mix(corner(0, 1, 0), corner(1, 1, 0), fraction[0]);
+        let s11 = mix(corner(0, 1, 1), corner(1, 1, 1), fraction[0]);
+        mix(mix(s00, s10, fraction[1]), mix(s01, s11, fraction[1]), fraction[2])
+    }
+
+    // Step 6 adds smoke in a hollow cube or a pair of hearts, depending on params.x
+    fn emit(&self, (x, y, z): (i32, i32, i32), params: &ComputeParamsUniform) -> f32 {
+        let (width, height, depth) = self.size;
+        let center = (width as i32/2, height as i32/2, depth as i32/2);
+        let smallest = width.min(height).min(depth);
+        if params.x < 0.5 {
+            let half = (scaled(7, width), scaled(7, height), scaled(7, depth));
+            let inside = cube((x - center.0, y - center.1, z - center.2 + scaled(10, depth)), half, scaled(3, smallest));
+            if inside { 30.0*params.delta_time } else { 0.0 }
+        } else {
+            let cell = scaled(2, smallest);
+            let c = (
+                (x.abs() - center.0 - scaled(30, width))/cell,
+                (y - center.1)/cell,
+                (z - center.2 + scaled(30, depth))/cell,
+            );
+            if (0..9).contains(&c.0) && (0..10).contains(&c.2) && c.1 == 0 {
+                LETTER[((9 - c.2)*9 + c.0) as usize] as f32*0.3
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn index(size: (u32, u32, u32), (x, y, z): (i32, i32, i32)) -> usize {
+    (x as usize) + (y as usize)*size.0 as usize + (z as usize)*(size.0*size.1) as usize
+}
+
+fn coords((width, height, depth): (u32, u32, u32)) -> impl Iterator<Item = (i32, i32, i32)> {
+    let (width, height, depth) = (width as i32, height as i32, depth as i32);
+    (0..depth).flat_map(move |z| (0..height).flat_map(move |y| (0..width).map(move |x| (x, y, z))))
+}
+
+fn mix(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| a[i]*(1.0 - t) + b[i]*t)
+}
+
+fn divergence(loaded: &[[f32; 4]; 7]) -> f32 {
+    (loaded[1][0] - loaded[2][0] + loaded[3][1] - loaded[4][1] + loaded[5][2] - loaded[6][2])/3.0
+}
+
+// One Jacobi iteration towards the pressure that cancels the divergence
+fn poisson(loaded: &[[f32; 4]; 7], loaded_pressure: &[f32; 7]) -> f32 {
+    (loaded_pressure[1..].iter().sum::<f32>() - divergence(loaded))/6.0
+}
+
+fn diffuse(loaded: &[[f32; 4]; 7], delta_time: f32) -> [f32; 4] {
+    let k = delta_time*DIFFUSION;
+    let k0 = 1.0 - delta_time*0.2;
+    [0, 1, 2, 3].map(|i| {
+        let neighbours: f32 = loaded[1..].iter().map(|cell| cell[i]).sum();
+        (loaded[0][i]*k0 + k*neighbours/6.0*SCALE)/(1.0 + k)
+    })
+}
+
+fn project_velocity(loaded: &[[f32; 4]; 7], loaded_pressure: &[f32; 7]) -> [f32; 4] {
+    let [x, y, z, w] = loaded[0];
+    [
+        x - (loaded_pressure[1] - loaded_pressure[2])*0.5,
+        y - (loaded_pressure[3] - loaded_pressure[4])*0.5,
+        z - (loaded_pressure[5] - loaded_pressure[6])*0.5,
+        w,
+    ]
+}
+
+// Whether `p` is on the edges of a cube of half size `b`, `e` cells thick
+fn cube(p: (i32, i32, i32), b: (i32, i32, i32), e: i32) -> bool {
+    let p = (p.0.abs() - b.0, p.1.abs() - b.1, p.2.abs() - b.2);
+    let q = (b.0 - p.0, b.1 - p.1, b.2 - p.2);
+    // Near two faces at once is near an edge
+    let near_faces = [q.0, q.1, q.2].iter().filter(|&&q| q < e).count();
+    p.0 <= b.0 && p.1 <= b.1 && p.2 <= b.2 && near_faces >= 2
+}
+
+// Emitters are laid out for a grid 100 cells across, like the shader's scaled()
+fn scaled(cells: i32, size: u32) -> i32 {
+    (cells*size as i32/100).max(1)
+}
+
+// One level of the multigrid solve. The finest is the grid itself, with the
+// walls held at zero pressure. The coarser ones cover what's inside the walls,
+// with cells `2^level` grid cells across, and keep the pressure at zero on their
+// outer faces by reading a mirror image beyond them.
+struct Level {
+    level: usize,
+    size: (u32, u32, u32),
+    pressure: Vec<f32>,
+    rhs: Vec<f32>,
+}
+
+impl Level {
+    fn new(level: usize, size: (u32, u32, u32)) -> Self {
+        let count = size.0 as usize*size.1 as usize*size.2 as usize;
+        Level {
+            level,
+            size,
+            pressure: vec![0.0; count],
+            rhs: vec![0.0; count],
+        }
+    }
+
+    fn index(&self, coords: (i32, i32, i32)) -> usize {
+        index(self.size, coords)
+    }
+
+    fn coords(&self) -> impl Iterator<Item = (i32, i32, i32)> {
+        coords(self.size)
+    }
+
+    fn is_unknown(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        let (first, end) = if self.level == 0 { (1, 1) } else { (0, 0) };
+        let inside = |c: i32, size: u32| c >= first && c < size as i32 - end;
+        inside(x, self.size.0) && inside(y, self.size.1) && inside(z, self.size.2)
+    }
+
+    // A neighbour one cell away at most, as the solve sees it
+    fn load(&self, (x, y, z): (i32, i32, i32)) -> f32 {
+        if self.level == 0 {
+            return if self.is_unknown((x, y, z)) { self.pressure[self.index((x, y, z))] } else { 0.0 };
+        }
+        let mut sign = 1.0;
+        let mut mirror = |c: i32, size: u32| {
+            if c < 0 || c >= size as i32 {
+                sign = -sign;
+            }
+            c.clamp(0, size as i32 - 1)
+        };
+        let coords = (mirror(x, self.size.0), mirror(y, self.size.1), mirror(z, self.size.2));
+        sign*self.pressure[self.index(coords)]
+    }
+
+    fn spacing_squared(&self) -> f32 {
+        (1 << (2*self.level)) as f32
+    }
+
+    fn neighbours(&self, (x, y, z): (i32, i32, i32)) -> [f32; 6] {
+        [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+            .map(|(dx, dy, dz)| self.load((x + dx, y + dy, z + dz)))
+    }
+
+    fn residual(&self, coords: (i32, i32, i32)) -> f32 {
+        if !self.is_unknown(coords) {
+            return 0.0;
+        }
+        let laplacian = (self.neighbours(coords).iter().sum::<f32>() - 6.0*self.load(coords))/self.spacing_squared();
+        self.rhs[self.index(coords)] - laplacian
+    }
+
+    // One weighted Jacobi sweep. Mirrored neighbours are minus the cell itself,
+    // so they count towards the diagonal.
+    fn smooth(&mut self) {
+        let pressure = self.coords().map(|coords| {
+            if !self.is_unknown(coords) {
+                return 0.0;
+            }
+            let p = self.load(coords);
+            let (sum, mirrored) = self.neighbours(coords).iter().zip(self.neighbour_coords(coords))
+                .fold((0.0, 0), |(sum, mirrored), (&value, neighbour)| {
+                    if self.level > 0 && !self.is_unknown(neighbour) { (sum, mirrored + 1) } else { (sum + value, mirrored) }
+                });
+            let jacobi = (sum - self.spacing_squared()*self.rhs[self.index(coords)])/(6 + mirrored) as f32;
+            (1.0 - OMEGA)*p + OMEGA*jacobi
+        }).collect();
+        self.pressure = pressure;
+    }
+
+    fn neighbour_coords(&self, (x, y, z): (i32, i32, i32)) -> [(i32, i32, i32); 6] {
+        [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+            .map(|(dx, dy, dz)| (x + dx, y + dy, z + dz))
+    }
+
+    // The finest level's cells start inside its walls
+    fn offset(&self) -> i32 {
+        if self.level == 0 { 1 } else { 0 }
+    }
+
+    // The residual averaged over each coarse cell, as the coarse level's right
+    // hand side, which starts from zero pressure
+    fn restrict(&self, coarse: &mut Level) {
+        let offset = self.offset();
+        for (x, y, z) in coarse.coords() {
+            let children = [0, 1].iter()
+                .flat_map(|&dz| [0, 1].iter().flat_map(move |&dy| [0, 1].iter().map(move |&dx| (dx, dy, dz))))
+                .map(|(dx, dy, dz)| (offset + 2*x + dx, offset + 2*y + dy, offset + 2*z + dz))
+                .filter(|&child| self.is_unknown(child));
+            let (sum, count) = children.fold((0.0, 0), |(sum, count), child| (sum + self.residual(child), count + 1));
+            let i = coarse.index((x, y, z));
+            coarse.rhs[i] = sum/count as f32;
+            coarse.pressure[i] = 0.0;
+        }
+    }
+
+    // Adds the coarse level's solution, interpolated between cell centres
+    fn prolong(&mut self, coarse: &Level) {
+        let offset = self.offset();
+        let pressure = self.coords().map(|(x, y, z)| {
+            if !self.is_unknown((x, y, z)) {
+                return 0.0;
+            }
+            let position = [x, y, z].map(|c| (c - offset) as f32*0.5 - 0.25);
+            let c0 = position.map(|c| c.floor() as i32);
+            let f = position.map(|c| c - c.floor());
+            let corner = |dx, dy, dz| coarse.load((c0[0] + dx, c0[1] + dy, c0[2] + dz));
+            let lerp = |a: f32, b: f32, t: f32| a*(1.0 - t) + b*t;
+            let s00 = lerp(corner(0, 0, 0), corner(1, 0, 0), f[0]);
+            let s01 = lerp(corner(0, 0, 1), corner(1, 0, 1), f[0]);
+            let s10 = lerp(corner(0, 1, 0), corner(1, 1, 0), f[0]);
+            let s11 = lerp(corner(0, 1, 1), corner(1, 1, 1), f[0]);
+            self.load((x, y, z)) + lerp(lerp(s00, s10, f[1]), lerp(s01, s11, f[1]), f[2])
+        }).collect();
+        self.pressure = pressure;
+    }
+}
+
+fn v_cycle(levels: &mut [Level]) {
+    let (level, coarser) = levels.split_first_mut().unwrap();
+    if coarser.is_empty() {
+        for _ in 0..COARSEST_SMOOTHING {
+            level.smooth();
+        }
+        return;
+    }
+    for _ in 0..PRE_SMOOTHING {
+        level.smooth();
+    }
+    level.restrict(&mut coarser[0]);
+    v_cycle(coarser);
+    level.prolong(&coarser[0]);
+    for _ in 0..POST_SMOOTHING {
+        level.smooth();
+    }
+}
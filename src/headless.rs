@@ -0,0 +1,418 @@
+use std::{iter, sync::mpsc};
+
+use web_time::Duration;
+
+use crate::{
+    clock::ManualClock,
+    config::Config,
+    demo::Demo,
+    fluid::FluidGrid,
+    scene::{compute_work_group_count, ComputeParamsUniform, Layouts, Multigrid, PressurePipelines},
+};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const DEFAULT_SIZE: (u32, u32) = (1920, 1080);
+
+/// The demo without a window: renders into its own texture and hands back the
+/// pixels. Time only moves when a frame is rendered, so the same times always give
+/// the same images.
+pub struct Headless {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    demo: Demo,
+    clock: ManualClock,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+    size: (u32, u32),
+    readback: Readback,
+}
+
+impl Headless {
+    /// Sets up the demo on a device from `request_device`.
+    pub async fn new(config: &Config) -> anyhow::Result<Self> {
+        let (device, queue) = request_device(config).await?;
+
+        // Frames come out at the internal resolution, so there's no scaling
+        let size = config.render_resolution.unwrap_or(DEFAULT_SIZE);
+        let mut demo = Demo::new(&device, &queue, FORMAT, size, config, true).await;
+        let clock = ManualClock::default();
+        demo.set_clock(Box::new(clock.clone()));
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT.add_srgb_suffix(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let readback = Readback::new(&device, size);
+
+        Ok(Headless {
+            device,
+            queue,
+            demo,
+            clock,
+            target,
+            target_view,
+            size,
+            readback,
+        })
+    }
+
+    /// Advances the demo to `time` since the start and renders a frame. Frames
+    /// should be rendered in order, as the smoke and transitions build on the last one.
+    pub fn render_frame(&mut self, time: Duration) -> anyhow::Result<image::RgbaImage> {
+        self.clock.set(time);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Headless Encoder"),
+        });
+        self.demo.update(&self.queue, &mut encoder);
+        self.demo.render(&self.target_view, self.size, &mut encoder);
+        self.readback.copy(&mut encoder, &self.target);
+        self.queue.submit(iter::once(encoder.finish()));
+        self.readback.read(&self.device)
+    }
+
+    /// The music's current (pattern, row).
+    pub fn position(&self) -> (usize, usize) {
+        self.demo.position()
+    }
+
+    /// Pulls interleaved stereo samples from the music. Returns false once it ended.
+    pub fn render_audio(&mut self, out: &mut [f32]) -> bool {
+        self.demo.render_audio(out)
+    }
+}
+
+/// The smoke compute shaders on their own, so what each of the simulation's
+/// steps does can be checked against `FluidGrid::step`, and the multigrid
+/// pressure solve against `FluidGrid::solve_pressure`.
+pub struct FluidCompute {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layouts: Layouts,
+    pipeline: wgpu::ComputePipeline,
+    pressure_pipelines: PressurePipelines,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+}
+
+impl FluidCompute {
+    /// Builds the compute pipelines on a device from `request_device`.
+    pub async fn new(config: &Config) -> anyhow::Result<Self> {
+        let (device, queue) = request_device(config).await?;
+        let layouts = Layouts::new(&device);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("smoke_compute.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("smoke_compute.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid compute pipeline layout"),
+            bind_group_layouts: &[&layouts.smoke_texture, &layouts.smoke_params],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Fluid compute pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "fluid_main",
+        });
+        let pressure_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("smoke_pressure.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("smoke_pressure.wgsl").into()),
+        });
+        let pressure_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid pressure pipeline layout"),
+            bind_group_layouts: &[&layouts.pressure, &layouts.smoke_params],
+            push_constant_ranges: &[],
+        });
+        let pressure_pipelines = PressurePipelines::new(&device, &pressure_pipeline_layout, &pressure_shader);
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid params buffer"),
+            size: std::mem::size_of::<ComputeParamsUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fluid params bind group"),
+            layout: &layouts.smoke_params,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+        });
+        Ok(FluidCompute {
+            device,
+            queue,
+            layouts,
+            pipeline,
+            pressure_pipelines,
+            params_buffer,
+            params_bind_group,
+        })
+    }
+
+    /// Uploads `grid`, runs step `params.step` of the shader over it and reads
+    /// back the smoke and pressure it wrote.
+    pub fn step(&self, grid: &FluidGrid, params: &ComputeParamsUniform) -> anyhow::Result<FluidGrid> {
+        // The outputs start out as copies of the inputs, which is what a step
+        // that writes nothing leaves
+        let cells: &[u8] = bytemuck::cast_slice(&grid.cells);
+        let pressure: &[u8] = bytemuck::cast_slice(&grid.pressure);
+        let smoke_in = self.create_texture(grid.size, wgpu::TextureFormat::Rgba32Float, cells);
+        let pressure_in = self.create_texture(grid.size, wgpu::TextureFormat::R32Float, pressure);
+        let smoke_out = self.create_texture(grid.size, wgpu::TextureFormat::Rgba32Float, cells);
+        let pressure_out = self.create_texture(grid.size, wgpu::TextureFormat::R32Float, pressure);
+        let packed = self.create_texture(grid.size, wgpu::TextureFormat::Rgba32Uint, &vec![0; cells.len()]);
+
+        let view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let views = [&smoke_in, &pressure_in, &smoke_out, &pressure_out, &packed].map(view);
+        let entries: Vec<wgpu::BindGroupEntry> = views.iter().zip(0..)
+            .map(|(view, binding)| wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fluid compute bind group"),
+            layout: &self.layouts.smoke_texture,
+            entries: &entries,
+        });
+        self.queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[*params]));
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Fluid compute encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Fluid compute pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.params_bind_group, &[]);
+            let (x, y, z) = compute_work_group_count(grid.size);
+            compute_pass.dispatch_workgroups(x, y, z);
+        }
+        self.queue.submit(iter::once(encoder.finish()));
+
+        Ok(FluidGrid {
+            size: grid.size,
+            cells: bytemuck::pod_collect_to_vec(&self.read_texture(&smoke_out, grid.size, 16)?),
+            pressure: bytemuck::pod_collect_to_vec(&self.read_texture(&pressure_out, grid.size, 4)?),
+        })
+    }
+
+    /// Uploads `grid`, improves on its pressure with `cycles` multigrid V-cycles
+    /// and reads back the pressure along with its residual, as the smoke scene
+    /// measures it.
+    pub fn solve_pressure(&self, grid: &FluidGrid, cycles: u32) -> anyhow::Result<(FluidGrid, f32)> {
+        let velocity = self.create_texture(grid.size, wgpu::TextureFormat::Rgba32Float, bytemuck::cast_slice(&grid.cells));
+        let pressure = self.create_texture(grid.size, wgpu::TextureFormat::R32Float, bytemuck::cast_slice(&grid.pressure));
+        let view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut multigrid = Multigrid::new(&self.device, &self.layouts, grid.size, &view(&velocity), &view(&pressure));
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Fluid pressure encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Fluid pressure pass"),
+                ..Default::default()
+            });
+            multigrid.solve(&mut compute_pass, &self.pressure_pipelines, cycles);
+            multigrid.measure(&mut compute_pass, &self.pressure_pipelines);
+        }
+        multigrid.copy_residual(&mut encoder);
+        self.queue.submit(iter::once(encoder.finish()));
+        multigrid.poll_residual();
+        self.device.poll(wgpu::Maintain::Wait);
+        multigrid.poll_residual();
+        let residual = multigrid.residual().ok_or_else(|| anyhow::anyhow!("the pressure residual wasn't read back"))?;
+
+        let solved = FluidGrid {
+            pressure: bytemuck::pod_collect_to_vec(&self.read_texture(&pressure, grid.size, 4)?),
+            ..grid.clone()
+        };
+        Ok((solved, residual))
+    }
+
+    fn create_texture(&self, size: (u32, u32, u32), format: wgpu::TextureFormat, data: &[u8]) -> wgpu::Texture {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: size.2,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Fluid texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format,
+            usage: wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let bytes_per_row = data.len() as u32/(size.1*size.2);
+        self.queue.write_texture(
+            texture.as_image_copy(),
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size.1),
+            },
+            extent,
+        );
+        texture
+    }
+
+    // Copies a 3D texture out, without the padding the copy needs between rows
+    fn read_texture(&self, texture: &wgpu::Texture, size: (u32, u32, u32), texel_size: u32) -> anyhow::Result<Vec<u8>> {
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let row_bytes = size.0*texel_size;
+        let padded_bytes_per_row = row_bytes.div_ceil(alignment)*alignment;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid readback buffer"),
+            size: (padded_bytes_per_row*size.1*size.2) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Fluid readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.1),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+        let data = slice.get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..row_bytes as usize])
+            .copied()
+            .collect();
+        buffer.unmap();
+        Ok(data)
+    }
+}
+
+/// A device with no surface. With `config.fallback_adapter` this picks a software
+/// rasterizer like lavapipe or WARP, for machines without a GPU.
+async fn request_device(config: &Config) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: config.backends,
+        ..Default::default()
+    });
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: config.fallback_adapter,
+        })
+        .await
+        .ok_or_else(|| anyhow::anyhow!("no suitable graphics adapter for {:?}", config.backends))?;
+    log::info!("Adapter {:?}", adapter.get_info());
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::default(),
+                required_limits: wgpu::Limits::default(),
+            },
+            None,
+        )
+        .await
+        .map_err(Into::into)
+}
+
+/// Copies a render target into a mappable buffer and back out as an image.
+struct Readback {
+    buffer: wgpu::Buffer,
+    size: (u32, u32),
+    padded_bytes_per_row: u32,
+}
+
+impl Readback {
+    fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (size.0 * 4).div_ceil(alignment) * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * size.1) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Readback {
+            buffer,
+            size,
+            padded_bytes_per_row,
+        }
+    }
+
+    fn copy(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.size.1),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.0,
+                height: self.size.1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Waits for the copy to finish and returns the pixels. The texture must be RGBA8.
+    fn read(&self, device: &wgpu::Device) -> anyhow::Result<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let row_bytes = (self.size.0 * 4) as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.size.1 as usize);
+        for row in slice.get_mapped_range().chunks(self.padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+        self.buffer.unmap();
+
+        image::RgbaImage::from_raw(self.size.0, self.size.1, pixels)
+            .ok_or_else(|| anyhow::anyhow!("readback buffer is too small"))
+    }
+}
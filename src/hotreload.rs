@@ -0,0 +1,66 @@
+use std::{path::{Path, PathBuf}, time::SystemTime};
+
+use web_time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The crate root, where the shaders and assets live while developing.
+pub fn source_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Polls modification times of a fixed set of files. Files that don't exist (e.g. when
+/// running a build away from its source tree) are simply never reported.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+        FileWatcher {
+            files,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns the files that changed since the last call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modified(path);
+            if modified.is_some() && modified != *last_modified {
+                *last_modified = modified;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Runs `f` inside a validation error scope, so a broken shader is returned as an error
+/// instead of reaching wgpu's uncaptured error handler (which panics).
+pub fn validated<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error),
+        None => Ok(value),
+    }
+}
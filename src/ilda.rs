@@ -0,0 +1,238 @@
+use std::{f32::consts::PI, io::Write};
+
+use anyhow::{anyhow, bail, Result};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::{
+    config::IldaFormat,
+    scene::{BeamShape, Laser, CONE_SPREAD, DEFAULT_INTENSITY},
+};
+
+const HEADER_SIZE: usize = 32;
+// Status byte bits
+const LAST_POINT: u8 = 0x80;
+const BLANKED: u8 = 0x40;
+// Points per shape. Projectors play a fixed number of points per second, so
+// these set how long the galvos stay on each part of the frame.
+const BLANK_POINTS: usize = 8;
+const DWELL_POINTS: usize = 12;
+const LINE_POINTS: usize = 32;
+const CIRCLE_POINTS: usize = 48;
+// Half the angle a sheet fans out over
+const FAN_ANGLE: f32 = PI/4.0;
+
+fn format_code(format: IldaFormat) -> u8 {
+    match format {
+        IldaFormat::ThreeD => 4,
+        IldaFormat::TwoD => 5,
+    }
+}
+
+fn record_size(format: IldaFormat) -> usize {
+    match format {
+        IldaFormat::ThreeD => 10,
+        IldaFormat::TwoD => 8,
+    }
+}
+
+/// One point the galvos move to. Coordinates span the whole scan field, with
+/// x to the right, y up and z towards the audience. In format 5 z is dropped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IldaPoint {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub color: [u8; 3],
+    /// Moved to with the beam off
+    pub blanked: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IldaFrame {
+    /// At most 8 ASCII characters survive writing
+    pub name: String,
+    pub company: String,
+    pub projector: u8,
+    pub points: Vec<IldaPoint>,
+}
+
+/// Writes `frames` as an ILDA file in `format`, ending with the empty header
+/// that marks the end. Every frame needs at least one point, since an empty
+/// one would read as the end of the file.
+pub fn write_ilda(out: &mut impl Write, format: IldaFormat, frames: &[IldaFrame]) -> Result<()> {
+    let total = u16::try_from(frames.len())
+        .map_err(|_| anyhow!("an ILDA file holds at most 65535 frames, got {}", frames.len()))?;
+    for (number, frame) in frames.iter().enumerate() {
+        let records = match u16::try_from(frame.points.len()) {
+            Ok(0) => bail!("frame {} has no points", number),
+            Ok(records) => records,
+            Err(_) => bail!("frame {} has {} points, at most 65535 fit", number, frame.points.len()),
+        };
+        write_header(out, format, frame, records, number as u16, total)?;
+        let mut record = Vec::with_capacity(record_size(format));
+        for (i, point) in frame.points.iter().enumerate() {
+            record.clear();
+            record.extend_from_slice(&point.x.to_be_bytes());
+            record.extend_from_slice(&point.y.to_be_bytes());
+            if format == IldaFormat::ThreeD {
+                record.extend_from_slice(&point.z.to_be_bytes());
+            }
+            let mut status = 0;
+            if i + 1 == frame.points.len() {
+                status |= LAST_POINT;
+            }
+            if point.blanked {
+                status |= BLANKED;
+            }
+            let [r, g, b] = point.color;
+            record.extend_from_slice(&[status, b, g, r]);
+            out.write_all(&record)?;
+        }
+    }
+    let end = IldaFrame {
+        name: String::new(),
+        company: String::new(),
+        projector: 0,
+        points: vec![],
+    };
+    write_header(out, format, &end, 0, total, total)?;
+    Ok(())
+}
+
+fn write_header(out: &mut impl Write, format: IldaFormat, frame: &IldaFrame, records: u16, number: u16, total: u16) -> Result<()> {
+    let mut header = [0; HEADER_SIZE];
+    header[0..4].copy_from_slice(b"ILDA");
+    header[7] = format_code(format);
+    write_name(&mut header[8..16], &frame.name);
+    write_name(&mut header[16..24], &frame.company);
+    header[24..26].copy_from_slice(&records.to_be_bytes());
+    header[26..28].copy_from_slice(&number.to_be_bytes());
+    header[28..30].copy_from_slice(&total.to_be_bytes());
+    header[30] = frame.projector;
+    out.write_all(&header)?;
+    Ok(())
+}
+
+// Names are ASCII padded with zeros, anything else is left out
+fn write_name(field: &mut [u8], name: &str) {
+    for (byte, c) in field.iter_mut().zip(name.bytes().filter(u8::is_ascii)) {
+        *byte = c;
+    }
+}
+
+fn read_name(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).trim_end_matches(['\0', ' ']).to_owned()
+}
+
+/// Reads the frames of an ILDA file in format 4 or 5, up to the empty header
+/// that ends it. Points read from format 5 have a z of 0.
+pub fn parse_ilda(data: &[u8]) -> Result<Vec<IldaFrame>> {
+    let mut frames = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let offset = data.len() - rest.len();
+        if rest.len() < HEADER_SIZE {
+            bail!("truncated header at byte {}", offset);
+        }
+        let (header, body) = rest.split_at(HEADER_SIZE);
+        if &header[0..4] != b"ILDA" {
+            bail!("no ILDA header at byte {}", offset);
+        }
+        let format = match header[7] {
+            4 => IldaFormat::ThreeD,
+            5 => IldaFormat::TwoD,
+            0..=2 => bail!("format {} at byte {} uses a palette, only true colour (4 and 5) is supported", header[7], offset),
+            code => bail!("unknown format {} at byte {}", code, offset),
+        };
+        let records = u16::from_be_bytes([header[24], header[25]]) as usize;
+        if records == 0 {
+            break;
+        }
+        let size = records * record_size(format);
+        if body.len() < size {
+            bail!("frame at byte {} needs {} bytes of points, only {} left", offset, size, body.len());
+        }
+        let (body, next) = body.split_at(size);
+        let points = body.chunks_exact(record_size(format)).map(|record| {
+            let coordinate = |i: usize| i16::from_be_bytes([record[i], record[i + 1]]);
+            let (z, color) = match format {
+                IldaFormat::ThreeD => (coordinate(4), &record[6..]),
+                IldaFormat::TwoD => (0, &record[4..]),
+            };
+            IldaPoint {
+                x: coordinate(0),
+                y: coordinate(2),
+                z,
+                color: [color[3], color[2], color[1]],
+                blanked: color[0] & BLANKED != 0,
+            }
+        }).collect();
+        frames.push(IldaFrame {
+            name: read_name(&header[8..16]),
+            company: read_name(&header[16..24]),
+            projector: header[30],
+            points,
+        });
+        rest = next;
+    }
+    Ok(frames)
+}
+
+/// The points that show `lasers` as beams from a single projector standing in
+/// the middle of the floor, aimed straight up the smoke grid's z axis. A beam's
+/// angle from vertical maps linearly onto the distance from the centre of the
+/// scan field, the way galvo angles do, so full scale is a beam lying flat;
+/// scale the output down to the projector's scan angle when playing it.
+pub fn draw_lasers(lasers: &[Laser]) -> Vec<IldaPoint> {
+    let mut points = vec![];
+    for laser in lasers {
+        let to_grid = Matrix4::from(laser.transform).invert().unwrap_or_else(Matrix4::identity);
+        let direction = |x: f32, y: f32, z: f32| (to_grid*Vector4::new(x, y, z, 0.0)).truncate();
+        let directions: Vec<Vector3<f32>> = match BeamShape::from_u32(laser.shape) {
+            BeamShape::Beam => vec![direction(0.0, 0.0, 1.0); DWELL_POINTS],
+            BeamShape::Sheet => (0..LINE_POINTS)
+                .map(|i| {
+                    let angle = FAN_ANGLE*(2.0*i as f32/(LINE_POINTS - 1) as f32 - 1.0);
+                    direction(angle.sin(), 0.0, angle.cos())
+                })
+                .collect(),
+            BeamShape::Cone => (0..=CIRCLE_POINTS)
+                .map(|i| {
+                    let around = 2.0*PI*i as f32/CIRCLE_POINTS as f32;
+                    direction(CONE_SPREAD*around.cos(), CONE_SPREAD*around.sin(), 1.0)
+                })
+                .collect(),
+        };
+        let brightness = (laser.intensity/DEFAULT_INTENSITY).clamp(0.0, 1.0);
+        let color = [0, 1, 2].map(|i| (laser.color[i].clamp(0.0, 1.0)*brightness*255.0).round() as u8);
+        // Travel to the start with the beam off, so the move doesn't draw a line
+        let start = scan_point(directions[0], [0; 3], true);
+        points.extend(std::iter::repeat_n(start, BLANK_POINTS));
+        points.extend(directions.into_iter().map(|direction| scan_point(direction, color, false)));
+    }
+    if points.is_empty() {
+        // A frame can't be empty, that would end the file
+        points.push(IldaPoint { x: 0, y: 0, z: 0, color: [0; 3], blanked: true });
+    }
+    points
+}
+
+fn scan_point(direction: Vector3<f32>, color: [u8; 3], blanked: bool) -> IldaPoint {
+    let direction = direction.normalize();
+    let from_vertical = direction.z.clamp(-1.0, 1.0).acos();
+    let horizontal = direction.x.hypot(direction.y);
+    let (x, y) = if horizontal > 1e-6 {
+        let radius = (from_vertical/(PI/2.0)).min(1.0)/horizontal;
+        (direction.x*radius, direction.y*radius)
+    } else {
+        (0.0, 0.0)
+    };
+    let scale = |v: f32| (v.clamp(-1.0, 1.0)*i16::MAX as f32).round() as i16;
+    IldaPoint {
+        x: scale(x),
+        y: scale(y),
+        z: scale(direction.z),
+        color,
+        blanked,
+    }
+}
@@ -1,6 +1,20 @@
 use std::{env, iter, sync::Arc};
 
+pub use config::{Config, IldaFormat, PressureSolver};
+pub use fluid::FluidGrid;
+pub use music::{it, load_module, EventKind, Format, Player, Stream, Tempo, Tracker, TrackerEvent};
+pub use rocket::{EditorEvent, Rocket};
+pub use scene::{ComputeParamsUniform, MIN_FLUID_SIZE};
+pub use timeline::{Scene, Transition};
 use demo::Demo;
+#[cfg(not(target_arch = "wasm32"))]
+pub use artnet::{ArtNet, DmxMapping, DmxSource, LaserLight, ShowState};
+#[cfg(not(target_arch = "wasm32"))]
+pub use headless::{FluidCompute, Headless};
+#[cfg(not(target_arch = "wasm32"))]
+pub use ilda::{parse_ilda, write_ilda, IldaFrame, IldaPoint};
+#[cfg(not(target_arch = "wasm32"))]
+pub use remote::{Command, FinalParam, Remote};
 #[cfg(target_arch="wasm32")]
 use web_sys::HtmlInputElement;
 use winit::{
@@ -13,12 +27,32 @@ use wasm_bindgen::prelude::*;
 mod model;
 mod resources;
 mod texture;
+mod analysis;
+mod audio;
 mod demo;
+mod config;
+mod clock;
+mod rocket;
+mod music;
+mod scene;
+mod fluid;
+#[cfg(not(target_arch = "wasm32"))]
+mod capture;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod timeline;
+#[cfg(not(target_arch = "wasm32"))]
+mod hotreload;
+#[cfg(not(target_arch = "wasm32"))]
+mod ilda;
+#[cfg(not(target_arch = "wasm32"))]
+mod remote;
+#[cfg(not(target_arch = "wasm32"))]
+mod artnet;
 //mod bufferedsource;
 
-use model::Vertex;
 
-pub const FLUID_SIZE: (usize, usize, usize) = (100,200,100);
+pub const FLUID_SIZE: (u32, u32, u32) = (100,100,100);
 pub const FLUID_SCALE: f64 = 1.0;
 
 #[rustfmt::skip]
@@ -106,14 +140,12 @@ struct State {
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline_final: wgpu::RenderPipeline,
     demo: Demo,
-    depth_texture: texture::Texture,
     window: Arc<Window>,
 }
 
 impl State {
-    async fn new(window: Arc<Window>) -> Self {
+    async fn new(window: Arc<Window>, config: &Config) -> Self {
         let mut size = window.inner_size();
         size.width = size.width.max(1);
         size.height = size.height.max(1);
@@ -122,7 +154,7 @@ impl State {
         log::info!("WGPU setup");
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             flags: wgpu::InstanceFlags::default(),
-            backends: wgpu::Backends::VULKAN | wgpu::Backends::METAL,
+            backends: config.backends,
             dx12_shader_compiler: Default::default(),
             gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
         });
@@ -160,12 +192,25 @@ impl State {
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
+        // The automatic modes are always there, they fall back by themselves
+        let present_mode = match config.present_mode {
+            Some(mode @ (wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync)) => mode,
+            Some(mode) if surface_caps.present_modes.contains(&mode) => mode,
+            Some(mode) => {
+                log::warn!("present mode {:?} isn't supported, using {:?}", mode, surface_caps.present_modes[0]);
+                surface_caps.present_modes[0]
+            }
+            None => surface_caps.present_modes[0],
+        };
+        log::info!("Present mode {:?}", present_mode);
+        let demo = Demo::new(&device, &queue, surface_format, (size.width, size.height), config, false).await;
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![surface_format.add_srgb_suffix()],
             desired_maximum_frame_latency: 2
@@ -173,70 +218,6 @@ impl State {
 
         surface.configure(&device, &config);
 
-        let shader_final = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shaderpassfinal.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaderpassfinal.wgsl").into()),
-        });
-
-        let demo = Demo::new(&device, &queue, surface_format).await;
-
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline_layout_final =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout final"),
-                bind_group_layouts: &[&demo.texture_bind_group_layout, &demo.uniform_bind_group_layout, &demo.texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline_final = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline final"),
-            layout: Some(&render_pipeline_layout_final),
-            vertex: wgpu::VertexState {
-                module: &shader_final,
-                entry_point: "vs_main",
-                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_final,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,},
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-        });
-        
         #[cfg(target_arch = "wasm32")]
         {
             web_sys::window()
@@ -260,9 +241,7 @@ impl State {
             queue,
             config,
             size,
-            render_pipeline_final,
             demo,
-            depth_texture,
             window,
         }
     }
@@ -273,20 +252,56 @@ impl State {
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
-            self.demo.camera.aspect = self.config.width as f32 / self.config.height as f32;
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.demo.resize(&self.device, (new_size.width, new_size.height));
         } else {
             panic!("Resized to {:?}", new_size);
         }
     }
-    fn input(&mut self, _event: &WindowEvent) -> bool {
-        false
+    // Transport controls: space pauses, left/right jump between timeline steps,
+    // down/up move a pattern back/forward, home restarts from the top.
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            event: KeyEvent {
+                logical_key,
+                state: ElementState::Pressed,
+                repeat,
+                ..
+            },
+            ..
+        } = event else {
+            return false;
+        };
+        match logical_key {
+            Key::Named(NamedKey::Space) if !repeat => self.demo.toggle_pause(),
+            Key::Named(NamedKey::ArrowLeft) => self.demo.seek_step(-1),
+            Key::Named(NamedKey::ArrowRight) => self.demo.seek_step(1),
+            Key::Named(NamedKey::ArrowDown) => self.demo.seek_pattern(-1),
+            Key::Named(NamedKey::ArrowUp) => self.demo.seek_pattern(1),
+            Key::Named(NamedKey::Home) if !repeat => self.demo.restart(),
+            _ => return false,
+        }
+        true
     }
 
     fn update(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.demo.hot_reload(&self.device);
+        // The page's slider sets the size of a cubic grid
+        #[cfg(target_arch = "wasm32")]
+        if let Some(size) = web_sys::window()
+            .and_then(|w| w.document())
+            .and_then(|d| d.get_element_by_id("size")?.dyn_into::<HtmlInputElement>().ok())
+            .map(|slider| slider.value_as_number())
+            .filter(|size| size.is_finite() && *size >= 1.0)
+        {
+            let size = size as u32;
+            self.demo.set_fluid_size((size, size, size));
+        }
+        self.demo.apply_fluid_settings(&self.device);
         // let mut encoder = self
         //     .device
         //     .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -314,8 +329,7 @@ impl State {
         self.demo.update(&self.queue, &mut encoder);
         self.demo.render(
             &view_final,
-            &self.depth_texture.view,
-            &mut self.render_pipeline_final,
+            (self.config.width, self.config.height),
             &mut encoder);
 
         self.queue.submit(iter::once(encoder.finish()));
@@ -327,6 +341,11 @@ impl State {
 
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    #[cfg(target_arch = "wasm32")]
+    let config = Config::default();
+    #[cfg(not(target_arch = "wasm32"))]
+    let config = Config::from_args();
+
     println!("{}", include_str!("../README"));
 
     cfg_if::cfg_if! {
@@ -342,13 +361,60 @@ pub async fn run() {
     }
     log::info!("hello world!");
 
+    #[cfg(not(target_arch = "wasm32"))]
+    if config.list_audio_devices {
+        match audio::output_devices() {
+            Ok(names) => names.iter().for_each(|name| println!("{}", name)),
+            Err(e) => {
+                log::error!("can't list audio devices: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if config.offline() {
+        let mut result = if config.render_dir.is_some() {
+            capture::render_to_disk(&config).await
+        } else if config.wav.is_some() {
+            capture::export_audio(&config).await
+        } else {
+            Ok(())
+        };
+        if result.is_ok() && config.ilda.is_some() {
+            result = capture::export_ilda(&config).await;
+        }
+        if let Err(e) = result {
+            log::error!("rendering failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     let title = "laser demo";
 
-    let builder = winit::window::WindowBuilder::new()
+    let monitor = config.monitor.and_then(|index| {
+        let monitor = event_loop.available_monitors().nth(index);
+        if monitor.is_none() {
+            log::warn!(
+                "no monitor with index {} ({} connected), opening on the primary one",
+                index, event_loop.available_monitors().count(),
+            );
+        }
+        monitor
+    });
+    let mut builder = winit::window::WindowBuilder::new()
         .with_title(title)
-        .with_inner_size(PhysicalSize::new(1920, 1080))
-        .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+        .with_inner_size(PhysicalSize::new(config.resolution.0, config.resolution.1));
+    if config.windowed {
+        if let Some(monitor) = &monitor {
+            builder = builder.with_position(monitor.position());
+        }
+    } else {
+        builder = builder.with_fullscreen(Some(winit::window::Fullscreen::Borderless(monitor)));
+    }
     #[cfg(target_arch = "wasm32")]
     let builder = {
         use winit::platform::web::WindowBuilderExtWebSys;
@@ -378,7 +444,7 @@ pub async fn run() {
     }
 
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(window.clone()).await;
+    let mut state = State::new(window.clone(), &config).await;
 
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     event_loop.run(move |event, target| {
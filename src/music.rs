@@ -0,0 +1,215 @@
+use anyhow::{anyhow, bail, Result};
+use xmrs::{
+    amiga::amiga_module::AmigaModule, module::{Module, Pattern}, s3m::s3m_module::S3mModule,
+    xm::xmmodule::XmModule,
+};
+use xmrsplayer::xmrsplayer::XmrsPlayer;
+
+use crate::resources::ASSETS;
+
+pub mod it;
+#[cfg(not(target_arch = "wasm32"))]
+mod opus;
+mod stream;
+
+pub use stream::{Stream, Tempo};
+
+/// Whatever plays the soundtrack, seen through the tracker's (pattern, row)
+/// positions that the timeline and sync tracks are keyed on.
+pub trait Player: Send {
+    /// The next interleaved stereo sample, or None once the music ended.
+    fn next(&mut self) -> Option<f32>;
+    /// The current (pattern, row).
+    fn position(&self) -> (usize, usize);
+    fn goto(&mut self, pattern: usize, row: usize);
+    /// The number of rows of each entry of the pattern order.
+    fn pattern_rows(&self) -> Vec<usize>;
+    /// What was written in the rows that started playing since the last call.
+    fn take_events(&mut self) -> Vec<TrackerEvent> {
+        vec![]
+    }
+}
+
+/// Something written in one channel of a pattern, reported as its row starts
+/// playing, so scenes can follow a particular drum channel or act on markers
+/// put in the effect column.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TrackerEvent {
+    pub pattern: usize,
+    pub row: usize,
+    pub channel: usize,
+    pub kind: EventKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// `note` counts semitones up from C-0 as 1. `volume` is only there when the
+    /// volume column sets one, otherwise the instrument's own volume applies.
+    Note { note: u8, instrument: Option<usize>, volume: Option<u8> },
+    NoteOff,
+    /// Effects are numbered as in XM, 0-F and then G=16 up to Z=35, whatever the
+    /// module was written in. Extended effects keep their sub-command in the
+    /// parameter, so E8x is effect 0xE with 0x8x.
+    Effect { effect: u8, parameter: u8 },
+}
+
+/// The tracker formats the music can be in. Whatever it was written in, it's
+/// converted to xmrs's `Module` so the player and the pattern/row sync see the
+/// same thing. IT goes through XM on the way, see `it::to_xm` for what that
+/// loses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Xm,
+    Mod,
+    S3m,
+    It,
+}
+
+impl Format {
+    /// Recognises a module by its header rather than its file name, since
+    /// trackers aren't consistent about extensions.
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b"Extended Module: ") {
+            Some(Format::Xm)
+        } else if data.starts_with(b"IMPM") {
+            Some(Format::It)
+        } else if data.get(44..48) == Some(b"SCRM") {
+            Some(Format::S3m)
+        } else if data.get(1080..1084).is_some_and(is_mod_tag) {
+            Some(Format::Mod)
+        } else {
+            None
+        }
+    }
+}
+
+// The tag after the sample headers and pattern order of a 31-sample MOD, which
+// is also where the channel count is given: "M.K." and friends are 4 channels,
+// "6CHN" or "12CH" say it outright.
+fn is_mod_tag(tag: &[u8]) -> bool {
+    matches!(tag, b"M.K." | b"M!K!" | b"M&K!" | b"FLT4" | b"FLT8" | b"CD81" | b"OKTA" | b"OCTA")
+        || matches!(tag, [n, b'C', b'H', b'N'] if n.is_ascii_digit())
+        || matches!(tag, [n, m, b'C', b'H'] if n.is_ascii_digit() && m.is_ascii_digit())
+}
+
+/// Loads a module of any supported format.
+pub fn load_module(data: &[u8]) -> Result<Module> {
+    match Format::detect(data) {
+        Some(Format::Xm) => XmModule::load(data)
+            .map(|xm| xm.to_module())
+            .map_err(|e| anyhow!("bad XM module: {:?}", e)),
+        Some(Format::Mod) => AmigaModule::load(data)
+            .map(|amiga| amiga.to_module())
+            .map_err(|e| anyhow!("bad MOD module: {:?}", e)),
+        Some(Format::S3m) => S3mModule::load(data)
+            .map(|s3m| s3m.to_module())
+            .map_err(|e| anyhow!("bad S3M module: {:?}", e)),
+        // xmrsplayer is tied to an xmrs that can't read Impulse Tracker files
+        Some(Format::It) => it::to_xm(data)
+            .and_then(|xm| XmModule::load(&xm).map_err(|e| anyhow!("{:?}", e)))
+            .map(|xm| xm.to_module())
+            .map_err(|e| anyhow!("bad IT module: {}", e)),
+        None => bail!("not a MOD, S3M, IT or XM module"),
+    }
+}
+
+/// A tracker module played by xmrsplayer.
+pub struct Tracker {
+    player: XmrsPlayer,
+    pattern_rows: Vec<usize>,
+    pattern_order: Vec<usize>,
+    patterns: Vec<Pattern>,
+    // The row events were last collected for
+    last_position: Option<(usize, usize)>,
+    events: Vec<TrackerEvent>,
+}
+
+impl Tracker {
+    pub fn new(module: Module, sample_rate: u32) -> Self {
+        let pattern_rows = module.pattern_order
+            .iter()
+            .map(|&p| module.pattern.get(p).map_or(0, |pattern| pattern.len()))
+            .collect();
+        Tracker {
+            pattern_order: module.pattern_order.clone(),
+            patterns: module.pattern.clone(),
+            player: XmrsPlayer::new(module.into(), sample_rate as f32),
+            pattern_rows,
+            last_position: None,
+            events: vec![],
+        }
+    }
+
+    fn collect_events(&mut self, (pattern, row): (usize, usize)) {
+        let slots = self.pattern_order.get(pattern)
+            .and_then(|&index| self.patterns.get(index))
+            .and_then(|rows| rows.get(row));
+        for (channel, slot) in slots.into_iter().flatten().enumerate() {
+            let event = |kind| TrackerEvent { pattern, row, channel, kind };
+            match u8::from(slot.note) {
+                0 => {}
+                97 => self.events.push(event(EventKind::NoteOff)),
+                note => self.events.push(event(EventKind::Note {
+                    note,
+                    instrument: slot.instrument,
+                    // 0x10-0x50 is a volume, anything else a volume column effect
+                    volume: (0x10..=0x50).contains(&slot.volume).then(|| slot.volume - 0x10),
+                })),
+            }
+            if slot.effect_type != 0 || slot.effect_parameter != 0 {
+                self.events.push(event(EventKind::Effect { effect: slot.effect_type, parameter: slot.effect_parameter }));
+            }
+        }
+    }
+}
+
+impl Player for Tracker {
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.player.next();
+        let position = self.position();
+        if self.last_position != Some(position) {
+            self.last_position = Some(position);
+            self.collect_events(position);
+        }
+        sample
+    }
+
+    fn position(&self) -> (usize, usize) {
+        (self.player.get_current_table_index(), self.player.get_current_row())
+    }
+
+    fn goto(&mut self, pattern: usize, row: usize) {
+        self.player.goto(pattern, row);
+        self.last_position = None;
+        self.events.clear();
+    }
+
+    fn pattern_rows(&self) -> Vec<usize> {
+        self.pattern_rows.clone()
+    }
+
+    fn take_events(&mut self) -> Vec<TrackerEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// The demo's music: the `music.*` file in the assets, played at `sample_rate`.
+/// Tracker modules are recognised by their header; anything else is taken to be
+/// a recording (Ogg Vorbis or Opus, or WAV) and streamed, with its rows coming from the
+/// tempo in `tempo.ron`.
+pub fn load_asset(sample_rate: u32) -> Result<Box<dyn Player>> {
+    let file = ASSETS
+        .files()
+        .find(|file| file.path().file_stem().is_some_and(|stem| stem == "music"))
+        .ok_or_else(|| anyhow!("no music.xm, .mod, .s3m, .it, .ogg, .opus or .wav in the assets"))?;
+    let path = file.path();
+    if Format::detect(file.contents()).is_some() {
+        let module = load_module(file.contents()).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        return Ok(Box::new(Tracker::new(module, sample_rate)));
+    }
+    let tempo = Tempo::load()?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let stream = Stream::new(file.contents(), extension, tempo, sample_rate)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    Ok(Box::new(stream))
+}
@@ -0,0 +1,687 @@
+use anyhow::{anyhow, bail, Result};
+
+// Impulse Tracker's tone portamento speeds for volume column values 193-202
+const PORTAMENTO_SPEEDS: [u16; 10] = [0x00, 0x01, 0x04, 0x08, 0x10, 0x20, 0x40, 0x60, 0x80, 0xFF];
+
+/// Rewrites an Impulse Tracker module as an XM one that xmrs can load.
+///
+/// Channels keep their numbers and the pattern order keeps its patterns, but
+/// `+++` separators are dropped, so orders after one count from one less than
+/// Impulse Tracker shows. Whatever XM can't express is left out or
+/// approximated: new note actions, pitch envelopes, filters, keyboard
+/// transposition within an instrument, channel volume and the rarer effects.
+pub fn to_xm(data: &[u8]) -> Result<Vec<u8>> {
+    let order_count = u16_at(data, 0x20)? as usize;
+    let instrument_count = u16_at(data, 0x22)? as usize;
+    let sample_count = u16_at(data, 0x24)? as usize;
+    let pattern_count = u16_at(data, 0x26)? as usize;
+    let compatible_version = u16_at(data, 0x2A)?;
+    let flags = u16_at(data, 0x2C)?;
+    let speed = u8_at(data, 0x32)?;
+    let tempo = u8_at(data, 0x33)?;
+    let channel_panning = bytes(data, 0x40, 64)?;
+    let orders = bytes(data, 0xC0, order_count)?;
+    let offsets = |at: usize, count: usize| {
+        (0..count).map(|i| Ok(u32_at(data, at + 4 * i)? as usize)).collect::<Result<Vec<_>>>()
+    };
+    let instrument_offsets = offsets(0xC0 + order_count, instrument_count)?;
+    let sample_offsets = offsets(0xC0 + order_count + 4 * instrument_count, sample_count)?;
+    let pattern_offsets = offsets(0xC0 + order_count + 4 * (instrument_count + sample_count), pattern_count)?;
+
+    let samples = sample_offsets.iter().enumerate()
+        .map(|(i, &offset)| Sample::read(data, offset).map_err(|e| anyhow!("sample {}: {}", i + 1, e)))
+        .collect::<Result<Vec<_>>>()?;
+    // Without instruments, patterns play samples directly
+    let instruments = if flags & 4 != 0 {
+        instrument_offsets.iter().enumerate()
+            .map(|(i, &offset)| {
+                Instrument::read(data, offset, compatible_version, &samples)
+                    .map_err(|e| anyhow!("instrument {}: {}", i + 1, e))
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        (0..samples.len()).map(Instrument::single).collect()
+    };
+
+    let order = orders.iter().copied()
+        .take_while(|&pattern| pattern != 255)
+        .filter(|&pattern| pattern != 254)
+        .take(256)
+        .collect::<Vec<_>>();
+    if order.is_empty() {
+        bail!("the pattern order is empty");
+    }
+    // Orders can name patterns that were never saved, which play as empty ones
+    let used_patterns = order.iter().map(|&pattern| pattern as usize + 1).max().unwrap_or(0);
+    let mut patterns = pattern_offsets.iter().enumerate()
+        .map(|(i, &offset)| read_pattern(data, offset).map_err(|e| anyhow!("pattern {}: {}", i, e)))
+        .collect::<Result<Vec<_>>>()?;
+    patterns.resize(patterns.len().max(used_patterns), vec![[Cell::default(); 64]; 64]);
+    // Disabled channels don't play
+    for row in patterns.iter_mut().flatten() {
+        for (cell, &panning) in row.iter_mut().zip(channel_panning) {
+            if panning & 0x80 != 0 {
+                *cell = Cell::default();
+            }
+        }
+    }
+    let channels = patterns.iter().flatten()
+        .filter_map(|row| row.iter().rposition(|cell| *cell != Cell::default()))
+        .max()
+        .map_or(2, |last| (last + 2) & !1);
+
+    let mut xm = b"Extended Module: ".to_vec();
+    put_name(&mut xm, bytes(data, 4, 26)?, 20);
+    xm.push(0x1A);
+    put_name(&mut xm, b"Impulse Tracker", 20);
+    xm.extend_from_slice(&0x0104u16.to_le_bytes());
+    xm.extend_from_slice(&276u32.to_le_bytes());
+    xm.extend_from_slice(&(order.len() as u16).to_le_bytes());
+    xm.extend_from_slice(&0u16.to_le_bytes());
+    xm.extend_from_slice(&(channels as u16).to_le_bytes());
+    xm.extend_from_slice(&(patterns.len() as u16).to_le_bytes());
+    xm.extend_from_slice(&(instruments.len() as u16).to_le_bytes());
+    // Linear slides
+    xm.extend_from_slice(&(flags >> 3 & 1).to_le_bytes());
+    xm.extend_from_slice(&(speed.max(1) as u16).to_le_bytes());
+    xm.extend_from_slice(&(if tempo < 32 { 125 } else { tempo as u16 }).to_le_bytes());
+    xm.extend_from_slice(&order);
+    xm.resize(xm.len() + 256 - order.len(), 0);
+
+    for (i, pattern) in patterns.iter().enumerate() {
+        let mut packed = vec![];
+        for row in pattern {
+            for cell in &row[..channels] {
+                cell.to_xm(instruments.len()).pack(&mut packed);
+            }
+        }
+        let packed_size = u16::try_from(packed.len()).map_err(|_| anyhow!("pattern {} is too big for XM", i))?;
+        xm.extend_from_slice(&9u32.to_le_bytes());
+        xm.push(0);
+        xm.extend_from_slice(&(pattern.len() as u16).to_le_bytes());
+        xm.extend_from_slice(&packed_size.to_le_bytes());
+        xm.extend_from_slice(&packed);
+    }
+
+    for instrument in &instruments {
+        instrument.write(&mut xm, &samples);
+    }
+    Ok(xm)
+}
+
+fn bytes(data: &[u8], offset: usize, length: usize) -> Result<&[u8]> {
+    offset.checked_add(length)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| anyhow!("the file ends before byte {}", offset + length))
+}
+
+fn u8_at(data: &[u8], offset: usize) -> Result<u8> {
+    Ok(bytes(data, offset, 1)?[0])
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(bytes(data, offset, 2)?.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(bytes(data, offset, 4)?.try_into().unwrap()))
+}
+
+// Cuts a name at its terminator and pads or truncates it to `length`
+fn put_name(xm: &mut Vec<u8>, name: &[u8], length: usize) {
+    let name = name.split(|&c| c == 0).next().unwrap_or_default();
+    let start = xm.len();
+    xm.extend(name.iter().take(length));
+    xm.resize(start + length, 0);
+}
+
+struct Sample<'a> {
+    name: &'a [u8],
+    // 0-64, with the sample's global volume applied
+    volume: u8,
+    // 0-64
+    panning: Option<u8>,
+    c5_speed: u32,
+    sixteen_bit: bool,
+    // Start and end frames, and whether it's ping-pong
+    repeat: Option<(usize, usize, bool)>,
+    // Mixed down to mono if it was stereo, in the range of its bit depth
+    frames: Vec<i16>,
+}
+
+impl<'a> Sample<'a> {
+    fn read(data: &'a [u8], offset: usize) -> Result<Self> {
+        if bytes(data, offset, 4)? != b"IMPS" {
+            bail!("no IMPS header");
+        }
+        let global_volume = u8_at(data, offset + 0x11)?.min(64);
+        let flags = u8_at(data, offset + 0x12)?;
+        let volume = u8_at(data, offset + 0x13)?.min(64);
+        let conversion = u8_at(data, offset + 0x2E)?;
+        let panning = u8_at(data, offset + 0x2F)?;
+        let length = u32_at(data, offset + 0x30)? as usize;
+        let repeat = |at: usize, on: u8, ping_pong: u8| -> Result<_> {
+            let (start, end) = (u32_at(data, offset + at)? as usize, u32_at(data, offset + at + 4)? as usize);
+            Ok((flags & on != 0 && start < end && end <= length).then_some((start, end, flags & ping_pong != 0)))
+        };
+        // XM has no sustain loop, but it's better than none
+        let repeat = repeat(0x34, 0x10, 0x40)?.or(repeat(0x40, 0x20, 0x80)?);
+        let frames = if flags & 1 != 0 {
+            read_frames(data, u32_at(data, offset + 0x48)? as usize, length, flags, conversion)?
+        } else {
+            vec![]
+        };
+        Ok(Sample {
+            name: bytes(data, offset + 0x14, 26)?,
+            volume: (volume as u16 * global_volume as u16 / 64) as u8,
+            panning: (panning & 0x80 != 0).then_some((panning & 0x7F).min(64)),
+            c5_speed: u32_at(data, offset + 0x3C)?,
+            sixteen_bit: flags & 2 != 0,
+            repeat: repeat.filter(|_| !frames.is_empty()),
+            frames,
+        })
+    }
+
+    // As XM's relative note and finetune, which are relative to 8363 Hz at C-4
+    fn tuning(&self) -> (i8, i8) {
+        let semitones = 12.0 * (self.c5_speed.max(1) as f64 / 8363.0).log2();
+        let note = semitones.round();
+        let finetune = ((semitones - note) * 128.0).round();
+        (note.clamp(-96.0, 95.0) as i8, finetune.clamp(-128.0, 127.0) as i8)
+    }
+
+    fn write_header(&self, xm: &mut Vec<u8>, instrument_volume: u8, instrument_panning: Option<u8>) {
+        let width = if self.sixteen_bit { 2 } else { 1 };
+        let (start, end, _) = self.repeat.unwrap_or_default();
+        let (relative_note, finetune) = self.tuning();
+        xm.extend_from_slice(&((self.frames.len() * width) as u32).to_le_bytes());
+        xm.extend_from_slice(&((start * width) as u32).to_le_bytes());
+        xm.extend_from_slice(&(((end - start) * width) as u32).to_le_bytes());
+        xm.push((self.volume as u16 * instrument_volume as u16 / 128) as u8);
+        xm.push(finetune as u8);
+        let repeat = match self.repeat {
+            None => 0,
+            Some((_, _, false)) => 1,
+            Some((_, _, true)) => 2,
+        };
+        xm.push(repeat | if self.sixteen_bit { 0x10 } else { 0 });
+        xm.push(self.panning.or(instrument_panning).map_or(128, |panning| (panning as u16 * 255 / 64) as u8));
+        xm.push(relative_note as u8);
+        xm.push(0);
+        put_name(xm, self.name, 22);
+    }
+
+    // XM stores each frame as the difference from the previous one
+    fn write_frames(&self, xm: &mut Vec<u8>) {
+        let mut previous = 0i16;
+        for &frame in &self.frames {
+            let delta = frame.wrapping_sub(previous);
+            if self.sixteen_bit {
+                xm.extend_from_slice(&delta.to_le_bytes());
+            } else {
+                xm.push(delta as u8);
+            }
+            previous = frame;
+        }
+    }
+}
+
+fn read_frames(data: &[u8], mut offset: usize, length: usize, flags: u8, conversion: u8) -> Result<Vec<i16>> {
+    let sixteen_bit = flags & 2 != 0;
+    let channels = if flags & 4 != 0 { 2 } else { 1 };
+    let mut decoded = vec![];
+    for _ in 0..channels {
+        let channel = if flags & 8 != 0 {
+            decompress(data, &mut offset, length, sixteen_bit, conversion & 4 != 0)?
+        } else {
+            let width = if sixteen_bit { 2 } else { 1 };
+            let raw = bytes(data, offset, length.saturating_mul(width))?;
+            offset += length * width;
+            let unsigned = conversion & 1 == 0;
+            let mut frames = raw.chunks_exact(width)
+                .map(|frame| match *frame {
+                    [low, high] => i16::from_le_bytes([low, high]) ^ if unsigned { i16::MIN } else { 0 },
+                    [byte] => (byte ^ if unsigned { 0x80 } else { 0 }) as i8 as i16,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            // Delta encoded
+            if conversion & 4 != 0 {
+                let mut total = 0i16;
+                for frame in &mut frames {
+                    total = total.wrapping_add(*frame);
+                    *frame = if sixteen_bit { total } else { total as i8 as i16 };
+                }
+            }
+            frames
+        };
+        decoded.push(channel);
+    }
+    Ok(match decoded.as_slice() {
+        [left, right] => left.iter().zip(right).map(|(&left, &right)| ((left as i32 + right as i32) / 2) as i16).collect(),
+        _ => decoded.swap_remove(0),
+    })
+}
+
+// IT214 and IT215 compression: blocks of variable-width deltas, each starting
+// over at full width. IT215 deltas are of the deltas.
+fn decompress(data: &[u8], offset: &mut usize, length: usize, sixteen_bit: bool, it215: bool) -> Result<Vec<i16>> {
+    let (block_length, sample_bits, width_bits) = if sixteen_bit { (0x4000, 16, 4) } else { (0x8000, 8, 3) };
+    let full_width = sample_bits + 1;
+    let band = 1 << width_bits;
+    let wrap = |value: i32| if sixteen_bit { value as i16 as i32 } else { value as i8 as i32 };
+    let mut frames = vec![];
+    while frames.len() < length {
+        let size = u16_at(data, *offset)? as usize;
+        let mut bits = Bits { data: bytes(data, *offset + 2, size)?, position: 0 };
+        *offset += 2 + size;
+        let end = frames.len() + block_length.min(length - frames.len());
+        let mut width = full_width;
+        let (mut delta, mut value) = (0, 0);
+        while frames.len() < end {
+            let mut read = bits.read(width)?;
+            if width < 7 {
+                // A lone top bit announces a new width
+                if read == 1 << (width - 1) {
+                    let new = bits.read(width_bits)? + 1;
+                    width = if new < width { new } else { new + 1 };
+                    continue;
+                }
+            } else if width < full_width {
+                // As does a value in a band just under the largest
+                let border = (((1 << sample_bits) - 1) >> (full_width - width)) - band / 2;
+                if read > border && read <= border + band {
+                    read -= border;
+                    width = if read < width { read } else { read + 1 };
+                    continue;
+                }
+            } else if read & 1 << sample_bits != 0 {
+                width = (read + 1) & 0xFF;
+                if width == 0 || width > full_width {
+                    bail!("bad bit width in compressed data");
+                }
+                continue;
+            }
+            let shift = 32 - width.min(sample_bits);
+            delta = wrap(delta + ((read << shift) as i32 >> shift));
+            value = wrap(value + delta);
+            frames.push((if it215 { value } else { delta }) as i16);
+        }
+    }
+    Ok(frames)
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Bits<'_> {
+    // Least significant bit first
+    fn read(&mut self, width: u32) -> Result<u32> {
+        let mut value = 0;
+        for bit in 0..width {
+            let byte = self.data.get(self.position / 8).ok_or_else(|| anyhow!("compressed data ends early"))?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << bit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    // Tick and value, the value 0-64
+    points: Vec<(u16, u16)>,
+    on: bool,
+    sustain: Option<u8>,
+    repeat: Option<(u8, u8)>,
+}
+
+impl Envelope {
+    // XM takes 12 points, a sustain point rather than a sustain loop, and
+    // panning from 0 to 64 rather than -32 to 32
+    fn read(data: &[u8], offset: usize, panning: bool) -> Result<Self> {
+        let flags = u8_at(data, offset)?;
+        let count = u8_at(data, offset + 1)?.min(12);
+        let [loop_start, loop_end, sustain_start, _] = bytes(data, offset + 2, 4)?.try_into().unwrap();
+        let points = (0..count as usize)
+            .map(|i| {
+                let value = u8_at(data, offset + 6 + 3 * i)?;
+                let value = if panning { (value as i8 as i16 + 32).clamp(0, 64) as u16 } else { value.min(64) as u16 };
+                Ok((u16_at(data, offset + 7 + 3 * i)?, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Envelope {
+            points,
+            on: flags & 1 != 0 && count > 0,
+            repeat: (flags & 2 != 0 && loop_start <= loop_end && loop_end < count).then_some((loop_start, loop_end)),
+            sustain: (flags & 4 != 0 && sustain_start < count).then_some(sustain_start),
+        })
+    }
+
+    fn write_points(&self, xm: &mut Vec<u8>) {
+        for i in 0..12 {
+            let (tick, value) = self.points.get(i).copied().unwrap_or_default();
+            xm.extend_from_slice(&tick.to_le_bytes());
+            xm.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        self.on as u8 | (self.sustain.is_some() as u8) << 1 | (self.repeat.is_some() as u8) << 2
+    }
+}
+
+struct Instrument<'a> {
+    name: &'a [u8],
+    // The IT samples it plays, at most 16 as XM allows
+    samples: Vec<usize>,
+    // Which of `samples` each XM note plays
+    keyboard: [u8; 96],
+    volume: u8,
+    panning: Option<u8>,
+    fadeout: u16,
+    volume_envelope: Envelope,
+    panning_envelope: Envelope,
+}
+
+impl<'a> Instrument<'a> {
+    fn read(data: &'a [u8], offset: usize, compatible_version: u16, samples: &[Sample]) -> Result<Self> {
+        if bytes(data, offset, 4)? != b"IMPI" {
+            bail!("no IMPI header");
+        }
+        let table = bytes(data, offset + 0x40, 240)?;
+        let mut instrument_samples = vec![];
+        let mut keyboard = [0; 96];
+        // XM's C-0 is IT's C-1
+        for (key, entry) in keyboard.iter_mut().zip(table.chunks_exact(2).skip(12)) {
+            let sample = entry[1] as usize;
+            if sample == 0 || samples.get(sample - 1).is_none_or(|sample| sample.frames.is_empty()) {
+                continue;
+            }
+            let index = match instrument_samples.iter().position(|&s| s == sample - 1) {
+                Some(index) => index,
+                None if instrument_samples.len() < 16 => {
+                    instrument_samples.push(sample - 1);
+                    instrument_samples.len() - 1
+                }
+                None => 0,
+            };
+            *key = index as u8;
+        }
+        let name = bytes(data, offset + 0x20, 26)?;
+        // Instruments from before Impulse Tracker 2 keep their envelopes in a
+        // way that isn't worth converting
+        if compatible_version < 0x200 {
+            return Ok(Instrument {
+                name,
+                samples: instrument_samples,
+                keyboard,
+                volume: 128,
+                panning: None,
+                fadeout: (u16_at(data, offset + 0x18)? as u32 * 64).min(0xFFF) as u16,
+                volume_envelope: Envelope::default(),
+                panning_envelope: Envelope::default(),
+            });
+        }
+        let panning = u8_at(data, offset + 0x19)?;
+        Ok(Instrument {
+            name,
+            samples: instrument_samples,
+            keyboard,
+            volume: u8_at(data, offset + 0x18)?.min(128),
+            panning: (panning & 0x80 == 0).then_some(panning.min(64)),
+            fadeout: (u16_at(data, offset + 0x14)? as u32 * 32).min(0xFFF) as u16,
+            volume_envelope: Envelope::read(data, offset + 0x130, false)?,
+            panning_envelope: Envelope::read(data, offset + 0x182, true)?,
+        })
+    }
+
+    // Stands in for a sample when the module has no instruments
+    fn single(sample: usize) -> Self {
+        Instrument {
+            name: &[],
+            samples: vec![sample],
+            keyboard: [0; 96],
+            volume: 128,
+            panning: None,
+            fadeout: 0,
+            volume_envelope: Envelope::default(),
+            panning_envelope: Envelope::default(),
+        }
+    }
+
+    fn write(&self, xm: &mut Vec<u8>, samples: &[Sample]) {
+        let samples = self.samples.iter().map(|&sample| &samples[sample])
+            .filter(|sample| !sample.frames.is_empty())
+            .collect::<Vec<_>>();
+        let name = if self.name.is_empty() { samples.first().map_or(&[][..], |sample| sample.name) } else { self.name };
+        xm.extend_from_slice(&263u32.to_le_bytes());
+        put_name(xm, name, 22);
+        xm.push(0);
+        xm.extend_from_slice(&(samples.len() as u16).to_le_bytes());
+        xm.extend_from_slice(&40u32.to_le_bytes());
+        xm.extend_from_slice(&self.keyboard);
+        self.volume_envelope.write_points(xm);
+        self.panning_envelope.write_points(xm);
+        for envelope in [&self.volume_envelope, &self.panning_envelope] {
+            xm.push(envelope.points.len() as u8);
+        }
+        for envelope in [&self.volume_envelope, &self.panning_envelope] {
+            let (start, end) = envelope.repeat.unwrap_or_default();
+            xm.extend_from_slice(&[envelope.sustain.unwrap_or_default(), start, end]);
+        }
+        xm.extend_from_slice(&[self.volume_envelope.kind(), self.panning_envelope.kind()]);
+        // No auto-vibrato
+        xm.extend_from_slice(&[0; 4]);
+        xm.extend_from_slice(&self.fadeout.to_le_bytes());
+        xm.extend_from_slice(&[0; 22]);
+        for sample in &samples {
+            sample.write_header(xm, self.volume, self.panning);
+        }
+        for sample in &samples {
+            sample.write_frames(xm);
+        }
+    }
+}
+
+// A pattern cell as Impulse Tracker stores it
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+struct Cell {
+    note: Option<u8>,
+    instrument: u8,
+    volume: Option<u8>,
+    command: u8,
+    parameter: u8,
+}
+
+fn read_pattern(data: &[u8], offset: usize) -> Result<Vec<[Cell; 64]>> {
+    // Patterns that were never saved are 64 empty rows
+    if offset == 0 {
+        return Ok(vec![[Cell::default(); 64]; 64]);
+    }
+    let length = u16_at(data, offset)? as usize;
+    let rows = u16_at(data, offset + 2)? as usize;
+    if rows == 0 || rows > 256 {
+        bail!("{} rows", rows);
+    }
+    let mut packed = bytes(data, offset + 8, length)?.iter().copied();
+    let mut next = || packed.next().ok_or_else(|| anyhow!("ends early"));
+    let mut pattern = vec![[Cell::default(); 64]; rows];
+    // Each channel's mask and values are remembered for the next time it
+    // comes up
+    let mut masks = [0; 64];
+    let mut last = [Cell::default(); 64];
+    let mut row = 0;
+    while row < rows {
+        let channel_mask = next()?;
+        if channel_mask == 0 {
+            row += 1;
+            continue;
+        }
+        let channel = (channel_mask as usize - 1) & 63;
+        if channel_mask & 0x80 != 0 {
+            masks[channel] = next()?;
+        }
+        let (mask, last) = (masks[channel], &mut last[channel]);
+        if mask & 1 != 0 {
+            last.note = Some(next()?);
+        }
+        if mask & 2 != 0 {
+            last.instrument = next()?;
+        }
+        if mask & 4 != 0 {
+            last.volume = Some(next()?);
+        }
+        if mask & 8 != 0 {
+            last.command = next()?;
+            last.parameter = next()?;
+        }
+        let cell = &mut pattern[row][channel];
+        if mask & 0x11 != 0 {
+            cell.note = last.note;
+        }
+        if mask & 0x22 != 0 {
+            cell.instrument = last.instrument;
+        }
+        if mask & 0x44 != 0 {
+            cell.volume = last.volume;
+        }
+        if mask & 0x88 != 0 {
+            cell.command = last.command;
+            cell.parameter = last.parameter;
+        }
+    }
+    Ok(pattern)
+}
+
+impl Cell {
+    fn to_xm(self, instruments: usize) -> XmCell {
+        let (volume, volume_effect) = self.volume.map_or((0, None), volume_column);
+        let mut effect = effect(self.command, self.parameter);
+        let note = match self.note {
+            // IT's C-5 plays samples at their C5 speed, as XM's C-4 does
+            Some(note @ 0..=119) => {
+                let mut note = note as i16 - 11;
+                while note < 1 {
+                    note += 12;
+                }
+                while note > 96 {
+                    note -= 12;
+                }
+                note as u8
+            }
+            // A note cut is an effect in XM, or failing that a note off
+            Some(254) if effect.is_none() => {
+                effect = Some((0xE, 0xC0));
+                0
+            }
+            Some(_) => 97,
+            None => 0,
+        };
+        let (effect, parameter) = effect.or(volume_effect).unwrap_or_default();
+        XmCell {
+            note,
+            instrument: if (self.instrument as usize) <= instruments { self.instrument } else { 0 },
+            volume,
+            effect,
+            parameter,
+        }
+    }
+}
+
+// The XM volume column byte for an IT volume column value, or an effect when
+// it can only be done in the effect column
+fn volume_column(volume: u8) -> (u8, Option<(u8, u8)>) {
+    match volume {
+        0..=64 => (0x10 + volume, None),
+        65..=74 => (0x90 | (volume - 65), None),
+        75..=84 => (0x80 | (volume - 75), None),
+        85..=94 => (0x70 | (volume - 85), None),
+        95..=104 => (0x60 | (volume - 95), None),
+        105..=114 => (0, Some((0x2, (volume - 105) * 4))),
+        115..=124 => (0, Some((0x1, (volume - 115) * 4))),
+        128..=192 => (0xC0 | ((volume - 128) as u16 * 15 / 64) as u8, None),
+        193..=202 => (0xF0 | PORTAMENTO_SPEEDS[(volume - 193) as usize].div_ceil(16).min(15) as u8, None),
+        203..=212 => (0xB0 | (volume - 203), None),
+        _ => (0, None),
+    }
+}
+
+// The XM effect for an IT one, numbered from A as 1, if there's one close
+// enough
+fn effect(command: u8, parameter: u8) -> Option<(u8, u8)> {
+    let (x, y) = (parameter >> 4, parameter & 0xF);
+    Some(match (command, x, y) {
+        // Speed
+        (1, _, _) if parameter > 0 => (0xF, parameter.min(0x1F)),
+        // Position jump
+        (2, _, _) => (0xB, parameter),
+        // Pattern break, to a row given in decimal in XM
+        (3, _, _) => (0xD, ((parameter.min(99) / 10) << 4) | (parameter.min(99) % 10)),
+        // Fine volume slides
+        (4, _, 0xF) if x != 0 => (0xE, 0xA0 | x),
+        (4, 0xF, _) if y != 0 => (0xE, 0xB0 | y),
+        (4, _, _) => (0xA, parameter),
+        // Pitch slides, then fine and extra fine ones
+        (5, 0xF, _) => (0xE, 0x20 | y),
+        (5, 0xE, _) => (33, 0x20 | y),
+        (5, _, _) => (0x2, parameter),
+        (6, 0xF, _) => (0xE, 0x10 | y),
+        (6, 0xE, _) => (33, 0x10 | y),
+        (6, _, _) => (0x1, parameter),
+        // Tone portamento, vibrato, tremor, arpeggio and their combinations
+        (7, _, _) => (0x3, parameter),
+        (8, _, _) => (0x4, parameter),
+        (9, _, _) => (29, parameter),
+        (10, _, _) => (0x0, parameter),
+        (11, _, _) => (0x6, parameter),
+        (12, _, _) => (0x5, parameter),
+        // Sample offset
+        (15, _, _) => (0x9, parameter),
+        // Panning slide, which goes the other way in XM
+        (16, _, _) if x != 0xF && y != 0xF => (25, y << 4 | x),
+        // Retrigger and tremolo
+        (17, _, _) => (27, parameter),
+        (18, _, _) => (0x7, parameter),
+        // Glissando, waveforms, panning, pattern loop, note cut and delay,
+        // pattern delay
+        (19, 0x1, _) => (0xE, 0x30 | y),
+        (19, 0x3, _) => (0xE, 0x40 | y),
+        (19, 0x4, _) => (0xE, 0x70 | y),
+        (19, 0x8, _) => (0x8, y * 0x11),
+        (19, 0xB, _) => (0xE, 0x60 | y),
+        (19, 0xC..=0xE, _) => (0xE, parameter),
+        // Tempo
+        (20, _, _) if parameter >= 0x20 => (0xF, parameter),
+        // Fine vibrato
+        (21, _, _) => (0x4, x << 4 | y.div_ceil(4)),
+        // Global volume and its slide
+        (22, _, _) => (16, parameter.min(128) / 2),
+        (23, _, _) if x != 0xF && y != 0xF => (17, parameter),
+        // Panning
+        (24, _, _) => (0x8, parameter),
+        _ => return None,
+    })
+}
+
+struct XmCell {
+    note: u8,
+    instrument: u8,
+    volume: u8,
+    effect: u8,
+    parameter: u8,
+}
+
+impl XmCell {
+    // Packed as a byte saying which of the fields follow
+    fn pack(&self, packed: &mut Vec<u8>) {
+        let fields = [self.note, self.instrument, self.volume, self.effect, self.parameter];
+        let present = fields.iter().enumerate()
+            .filter(|(_, &field)| field != 0)
+            .fold(0x80, |present, (i, _)| present | 1 << i);
+        packed.push(present);
+        packed.extend(fields.iter().filter(|&&field| field != 0));
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Mutex;
+
+use audiopus::{coder::{Decoder as Libopus, GenericCtl}, packet::Packet as OpusPacket, Channels, MutSignals, SampleRate};
+use symphonia::core::{
+    audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal, SignalSpec},
+    codecs::{CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult, CODEC_TYPE_OPUS},
+    errors::{decode_error, unsupported_error, Result},
+    formats::Packet,
+    support_codec,
+};
+
+// The longest an Opus packet can play for, 120 ms
+const MAX_FRAMES: usize = 5760;
+
+/// Decodes the Opus packets symphonia reads from an Ogg file with libopus,
+/// since symphonia has no Opus decoder of its own. Only mono and stereo
+/// streams are supported.
+pub struct OpusDecoder {
+    params: CodecParameters,
+    // Symphonia wants decoders it can share between threads, which libopus'
+    // isn't, but it's only ever used through `&mut self`
+    decoder: Mutex<Libopus>,
+    channels: usize,
+    interleaved: Vec<f32>,
+    buffer: AudioBuffer<f32>,
+}
+
+impl Decoder for OpusDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        let Some(layout) = params.channels else {
+            return unsupported_error("opus: no channel layout");
+        };
+        let channels = match layout.count() {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => return unsupported_error("opus: only mono and stereo are supported"),
+        };
+        // libopus decodes at any of its rates, and the Ogg mapping always uses 48 kHz
+        let Ok(decoder) = Libopus::new(SampleRate::Hz48000, channels) else {
+            return unsupported_error("opus: libopus can't make a decoder");
+        };
+        Ok(OpusDecoder {
+            params: params.clone(),
+            decoder: Mutex::new(decoder),
+            channels: layout.count(),
+            interleaved: vec![0.0; MAX_FRAMES * layout.count()],
+            buffer: AudioBuffer::new(MAX_FRAMES as u64, SignalSpec::new(48000, layout)),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus")]
+    }
+
+    fn reset(&mut self) {
+        if let Ok(decoder) = self.decoder.get_mut() {
+            let _ = decoder.reset_state();
+        }
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        self.buffer.clear();
+        let Ok(decoder) = self.decoder.get_mut() else {
+            return decode_error("opus: the decoder panicked before");
+        };
+        let (Ok(input), Ok(output)) = (OpusPacket::try_from(packet.buf()), MutSignals::try_from(&mut self.interleaved[..])) else {
+            return decode_error("opus: empty packet");
+        };
+        let Ok(frames) = decoder.decode_float(Some(input), output, false) else {
+            return decode_error("opus: invalid packet");
+        };
+        self.buffer.render_reserved(Some(frames));
+        for channel in 0..self.channels {
+            for (frame, sample) in self.buffer.chan_mut(channel).iter_mut().enumerate() {
+                *sample = self.interleaved[frame * self.channels + channel];
+            }
+        }
+        Ok(self.buffer.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        FinalizeResult::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buffer.as_audio_buffer_ref()
+    }
+}
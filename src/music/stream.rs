@@ -0,0 +1,249 @@
+use std::{collections::VecDeque, io::Cursor};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CodecRegistry, Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::Time,
+};
+
+#[cfg(target_arch = "wasm32")]
+use symphonia::core::codecs::CODEC_TYPE_OPUS;
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::opus::OpusDecoder;
+use super::Player;
+use crate::resources::ASSETS;
+
+pub const TEMPO_FILE: &str = "tempo.ron";
+
+/// How a recording lines up with the tracker rows the timeline is written in:
+/// row 0 of pattern 0 falls `offset` seconds into the file, and from there rows
+/// tick at a steady `bpm`.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Tempo {
+    pub bpm: f64,
+    pub rows_per_beat: usize,
+    #[serde(default = "default_rows_per_pattern")]
+    pub rows_per_pattern: usize,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_rows_per_pattern() -> usize {
+    64
+}
+
+impl Tempo {
+    pub fn load() -> Result<Self> {
+        let source = ASSETS.get_file(TEMPO_FILE).and_then(|file| file.contents_utf8())
+            .ok_or_else(|| anyhow!("a recorded soundtrack needs its tempo in {}", TEMPO_FILE))?;
+        Self::parse(TEMPO_FILE, source)
+    }
+
+    pub fn parse(name: &str, source: &str) -> Result<Self> {
+        let tempo: Tempo = ron::from_str(source)
+            .map_err(|e| anyhow!("{}:{}", name, e))?;
+        if !tempo.bpm.is_finite() || tempo.bpm <= 0.0 || tempo.rows_per_beat == 0 || tempo.rows_per_pattern == 0 {
+            bail!("{}: bpm, rows_per_beat and rows_per_pattern must be positive", name);
+        }
+        Ok(tempo)
+    }
+
+    fn row_length(&self) -> f64 {
+        60.0 / (self.bpm * self.rows_per_beat as f64)
+    }
+
+    /// Rows counted from the start of the song at `time` seconds into the file.
+    pub fn song_row(&self, time: f64) -> usize {
+        ((time - self.offset) / self.row_length()).max(0.0) as usize
+    }
+
+    pub fn time(&self, pattern: usize, row: usize) -> f64 {
+        self.offset + (pattern * self.rows_per_pattern + row) as f64 * self.row_length()
+    }
+}
+
+/// A recorded soundtrack, decoded a packet at a time as it plays and resampled
+/// to the output rate. Its patterns are all `rows_per_pattern` long, as many as
+/// it takes to cover the file.
+pub struct Stream {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    tempo: Tempo,
+    file_rate: u32,
+    length: u64,
+    // Frames at the start that only prime the decoder, like Opus' pre-skip
+    delay: u64,
+    // Decoded frames from `front` on, with the read position `phase` frames past
+    // the first of them. Output frames are interpolated between the first two.
+    frames: VecDeque<[f32; 2]>,
+    front: u64,
+    phase: f64,
+    step: f64,
+    // Frames before this one are the delay, or left over from seeking to a
+    // packet boundary
+    skip_to: u64,
+    right: Option<f32>,
+    ended: bool,
+}
+
+impl Stream {
+    /// `extension` only helps pick the container, the contents decide.
+    pub fn new(data: &'static [u8], extension: Option<&str>, tempo: Tempo, sample_rate: u32) -> Result<Self> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = extension {
+            hint.with_extension(extension);
+        }
+        let format = symphonia::default::get_probe()
+            .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?
+            .format;
+        let track = format.tracks().iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("no audio track"))?;
+        #[cfg(target_arch = "wasm32")]
+        if track.codec_params.codec == CODEC_TYPE_OPUS {
+            bail!("Opus is decoded with libopus, which the web build doesn't have, use Vorbis or WAV");
+        }
+        let mut codecs = CodecRegistry::new();
+        symphonia::default::register_enabled_codecs(&mut codecs);
+        #[cfg(not(target_arch = "wasm32"))]
+        codecs.register_all::<OpusDecoder>();
+        let decoder = codecs.make(&track.codec_params, &DecoderOptions::default())?;
+        let file_rate = track.codec_params.sample_rate.ok_or_else(|| anyhow!("unknown sample rate"))?;
+        let length = track.codec_params.n_frames.ok_or_else(|| anyhow!("unknown length"))?;
+        let delay = track.codec_params.delay.unwrap_or(0) as u64;
+        Ok(Stream {
+            track_id: track.id,
+            format,
+            decoder,
+            tempo,
+            file_rate,
+            length,
+            delay,
+            frames: VecDeque::new(),
+            front: delay,
+            phase: 0.0,
+            step: file_rate as f64 / sample_rate as f64,
+            skip_to: delay,
+            right: None,
+            ended: false,
+        })
+    }
+
+    fn time(&self) -> f64 {
+        (self.front as f64 + self.phase - self.delay as f64) / self.file_rate as f64
+    }
+
+    fn next_frame(&mut self) -> Option<[f32; 2]> {
+        loop {
+            while self.phase >= 1.0 && !self.frames.is_empty() {
+                self.frames.pop_front();
+                self.front += 1;
+                self.phase -= 1.0;
+            }
+            if self.phase < 1.0 && self.frames.len() >= 2 {
+                break;
+            }
+            if !self.decode() {
+                return None;
+            }
+        }
+        let (a, b, t) = (self.frames[0], self.frames[1], self.phase as f32);
+        self.phase += self.step;
+        Some([a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t])
+    }
+
+    // Appends the next packet's frames, returning false at the end of the file
+    fn decode(&mut self) -> bool {
+        while !self.ended {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    log::error!("soundtrack: {}", e);
+                    break;
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A damaged packet is only a short dropout
+                Err(Error::DecodeError(e)) => {
+                    log::warn!("soundtrack: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("soundtrack: {}", e);
+                    break;
+                }
+            };
+            let channels = decoded.spec().channels.count();
+            let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            samples.copy_interleaved_ref(decoded);
+            let skip = self.skip_to.saturating_sub(packet.ts()) as usize;
+            if self.frames.is_empty() {
+                self.front = packet.ts() + skip as u64;
+            }
+            self.frames.extend(samples.samples().chunks_exact(channels).skip(skip).map(|frame| match frame {
+                [mono] => [*mono, *mono],
+                [left, right, ..] => [*left, *right],
+                [] => unreachable!(),
+            }));
+            return true;
+        }
+        self.ended = true;
+        false
+    }
+}
+
+impl Player for Stream {
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let [left, right] = self.next_frame()?;
+        self.right = Some(right);
+        Some(left)
+    }
+
+    fn position(&self) -> (usize, usize) {
+        let row = self.tempo.song_row(self.time());
+        (row / self.tempo.rows_per_pattern, row % self.tempo.rows_per_pattern)
+    }
+
+    fn goto(&mut self, pattern: usize, row: usize) {
+        let time = self.tempo.time(pattern, row).max(0.0);
+        self.frames.clear();
+        self.phase = 0.0;
+        self.right = None;
+        self.decoder.reset();
+        let seek_time = Time::from(time + self.delay as f64 / self.file_rate as f64);
+        match self.format.seek(SeekMode::Accurate, SeekTo::Time { time: seek_time, track_id: Some(self.track_id) }) {
+            Ok(seeked) => {
+                self.front = seeked.required_ts;
+                self.skip_to = seeked.required_ts;
+                self.ended = false;
+            }
+            Err(e) => {
+                log::warn!("soundtrack can't seek to {:.3}s: {}", time, e);
+                self.ended = true;
+            }
+        }
+    }
+
+    fn pattern_rows(&self) -> Vec<usize> {
+        let rows = self.tempo.song_row(self.length.saturating_sub(self.delay) as f64 / self.file_rate as f64) + 1;
+        vec![self.tempo.rows_per_pattern; rows.div_ceil(self.tempo.rows_per_pattern)]
+    }
+}
@@ -0,0 +1,252 @@
+use std::{io, net::{SocketAddr, UdpSocket}};
+
+use crate::{
+    config::{PressureSolver, DEFAULT_PRESSURE_CYCLES},
+    timeline::{Scene, Transition, MAX_LASERS},
+};
+
+// The messages understood, for setting up a controller:
+//
+//   /scene <scene> [transition]   switch scene now, in timeline syntax: "CDs(9)" "Fade(1.0)"
+//   /transition <transition>      restart the current scene with this transition
+//   /laser/<1-64>/color r g b [a] override a laser's colour
+//   /laser/<1-64>/color           go back to the scene's colour
+//   /final/<x|x2|transition> v    override a parameter of the final pass
+//   /final/<x|x2|transition>      go back to the computed value
+//   /pause [0|1]                  pause or resume, or toggle without an argument
+//   /seek <pattern> <row>         jump to a position in the music
+//   /step <index>                 jump to a step of the timeline
+//   /fluid <n> | <x> <y> <z>      recreate the smoke grid at this size
+//   /pressure jacobi | multigrid [cycles]
+//                                 switch the smoke's pressure solver
+//   /release                      drop every override
+//
+// Numbers may be sent as ints or floats.
+
+/// Something an operator asked for. Only the shape of the message is checked
+/// here, the demo checks the values against what it has loaded.
+#[derive(Clone, Debug)]
+pub enum Command {
+    Scene(Scene, Transition),
+    Transition(Transition),
+    LaserColor(usize, Option<[f32; 4]>),
+    FinalParam(FinalParam, Option<f32>),
+    Pause(Option<bool>),
+    Seek(usize, usize),
+    Step(usize),
+    FluidSize((u32, u32, u32)),
+    Pressure(PressureSolver),
+    Release,
+}
+
+/// The fields of the final pass's `ShaderParamsUniform` that can be overridden.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FinalParam {
+    X,
+    X2,
+    Transition,
+}
+
+#[derive(Clone, Debug)]
+enum Argument {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+
+/// Listens for OSC messages on a UDP socket, without ever blocking the frame.
+pub struct Remote {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl Remote {
+    /// `address` is either a port, which is then listened to on every interface, or
+    /// a full socket address.
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let socket = match address.parse::<u16>() {
+            Ok(port) => UdpSocket::bind(("0.0.0.0", port))?,
+            Err(_) => UdpSocket::bind(address)?,
+        };
+        socket.set_nonblocking(true)?;
+        log::info!("listening for OSC on {}", socket.local_addr()?);
+        Ok(Remote {
+            socket,
+            buffer: vec![0; 65536],
+        })
+    }
+
+    /// Where it's listening.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Every command that arrived since the last call. Malformed messages are
+    /// logged and dropped.
+    pub fn receive(&mut self) -> Vec<Command> {
+        let mut commands = vec![];
+        loop {
+            let (length, sender) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("OSC socket: {}", e);
+                    break;
+                }
+            };
+            let mut messages = vec![];
+            if let Err(e) = parse_packet(&self.buffer[..length], &mut messages) {
+                log::warn!("bad OSC packet from {}: {}", sender, e);
+                continue;
+            }
+            for (address, arguments) in messages {
+                match command(&address, &arguments) {
+                    Ok(command) => commands.push(command),
+                    Err(e) => log::warn!("OSC {} from {}: {}", address, sender, e),
+                }
+            }
+        }
+        commands
+    }
+}
+
+fn command(address: &str, arguments: &[Argument]) -> Result<Command, String> {
+    let parts = address.strip_prefix('/').unwrap_or(address).split('/').collect::<Vec<_>>();
+    Ok(match (parts.as_slice(), arguments) {
+        (["scene"], [scene]) => Command::Scene(ron_value(scene)?, Transition::None),
+        (["scene"], [scene, transition]) => Command::Scene(ron_value(scene)?, ron_value(transition)?),
+        (["transition"], [transition]) => Command::Transition(ron_value(transition)?),
+        (["laser", laser, "color"], color) => {
+            let laser = match laser.parse::<usize>() {
+                Ok(laser @ 1..=MAX_LASERS) => laser - 1,
+                _ => return Err(format!("no laser {}, they're numbered 1 to {}", laser, MAX_LASERS)),
+            };
+            let color = match color {
+                [] => None,
+                [r, g, b] => Some([float(r)?, float(g)?, float(b)?, 0.0]),
+                [r, g, b, a] => Some([float(r)?, float(g)?, float(b)?, float(a)?]),
+                _ => return Err("expected r g b [a]".to_owned()),
+            };
+            Command::LaserColor(laser, color)
+        }
+        (["final", param], value) => {
+            let param = match *param {
+                "x" => FinalParam::X,
+                "x2" => FinalParam::X2,
+                "transition" => FinalParam::Transition,
+                _ => return Err(format!("no final pass parameter {}", param)),
+            };
+            let value = match value {
+                [] => None,
+                [value] => Some(float(value)?),
+                _ => return Err("expected one value".to_owned()),
+            };
+            Command::FinalParam(param, value)
+        }
+        (["pause"], []) => Command::Pause(None),
+        (["pause"], [paused]) => Command::Pause(Some(match paused {
+            Argument::Bool(paused) => *paused,
+            paused => index(paused)? != 0,
+        })),
+        (["seek"], [pattern, row]) => Command::Seek(index(pattern)?, index(row)?),
+        (["step"], [step]) => Command::Step(index(step)?),
+        (["fluid"], [size]) => {
+            let size = index(size)? as u32;
+            Command::FluidSize((size, size, size))
+        }
+        (["fluid"], [x, y, z]) => Command::FluidSize((index(x)? as u32, index(y)? as u32, index(z)? as u32)),
+        (["pressure"], [solver, cycles @ ..]) => Command::Pressure(match (solver, cycles) {
+            (Argument::String(solver), []) if solver == "jacobi" => PressureSolver::Jacobi,
+            (Argument::String(solver), []) if solver == "multigrid" => PressureSolver::Multigrid(DEFAULT_PRESSURE_CYCLES),
+            (Argument::String(solver), [cycles]) if solver == "multigrid" => match index(cycles)? {
+                0 => return Err("a multigrid solve needs at least one cycle".to_owned()),
+                cycles => PressureSolver::Multigrid(cycles as u32),
+            },
+            _ => return Err("expected jacobi, or multigrid and optionally a number of cycles".to_owned()),
+        }),
+        (["release"], []) => Command::Release,
+        _ => return Err(format!("unknown message with {} arguments", arguments.len())),
+    })
+}
+
+fn ron_value<T: serde::de::DeserializeOwned>(argument: &Argument) -> Result<T, String> {
+    match argument {
+        Argument::String(source) => ron::from_str(source).map_err(|e| format!("\"{}\": {}", source, e)),
+        _ => Err(format!("expected a string, got {:?}", argument)),
+    }
+}
+
+fn float(argument: &Argument) -> Result<f32, String> {
+    let value = match *argument {
+        Argument::Float(value) => value,
+        Argument::Int(value) => value as f32,
+        _ => return Err(format!("expected a number, got {:?}", argument)),
+    };
+    if !value.is_finite() {
+        return Err(format!("{} is not a usable number", value));
+    }
+    Ok(value)
+}
+
+// Controllers often only send floats, so whole floats count as integers
+fn index(argument: &Argument) -> Result<usize, String> {
+    match *argument {
+        Argument::Int(value) if value >= 0 => Ok(value as usize),
+        Argument::Float(value) if value >= 0.0 && value.fract() == 0.0 => Ok(value as usize),
+        _ => Err(format!("expected a non-negative integer, got {:?}", argument)),
+    }
+}
+
+// Bundles are unpacked into their messages, which are all applied right away
+// whatever their time tag says.
+fn parse_packet(packet: &[u8], messages: &mut Vec<(String, Vec<Argument>)>) -> Result<(), String> {
+    if let Some(mut rest) = packet.strip_prefix(b"#bundle\0") {
+        rest = rest.get(8..).ok_or("bundle without a time tag")?;
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap()) as usize;
+            parse_packet(take(&mut rest, length)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let mut rest = packet;
+    let address = osc_string(&mut rest)?;
+    if !address.starts_with('/') {
+        return Err(format!("\"{}\" is not an OSC address", address));
+    }
+    // Very old senders leave out the type tags, and then there are no arguments
+    // we could make sense of anyway
+    let tags = if rest.is_empty() { ",".to_owned() } else { osc_string(&mut rest)? };
+    let tags = tags.strip_prefix(',').ok_or("missing type tags")?;
+    let mut arguments = vec![];
+    for tag in tags.chars() {
+        arguments.push(match tag {
+            'i' => Argument::Int(i32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap())),
+            'f' => Argument::Float(f32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap())),
+            's' => Argument::String(osc_string(&mut rest)?),
+            'T' => Argument::Bool(true),
+            'F' => Argument::Bool(false),
+            _ => return Err(format!("unsupported argument type '{}'", tag)),
+        });
+    }
+    messages.push((address, arguments));
+    Ok(())
+}
+
+fn take<'a>(rest: &mut &'a [u8], length: usize) -> Result<&'a [u8], String> {
+    if rest.len() < length {
+        return Err("packet ends too early".to_owned());
+    }
+    let (taken, remaining) = rest.split_at(length);
+    *rest = remaining;
+    Ok(taken)
+}
+
+// A nul-terminated string padded to a multiple of 4 bytes
+fn osc_string(rest: &mut &[u8]) -> Result<String, String> {
+    let end = rest.iter().position(|&b| b == 0).ok_or("unterminated string")?;
+    let string = std::str::from_utf8(&rest[..end]).map_err(|e| e.to_string())?.to_owned();
+    take(rest, (end + 4) & !3)?;
+    Ok(string)
+}
@@ -0,0 +1,378 @@
+use serde::{Deserialize, Serialize};
+use web_time::Duration;
+
+use crate::resources::ASSETS;
+
+pub const TRACKS_FILE: &str = "sync.ron";
+pub const DEFAULT_EDITOR: &str = "127.0.0.1:1338";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    Smooth,
+    Ramp,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Key {
+    pub row: u32,
+    pub value: f32,
+    pub interpolation: Interpolation,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Track {
+    pub name: String,
+    // Sorted by row
+    pub keys: Vec<Key>,
+}
+
+impl Track {
+    /// The value at `row`, or None if the track has no keys.
+    pub fn value(&self, row: f64) -> Option<f32> {
+        let next = self.keys.partition_point(|key| (key.row as f64) <= row);
+        let Some(key) = next.checked_sub(1).map(|i| self.keys[i]) else {
+            return self.keys.first().map(|key| key.value);
+        };
+        let Some(next) = self.keys.get(next) else {
+            return Some(key.value);
+        };
+        let t = ((row - key.row as f64) / (next.row - key.row) as f64) as f32;
+        let t = match key.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+            Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+            Interpolation::Ramp => t * t,
+        };
+        Some(key.value + (next.value - key.value) * t)
+    }
+
+    fn set_key(&mut self, key: Key) {
+        match self.keys.binary_search_by_key(&key.row, |k| k.row) {
+            Ok(i) => self.keys[i] = key,
+            Err(i) => self.keys.insert(i, key),
+        }
+    }
+
+    fn delete_key(&mut self, row: u32) {
+        self.keys.retain(|key| key.row != row);
+    }
+}
+
+/// What the editor asked the demo to do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EditorEvent {
+    Seek(u32),
+    Pause(bool),
+}
+
+/// Animation tracks in the style of GNU Rocket, indexed by a running row count
+/// over the whole song. While developing, the tracks come live from a Rocket
+/// editor; otherwise they are played back from `sync.ron` in `ASSETS`, which the
+/// editor's save command writes.
+pub struct Rocket {
+    tracks: Vec<Track>,
+    row: f64,
+    last_row: Option<u32>,
+    row_started: Duration,
+    row_length: Duration,
+    #[cfg(not(target_arch = "wasm32"))]
+    editor: Option<editor::Editor>,
+}
+
+impl Rocket {
+    /// Plays back the tracks embedded in `ASSETS`, if there are any.
+    pub fn load() -> anyhow::Result<Self> {
+        match ASSETS.get_file(TRACKS_FILE).and_then(|file| file.contents_utf8()) {
+            Some(source) => Self::from_source(TRACKS_FILE, source),
+            None => Ok(Self::from_tracks(vec![])),
+        }
+    }
+
+    pub fn from_source(name: &str, source: &str) -> anyhow::Result<Self> {
+        let mut tracks: Vec<Track> = ron::from_str(source)
+            .map_err(|e| anyhow::anyhow!("{}:{}", name, e))?;
+        for track in tracks.iter_mut() {
+            track.keys.sort_by_key(|key| key.row);
+        }
+        Ok(Self::from_tracks(tracks))
+    }
+
+    fn from_tracks(tracks: Vec<Track>) -> Self {
+        Rocket {
+            tracks,
+            row: 0.0,
+            last_row: None,
+            row_started: Duration::ZERO,
+            row_length: Duration::ZERO,
+            #[cfg(not(target_arch = "wasm32"))]
+            editor: None,
+        }
+    }
+
+    /// Connects to an editor at `address`. Tracks start out empty and are filled in
+    /// by the editor as they are first asked for.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect(address: &str) -> std::io::Result<Self> {
+        let mut rocket = Self::from_tracks(vec![]);
+        rocket.editor = Some(editor::Editor::connect(address)?);
+        log::info!("connected to the sync editor at {}", address);
+        Ok(rocket)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_connected(&self) -> bool {
+        self.editor.is_some()
+    }
+
+    /// Moves to `row` and handles what the editor sent since the last call.
+    /// `now` is the demo's clock; rows only come from the music in whole steps, so
+    /// it's used to carry on smoothly between them.
+    pub fn update(&mut self, row: u32, now: Duration) -> Vec<EditorEvent> {
+        if self.last_row != Some(row) {
+            if self.last_row.map(|last| last + 1) == Some(row) {
+                self.row_length = now.saturating_sub(self.row_started);
+            }
+            self.row_started = now;
+            self.last_row = Some(row);
+            #[cfg(not(target_arch = "wasm32"))]
+            self.send_row(row);
+        }
+        let fraction = if self.row_length.is_zero() {
+            0.0
+        } else {
+            (now.saturating_sub(self.row_started).as_secs_f64() / self.row_length.as_secs_f64()).min(1.0)
+        };
+        self.row = row as f64 + fraction;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.poll_editor();
+        #[cfg(target_arch = "wasm32")]
+        return vec![];
+    }
+
+    /// The current value of the track `name`, or None if it has no keys, in which
+    /// case the demo falls back to its built-in animation.
+    pub fn value(&mut self, name: &str) -> Option<f32> {
+        let index = self.track_index(name);
+        self.tracks[index].value(self.row)
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn track_index(&mut self, name: &str) -> usize {
+        if let Some(index) = self.tracks.iter().position(|track| track.name == name) {
+            return index;
+        }
+        self.tracks.push(Track {
+            name: name.to_owned(),
+            keys: vec![],
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(editor) = &mut self.editor {
+            if let Err(e) = editor.get_track(name) {
+                log::warn!("lost the sync editor: {}", e);
+                self.editor = None;
+            }
+        }
+        self.tracks.len() - 1
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn send_row(&mut self, row: u32) {
+        if let Some(editor) = &mut self.editor {
+            if let Err(e) = editor.set_row(row) {
+                log::warn!("lost the sync editor: {}", e);
+                self.editor = None;
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll_editor(&mut self) -> Vec<EditorEvent> {
+        use editor::Message;
+
+        let mut events = vec![];
+        let mut save = false;
+        let Some(editor) = &mut self.editor else {
+            return events;
+        };
+        loop {
+            match editor.receive() {
+                Some(Message::SetKey(track, key)) => {
+                    if let Some(track) = self.tracks.get_mut(track as usize) {
+                        track.set_key(key);
+                    }
+                }
+                Some(Message::DeleteKey(track, row)) => {
+                    if let Some(track) = self.tracks.get_mut(track as usize) {
+                        track.delete_key(row);
+                    }
+                }
+                Some(Message::SetRow(row)) => {
+                    // Don't echo the row back once the demo gets there
+                    self.last_row = Some(row);
+                    events.push(EditorEvent::Seek(row));
+                }
+                Some(Message::Pause(paused)) => events.push(EditorEvent::Pause(paused)),
+                Some(Message::SaveTracks) => save = true,
+                Some(Message::Disconnected) => {
+                    log::warn!("the sync editor disconnected");
+                    self.editor = None;
+                    break;
+                }
+                None => break,
+            }
+        }
+        if save {
+            if let Err(e) = self.save() {
+                log::error!("saving the sync tracks failed: {:#}", e);
+            }
+        }
+        events
+    }
+
+    // Writes the tracks to the source tree, to be embedded in the next build
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save(&self) -> anyhow::Result<()> {
+        let path = crate::hotreload::source_dir().join("assets").join(TRACKS_FILE);
+        let source = ron::ser::to_string_pretty(&self.tracks, ron::ser::PrettyConfig::default())?;
+        std::fs::write(&path, source)?;
+        log::info!("saved the sync tracks to {}", path.display());
+        Ok(())
+    }
+}
+
+// The TCP protocol spoken by GNU Rocket editors. Everything is big-endian.
+#[cfg(not(target_arch = "wasm32"))]
+mod editor {
+    use std::{
+        io::{self, Read, Write},
+        net::{Shutdown, TcpStream, ToSocketAddrs},
+        sync::mpsc,
+        thread,
+    };
+
+    use web_time::Duration;
+
+    use super::{Interpolation, Key};
+
+    const CLIENT_GREETING: &[u8] = b"hello, synctracker!";
+    const SERVER_GREETING: &[u8] = b"hello, demo!";
+
+    const SET_KEY: u8 = 0;
+    const DELETE_KEY: u8 = 1;
+    const GET_TRACK: u8 = 2;
+    const SET_ROW: u8 = 3;
+    const PAUSE: u8 = 4;
+    const SAVE_TRACKS: u8 = 5;
+
+    pub enum Message {
+        SetKey(u32, Key),
+        DeleteKey(u32, u32),
+        SetRow(u32),
+        Pause(bool),
+        SaveTracks,
+        Disconnected,
+    }
+
+    pub struct Editor {
+        stream: TcpStream,
+        messages: mpsc::Receiver<Message>,
+    }
+
+    impl Editor {
+        pub fn connect(address: &str) -> io::Result<Self> {
+            let address = address.to_socket_addrs()?.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+            let mut stream = TcpStream::connect_timeout(&address, Duration::from_millis(500))?;
+            stream.set_nodelay(true)?;
+            stream.write_all(CLIENT_GREETING)?;
+            let mut greeting = [0; SERVER_GREETING.len()];
+            stream.read_exact(&mut greeting)?;
+            if greeting != SERVER_GREETING {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a sync editor"));
+            }
+
+            // Reads block, so they happen on their own thread
+            let (sender, messages) = mpsc::channel();
+            let mut reader = stream.try_clone()?;
+            thread::spawn(move || loop {
+                let message = read_message(&mut reader).unwrap_or(Message::Disconnected);
+                let disconnected = matches!(message, Message::Disconnected);
+                if sender.send(message).is_err() || disconnected {
+                    break;
+                }
+            });
+            Ok(Editor { stream, messages })
+        }
+
+        pub fn get_track(&mut self, name: &str) -> io::Result<()> {
+            let mut message = vec![GET_TRACK];
+            message.extend_from_slice(&(name.len() as u32).to_be_bytes());
+            message.extend_from_slice(name.as_bytes());
+            self.stream.write_all(&message)
+        }
+
+        pub fn set_row(&mut self, row: u32) -> io::Result<()> {
+            let mut message = vec![SET_ROW];
+            message.extend_from_slice(&row.to_be_bytes());
+            self.stream.write_all(&message)
+        }
+
+        pub fn receive(&mut self) -> Option<Message> {
+            match self.messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => Some(Message::Disconnected),
+            }
+        }
+    }
+
+    impl Drop for Editor {
+        fn drop(&mut self) {
+            // Wakes up the reader thread so it can finish
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn read_message(stream: &mut TcpStream) -> io::Result<Message> {
+        Ok(match read_u8(stream)? {
+            SET_KEY => {
+                let track = read_u32(stream)?;
+                let row = read_u32(stream)?;
+                let value = f32::from_bits(read_u32(stream)?);
+                let interpolation = match read_u8(stream)? {
+                    0 => Interpolation::Step,
+                    1 => Interpolation::Linear,
+                    2 => Interpolation::Smooth,
+                    _ => Interpolation::Ramp,
+                };
+                Message::SetKey(track, Key { row, value, interpolation })
+            }
+            DELETE_KEY => Message::DeleteKey(read_u32(stream)?, read_u32(stream)?),
+            SET_ROW => Message::SetRow(read_u32(stream)?),
+            PAUSE => Message::Pause(read_u8(stream)? != 0),
+            SAVE_TRACKS => Message::SaveTracks,
+            command => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown sync command {}", command),
+            )),
+        })
+    }
+
+    fn read_u8(stream: &mut TcpStream) -> io::Result<u8> {
+        let mut bytes = [0; 1];
+        stream.read_exact(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    fn read_u32(stream: &mut TcpStream) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        stream.read_exact(&mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+}
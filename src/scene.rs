@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+
+use rand::rngs::SmallRng;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, ComputePipeline, Extent3d, RenderPass, RenderPipeline, TextureFormat, TextureView};
+
+use crate::{
+    config::{Config, PressureSolver},
+    demo::Camera,
+    music::TrackerEvent,
+    rocket::Rocket,
+    texture::Texture,
+    timeline,
+};
+
+mod black;
+mod cds;
+mod ocean;
+mod pressure;
+mod slide;
+mod smoke;
+mod starwars;
+
+pub use pressure::{pressure_levels, Multigrid, PressurePipelines, COARSEST_SMOOTHING, POST_SMOOTHING, PRE_SMOOTHING};
+pub use smoke::{fit_fluid_size, BeamShape, Choreography, ComputeParamsUniform, Laser, CONE_SPREAD, DEFAULT_INTENSITY, MIN_FLUID_SIZE};
+
+/// One kind of effect. The timeline's cues pick a scene and give it a number,
+/// which each scene reads its own way: a slide index, a laser count...
+///
+/// A scene draws its image in `render`, and the demo then composites `output`
+/// with the previous scene's last frame in the final pass.
+pub trait Scene {
+    /// Advances the animation. Only called while the scene is on screen.
+    fn update(
+        &mut self,
+        _number: i32,
+        _frame: &mut Frame,
+        _shared: &Shared,
+        _queue: &wgpu::Queue,
+        _encoder: &mut CommandEncoder,
+    ) {
+    }
+
+    /// Encodes the passes that draw the scene into `output`.
+    fn render(&self, number: i32, shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder);
+
+    /// The image the final pass shows, once `render` has drawn it.
+    fn output<'a>(&'a self, _number: i32, _shared: &'a Shared, targets: &'a RenderTargets) -> &'a BindGroup {
+        &targets.texture_pass1_bindgroup
+    }
+
+    /// An image that can stand in for the scene's last frame when there is none,
+    /// like after seeking. Black if `None`.
+    fn still<'a>(&self, _number: i32, _shared: &'a Shared) -> Option<&'a BindGroup> {
+        None
+    }
+
+    /// The final pass's `x` parameter, for scenes that animate their entrance
+    /// with it. `since_transition` is in seconds.
+    fn final_x(&self, _number: i32, _since_transition: f32) -> f32 {
+        0.0
+    }
+
+    /// The lasers the scene showed as of the last `update`, for mirroring onto
+    /// stage lighting.
+    fn lasers(&self) -> &[Laser] {
+        &[]
+    }
+
+    /// Recreates whatever has the size of the smoke grid.
+    fn resize_fluid(&mut self, _device: &wgpu::Device, _layouts: &Layouts, _size: (u32, u32, u32)) {
+    }
+
+    /// Switches how the smoke solves for its pressure, making what that takes.
+    fn set_pressure_solver(&mut self, _device: &wgpu::Device, _layouts: &Layouts, _solver: PressureSolver) {
+    }
+
+    /// How far the smoke's pressure was from cancelling its divergence lately,
+    /// relative to the divergence, for scenes that simulate smoke.
+    fn pressure_residual(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// What a scene gets to build itself.
+pub struct Init<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub config: &'a Config,
+    pub shared: &'a Shared,
+    /// The format of every render target
+    pub format: TextureFormat,
+}
+
+/// The state of the demo for one frame of `Scene::update`.
+pub struct Frame<'a> {
+    /// Seconds since the start, and since the last frame
+    pub time: f64,
+    pub delta_time: f64,
+    /// Where the music is
+    pub pattern: usize,
+    pub row: usize,
+    /// What the tracker played since the last frame
+    pub events: &'a [TrackerEvent],
+    pub rocket: &'a mut Rocket,
+    pub camera: &'a mut Camera,
+    pub rng: &'a mut SmallRng,
+    /// Laser colours forced by the remote, by laser index
+    pub laser_colors: &'a [Option<[f32; 4]>],
+    /// Whether the smoke should measure its pressure residual, which the demo
+    /// asks for once between log lines
+    pub measure_pressure: bool,
+}
+
+/// GPU objects that the demo owns and every scene may use.
+pub struct Shared {
+    pub layouts: Layouts,
+    /// Pipelines by the file name of their shader
+    pub pipelines: HashMap<&'static str, RenderPipeline>,
+    pub compute_pipelines: HashMap<&'static str, ComputePipeline>,
+    /// The multigrid pressure solve's, from smoke_pressure.wgsl
+    pub pressure_pipelines: PressurePipelines,
+    pub full_quad_vertex_buffer: Buffer,
+    pub full_quad_index_buffer: Buffer,
+    /// Instance 0 covers the whole target, 1 and 2 are the windows on the left
+    /// and right of a slide
+    pub instance_buffer: Buffer,
+    pub bg_uniform_bind_group: BindGroup,
+    pub object_uniform_bind_group: BindGroup,
+    pub final_function_bindgroup: BindGroup,
+    pub slide_textures: Vec<Texture>,
+    pub slide_texture_bindgroups: Vec<BindGroup>,
+}
+
+impl Shared {
+    pub fn pipeline(&self, file_name: &str) -> &RenderPipeline {
+        &self.pipelines[file_name]
+    }
+
+    /// Binds the quad and its instances, to be drawn with `draw_indexed(0..6, 0, instance)`.
+    pub fn set_quad<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.full_quad_vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(
+            self.full_quad_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+    }
+}
+
+/// Starts a pass that clears `view` to black, and `depth` too if given.
+pub fn clear_pass<'a>(
+    encoder: &'a mut CommandEncoder,
+    label: &str,
+    view: &'a TextureView,
+    depth: Option<&'a TextureView>,
+) -> RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: depth.map(|view| wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: wgpu::StoreOp::Store,
+            }),
+            stencil_ops: None,
+        }),
+        ..Default::default()
+    })
+}
+
+// The @workgroup_size of every compute entry point that runs over the smoke
+// grid, in smoke_compute.wgsl and smoke_pressure.wgsl
+const WORKGROUP_SIZE: (u32, u32, u32) = (8, 8, 4);
+
+/// How many workgroups of WORKGROUP_SIZE it takes to cover a grid of `size`.
+pub fn compute_work_group_count((width, height, depth): (u32, u32, u32)) -> (u32, u32, u32) {
+    (
+        width.div_ceil(WORKGROUP_SIZE.0),
+        height.div_ceil(WORKGROUP_SIZE.1),
+        depth.div_ceil(WORKGROUP_SIZE.2),
+    )
+}
+
+/// An instance of every scene, for the timeline's cues to pick from.
+pub struct Scenes(Vec<Box<dyn Scene>>);
+
+impl Scenes {
+    pub async fn new(init: &Init<'_>, rng: &mut SmallRng) -> Self {
+        // In the order of `index`
+        Scenes(vec![
+            Box::new(slide::Slide),
+            Box::new(black::Black),
+            Box::new(cds::Cds::new(init, rng)),
+            Box::new(starwars::StarWars::new(init, rng).await),
+            Box::new(ocean::Ocean::new(init)),
+            Box::new(smoke::Smoke::new(init)),
+        ])
+    }
+
+    fn index(cue: timeline::Scene) -> (usize, i32) {
+        match cue {
+            timeline::Scene::Slide(number) => (0, number),
+            timeline::Scene::Black => (1, 0),
+            timeline::Scene::CDs(number) => (2, number),
+            timeline::Scene::StarWars(number) => (3, number),
+            timeline::Scene::Ocean(number) => (4, number),
+            timeline::Scene::Smoke(number) => (5, number),
+        }
+    }
+
+    /// The scene for `cue`, and the number to give it.
+    pub fn get(&self, cue: timeline::Scene) -> (&dyn Scene, i32) {
+        let (index, number) = Self::index(cue);
+        (self.0[index].as_ref(), number)
+    }
+
+    pub fn get_mut(&mut self, cue: timeline::Scene) -> (&mut dyn Scene, i32) {
+        let (index, number) = Self::index(cue);
+        (self.0[index].as_mut(), number)
+    }
+
+    pub fn resize_fluid(&mut self, device: &wgpu::Device, layouts: &Layouts, size: (u32, u32, u32)) {
+        for scene in &mut self.0 {
+            scene.resize_fluid(device, layouts, size);
+        }
+    }
+
+    pub fn set_pressure_solver(&mut self, device: &wgpu::Device, layouts: &Layouts, solver: PressureSolver) {
+        for scene in &mut self.0 {
+            scene.set_pressure_solver(device, layouts, solver);
+        }
+    }
+}
+
+/// Bind group layouts, shared by the pipelines and the bind groups made for them.
+pub struct Layouts {
+    /// A 2D texture and its sampler
+    pub texture: BindGroupLayout,
+    /// A camera, a `ShaderParamsUniform` and the `AudioUniform` at binding 2
+    pub uniform: BindGroupLayout,
+    pub smoke_texture: BindGroupLayout,
+    pub smoke_params: BindGroupLayout,
+    /// Two pressure textures to read and two to write, and the sums the
+    /// residual is worked out from
+    pub pressure: BindGroupLayout,
+    pub smoke_render: BindGroupLayout,
+    pub lasers: BindGroupLayout,
+}
+
+impl Layouts {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniform_buffer = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let smoke_texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D3,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let smoke_storage = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D3,
+            },
+            count: None,
+        };
+
+        Layouts {
+            texture: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            }),
+            uniform: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_buffer(0, wgpu::ShaderStages::VERTEX),
+                    uniform_buffer(1, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                    uniform_buffer(2, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                ],
+                label: Some("uniform_bind_group_layout"),
+            }),
+            smoke_texture: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    smoke_texture(0),
+                    smoke_texture(1),
+                    smoke_storage(2, wgpu::TextureFormat::Rgba32Float),
+                    smoke_storage(3, wgpu::TextureFormat::R32Float),
+                    smoke_storage(4, wgpu::TextureFormat::Rgba32Uint),
+                ],
+                label: Some("smoke_texture_bind_group_layout"),
+            }),
+            smoke_params: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[uniform_buffer(0, wgpu::ShaderStages::COMPUTE)],
+                label: Some("Smoke params bind group layout"),
+            }),
+            pressure: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    smoke_texture(0),
+                    smoke_texture(1),
+                    smoke_storage(2, wgpu::TextureFormat::R32Float),
+                    smoke_storage(3, wgpu::TextureFormat::R32Float),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Pressure bind group layout"),
+            }),
+            smoke_render: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                    },
+                    count: None,
+                }],
+                label: Some("Smoke render bind group layout"),
+            }),
+            lasers: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Lasers bind group layout"),
+            }),
+        }
+    }
+}
+
+/// Every render target that has the internal resolution. These are recreated
+/// whenever it changes.
+pub struct RenderTargets {
+    pub size: (u32, u32),
+    pub texture_pass1: Texture,
+    pub texture_pass1_bindgroup: BindGroup,
+    pub texture_pass2: Texture,
+    pub texture_pass2_bindgroup: BindGroup,
+    pub texture_pass_window: Texture,
+    pub texture_pass_window_bindgroup: BindGroup,
+    pub previous_pass_texture: Texture,
+    pub previous_pass_texture_bind_group: BindGroup,
+    /// The finished frame, before it is scaled to the window
+    pub output: Texture,
+    pub output_bindgroup: BindGroup,
+    pub depth_texture: Texture,
+}
+
+impl RenderTargets {
+    pub fn new(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        size: (u32, u32),
+    ) -> Self {
+        let create = |label: &str| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                size: Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                label: Some(label),
+                view_formats: &[]
+            });
+            let texture = Texture::from_texture(device, texture, wgpu::FilterMode::Linear);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: texture_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+                label: Some(label),
+            });
+            (texture, bind_group)
+        };
+        let (texture_pass1, texture_pass1_bindgroup) = create("Pass 1");
+        let (texture_pass2, texture_pass2_bindgroup) = create("Pass 2");
+        let (texture_pass_window, texture_pass_window_bindgroup) = create("Pass window");
+        let (previous_pass_texture, previous_pass_texture_bind_group) = create("Previous pass");
+        let (output, output_bindgroup) = create("Output");
+        RenderTargets {
+            size,
+            texture_pass1,
+            texture_pass1_bindgroup,
+            texture_pass2,
+            texture_pass2_bindgroup,
+            texture_pass_window,
+            texture_pass_window_bindgroup,
+            previous_pass_texture,
+            previous_pass_texture_bind_group,
+            output,
+            output_bindgroup,
+            depth_texture: Texture::create_depth_texture(device, size, "depth_texture"),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use wgpu::CommandEncoder;
+
+use super::{clear_pass, RenderTargets, Scene, Shared};
+
+pub struct Black;
+
+impl Scene for Black {
+    fn render(&self, _number: i32, _shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder) {
+        clear_pass(encoder, "Clear pass 1", &targets.texture_pass1.view, None);
+    }
+}
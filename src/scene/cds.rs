@@ -0,0 +1,91 @@
+use cgmath::{Quaternion, Rotation3, Vector3};
+use rand::{rngs::SmallRng, Rng};
+use wgpu::{util::DeviceExt, Buffer, CommandEncoder};
+
+use super::{clear_pass, Frame, Init, RenderTargets, Scene, Shared};
+use crate::Instance;
+
+const NUM_CDS: usize = 300;
+
+/// CDs flying at the camera in a window on the slide `number`, with one big one
+/// wobbling in front.
+pub struct Cds {
+    instances: Vec<Instance>,
+    instance_buffer: Buffer,
+}
+
+impl Cds {
+    pub fn new(init: &Init, rng: &mut SmallRng) -> Self {
+        let mut instances = vec![];
+        for i in 0..NUM_CDS+1 {
+            instances.push(Instance {
+                position: cgmath::Vector3::new(rng.gen_range(-30.0..30.0), rng.gen_range(-16.0..16.0), rng.gen_range(-25.0..0.0)),
+                rotation: cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(i as f32*3.0)),
+                scale: cgmath::Vector3::new(1.0,1.0,1.0),
+                tex_offset: cgmath::Vector2::new(0.0, 0.0)
+            });
+        }
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_buffer = init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("CD instances Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Cds {
+            instances,
+            instance_buffer,
+        }
+    }
+}
+
+impl Scene for Cds {
+    fn update(&mut self, _number: i32, frame: &mut Frame, _shared: &Shared, queue: &wgpu::Queue, _encoder: &mut CommandEncoder) {
+        let time = frame.time as f32;
+        let speed = frame.rocket.value("cds:speed").unwrap_or(10.);
+        for i in 0..NUM_CDS {
+            self.instances[i].position.z += speed*frame.delta_time as f32;
+            if self.instances[i].position.z >= 0. {
+                self.instances[i].position.z = frame.rng.gen_range(-40.0..-20.0);
+            }
+            self.instances[i].rotation = 
+                Quaternion::from_angle_x(cgmath::Rad(i as f32+time))
+                *Quaternion::from_angle_y(cgmath::Rad(i as f32-time));
+        }
+        self.instances[NUM_CDS].position = Vector3::new(0.0,0.0,7.5);
+        self.instances[NUM_CDS].rotation = 
+            Quaternion::from_angle_x(cgmath::Rad(1.*time.cos()))
+            *Quaternion::from_angle_y(cgmath::Rad(1.*time.sin()));
+            
+        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    }
+
+    fn render(&self, number: i32, shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder) {
+        {
+            let mut render_pass = clear_pass(encoder, "Render Pass 1", &targets.texture_pass_window.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_cdrender.wgsl"));
+            shared.set_quad(&mut render_pass);
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_bind_group(0, &shared.object_uniform_bind_group, &[]);
+            render_pass.draw_indexed(0..6, 0, 0..NUM_CDS as u32+1);
+        }
+        {
+            let mut render_pass = clear_pass(encoder, "Render Pass 2", &targets.texture_pass1.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_simple.wgsl"));
+            shared.set_quad(&mut render_pass);
+            // render background (slide)
+            render_pass.set_bind_group(1, &shared.final_function_bindgroup, &[]);
+            render_pass.set_bind_group(0, &shared.slide_texture_bindgroups[number as usize], &[]);
+            render_pass.set_bind_group(2, &shared.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+            // render window (CDs)
+            render_pass.set_bind_group(0, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.set_bind_group(2, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.draw_indexed(0..6, 0, 1..2);
+        }
+    }
+
+    fn still<'a>(&self, number: i32, shared: &'a Shared) -> Option<&'a wgpu::BindGroup> {
+        Some(&shared.slide_texture_bindgroups[number as usize])
+    }
+}
@@ -0,0 +1,72 @@
+use wgpu::{BindGroup, CommandEncoder};
+
+use super::{clear_pass, Init, RenderTargets, Scene, Shared};
+use crate::{resources::ASSETS, texture::Texture};
+
+/// The sea, in a window on the slide `number`.
+pub struct Ocean {
+    _texture: Texture,
+    texture_bindgroup: BindGroup,
+}
+
+impl Ocean {
+    pub fn new(init: &Init) -> Self {
+        let texture = Texture::from_bytes(
+            init.device,
+            init.queue,
+            ASSETS.get_file("environment.jpg").unwrap().contents(),
+            "Ocean texture",
+            init.format,
+            (wgpu::AddressMode::Repeat, wgpu::AddressMode::MirrorRepeat)).unwrap();
+        let texture_bindgroup = init.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &init.shared.layouts.texture,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: None,
+        });
+        Ocean {
+            _texture: texture,
+            texture_bindgroup,
+        }
+    }
+}
+
+impl Scene for Ocean {
+    fn render(&self, number: i32, shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder) {
+        {
+            let mut render_pass = clear_pass(encoder, "Render Pass 1", &targets.texture_pass_window.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_ocean.wgsl"));
+            shared.set_quad(&mut render_pass);
+            render_pass.set_bind_group(1, &shared.final_function_bindgroup, &[]);
+            render_pass.set_bind_group(0, &self.texture_bindgroup, &[]);
+            render_pass.set_bind_group(2, &self.texture_bindgroup, &[]);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+        }
+        {
+            let mut render_pass = clear_pass(encoder, "Render Pass 2", &targets.texture_pass1.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_simple.wgsl"));
+            shared.set_quad(&mut render_pass);
+            // render background (slide)
+            render_pass.set_bind_group(1, &shared.final_function_bindgroup, &[]);
+            render_pass.set_bind_group(0, &shared.slide_texture_bindgroups[number as usize], &[]);
+            render_pass.set_bind_group(2, &shared.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+            // render window (ocean)
+            render_pass.set_bind_group(0, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.set_bind_group(2, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.draw_indexed(0..6, 0, 1..2);
+        }
+    }
+
+    fn still<'a>(&self, number: i32, shared: &'a Shared) -> Option<&'a BindGroup> {
+        Some(&shared.slide_texture_bindgroups[number as usize])
+    }
+}
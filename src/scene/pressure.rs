@@ -0,0 +1,349 @@
+use std::sync::mpsc;
+
+use wgpu::{util::DeviceExt, BindGroup, Buffer, CommandEncoder, ComputePass, ComputePipeline, TextureView};
+
+use super::{compute_work_group_count, Layouts};
+
+// Smoothing sweeps ping-pong between a level's two pressure textures, and so
+// does adding the coarse correction. With an even number before and an odd one
+// after, every level ends a V-cycle back in its first texture.
+pub const PRE_SMOOTHING: u32 = 2;
+pub const POST_SMOOTHING: u32 = 1;
+/// Sweeps on the coarsest level instead of solving it exactly. Even, as above.
+pub const COARSEST_SMOOTHING: u32 = 16;
+// Levels get coarser until one is smaller than this in some direction
+const MIN_COARSE_SIZE: u32 = 4;
+
+/// The sizes of the multigrid levels for a grid of `size`, finest first. The
+/// second level covers the grid inside its walls at half the resolution, and
+/// each one after that halves the last, rounding up.
+pub fn pressure_levels(size: (u32, u32, u32)) -> Vec<(u32, u32, u32)> {
+    let mut levels = vec![size];
+    loop {
+        let (x, y, z) = match levels[..] {
+            [(x, y, z)] => (x - 2, y - 2, z - 2),
+            [.., last] => last,
+            [] => unreachable!(),
+        };
+        let coarser = (x.div_ceil(2), y.div_ceil(2), z.div_ceil(2));
+        if coarser.0.min(coarser.1).min(coarser.2) < MIN_COARSE_SIZE {
+            return levels;
+        }
+        levels.push(coarser);
+    }
+}
+
+// Has to match residual_main's @workgroup_size in every direction. The other
+// entry points use WORKGROUP_SIZE.
+const RESIDUAL_WORKGROUP_SIZE: u32 = 4;
+
+/// The compute pipelines for the entry points of smoke_pressure.wgsl.
+pub struct PressurePipelines {
+    divergence: ComputePipeline,
+    smooth: ComputePipeline,
+    restrict: ComputePipeline,
+    prolong: ComputePipeline,
+    output: ComputePipeline,
+    residual: ComputePipeline,
+}
+
+impl PressurePipelines {
+    /// `layout` takes a `Layouts::pressure` bind group and a
+    /// `Layouts::smoke_params` one.
+    pub fn new(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule) -> Self {
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(layout),
+            module: shader,
+            entry_point,
+        });
+        PressurePipelines {
+            divergence: pipeline("divergence_main"),
+            smooth: pipeline("smooth_main"),
+            restrict: pipeline("restrict_main"),
+            prolong: pipeline("prolong_main"),
+            output: pipeline("output_main"),
+            residual: pipeline("residual_main"),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PressureParamsUniform {
+    level: u32,
+    spacing_squared: f32,
+    _padding: [u32; 2],
+}
+
+// The bind groups that work on one level. Every level ends a V-cycle with its
+// pressure in the first of its two textures.
+struct Level {
+    size: (u32, u32, u32),
+    params: BindGroup,
+    // A smoothing sweep from each pressure texture into the other
+    smooth: [BindGroup; 2],
+    // To the next coarser level and back, which the coarsest doesn't have
+    restrict: Option<BindGroup>,
+    prolong: Option<BindGroup>,
+}
+
+enum Readback {
+    Idle,
+    // The sums are being copied, in a command buffer that isn't submitted yet
+    Copied,
+    Mapping(mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>),
+}
+
+/// A multigrid solve for a smoke grid's pressure: the levels' textures, and the
+/// bind groups that run it between the grid's velocity and pressure textures.
+pub struct Multigrid {
+    levels: Vec<Level>,
+    // From the grid to the finest level and back
+    divergence: BindGroup,
+    output: BindGroup,
+    residual: BindGroup,
+    partial_sums: Buffer,
+    readback_buffer: Buffer,
+    readback: Readback,
+    last_residual: Option<f32>,
+}
+
+impl Multigrid {
+    /// Solves into `pressure` for a grid of `size` whose velocity is in
+    /// `velocity`, taking what's in `pressure` as the first guess.
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        size: (u32, u32, u32),
+        velocity: &TextureView,
+        pressure: &TextureView,
+    ) -> Self {
+        let create_texture = |label, (width, height, depth)| device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: depth,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let sizes = pressure_levels(size);
+        // Each level's two pressure textures and its right hand side
+        let textures: Vec<[TextureView; 3]> = sizes.iter()
+            .map(|&size| [
+                create_texture("Pressure level texture 1", size),
+                create_texture("Pressure level texture 2", size),
+                create_texture("Pressure level right hand side", size),
+            ])
+            .collect();
+        // Entry points that write one texture write the other to this
+        let unused = create_texture("Pressure unused output", (1, 1, 1));
+
+        let residual_workgroups = |n: u32| n.div_ceil(RESIDUAL_WORKGROUP_SIZE) as u64;
+        let partial_sums_size = residual_workgroups(size.0)*residual_workgroups(size.1)*residual_workgroups(size.2)
+            *std::mem::size_of::<[f32; 2]>() as u64;
+        let partial_sums = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pressure residual sums buffer"),
+            size: partial_sums_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pressure residual readback buffer"),
+            size: partial_sums_size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = |label, inputs: [&TextureView; 2], outputs: [&TextureView; 2]| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: &layouts.pressure,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(inputs[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(inputs[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(outputs[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(outputs[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: partial_sums.as_entire_binding(),
+                },
+            ],
+        });
+        let levels = sizes.iter().enumerate()
+            .map(|(level, &size)| {
+                let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Pressure params buffer"),
+                    contents: bytemuck::cast_slice(&[PressureParamsUniform {
+                        level: level as u32,
+                        spacing_squared: (1 << (2*level)) as f32,
+                        _padding: [0; 2],
+                    }]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let [pressure1, pressure2, rhs] = &textures[level];
+                let coarser = textures.get(level + 1);
+                Level {
+                    size,
+                    params: device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("Pressure params bind group"),
+                        layout: &layouts.smoke_params,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: params.as_entire_binding(),
+                        }],
+                    }),
+                    smooth: [
+                        bind_group("Pressure smooth bind group 1", [pressure1, rhs], [pressure2, &unused]),
+                        bind_group("Pressure smooth bind group 2", [pressure2, rhs], [pressure1, &unused]),
+                    ],
+                    restrict: coarser.map(|[coarse_pressure, _, coarse_rhs]| {
+                        bind_group("Pressure restrict bind group", [pressure1, rhs], [coarse_rhs, coarse_pressure])
+                    }),
+                    prolong: coarser.map(|[coarse_pressure, _, _]| {
+                        bind_group("Pressure prolong bind group", [pressure1, coarse_pressure], [pressure2, &unused])
+                    }),
+                }
+            })
+            .collect();
+
+        let [finest1, finest2, finest_rhs] = &textures[0];
+        Multigrid {
+            levels,
+            divergence: bind_group("Pressure divergence bind group", [velocity, pressure], [finest_rhs, finest1]),
+            output: bind_group("Pressure output bind group", [finest1, finest_rhs], [pressure, &unused]),
+            residual: bind_group("Pressure residual bind group", [finest1, finest_rhs], [finest2, &unused]),
+            partial_sums,
+            readback_buffer,
+            readback: Readback::Idle,
+            last_residual: None,
+        }
+    }
+
+    /// Improves on the grid's pressure with `cycles` V-cycles and writes it
+    /// back. The walls come back with zero pressure.
+    pub fn solve<'a>(&'a self, compute_pass: &mut ComputePass<'a>, pipelines: &'a PressurePipelines, cycles: u32) {
+        let finest = &self.levels[0];
+        dispatch(compute_pass, &pipelines.divergence, &self.divergence, &finest.params, finest.size);
+        for _ in 0..cycles {
+            self.v_cycle(compute_pass, pipelines, 0);
+        }
+        dispatch(compute_pass, &pipelines.output, &self.output, &finest.params, finest.size);
+    }
+
+    /// Measures how far the grid's pressure is from cancelling the divergence,
+    /// whichever way it was solved, for `copy_residual` to read back.
+    pub fn measure<'a>(&'a self, compute_pass: &mut ComputePass<'a>, pipelines: &'a PressurePipelines) {
+        let finest = &self.levels[0];
+        dispatch(compute_pass, &pipelines.divergence, &self.divergence, &finest.params, finest.size);
+        compute_pass.set_pipeline(&pipelines.residual);
+        compute_pass.set_bind_group(0, &self.residual, &[]);
+        compute_pass.set_bind_group(1, &finest.params, &[]);
+        let (width, height, depth) = finest.size;
+        compute_pass.dispatch_workgroups(
+            width.div_ceil(RESIDUAL_WORKGROUP_SIZE),
+            height.div_ceil(RESIDUAL_WORKGROUP_SIZE),
+            depth.div_ceil(RESIDUAL_WORKGROUP_SIZE),
+        );
+    }
+
+    fn v_cycle<'a>(&'a self, compute_pass: &mut ComputePass<'a>, pipelines: &'a PressurePipelines, index: usize) {
+        let level = &self.levels[index];
+        let smooth = |compute_pass: &mut ComputePass<'a>, sweeps: u32, first: usize| {
+            for i in 0..sweeps as usize {
+                dispatch(compute_pass, &pipelines.smooth, &level.smooth[(first + i)%2], &level.params, level.size);
+            }
+        };
+        let (Some(restrict), Some(prolong)) = (&level.restrict, &level.prolong) else {
+            smooth(compute_pass, COARSEST_SMOOTHING, 0);
+            return;
+        };
+        smooth(compute_pass, PRE_SMOOTHING, 0);
+        dispatch(compute_pass, &pipelines.restrict, restrict, &level.params, self.levels[index + 1].size);
+        self.v_cycle(compute_pass, pipelines, index + 1);
+        // Adding the correction moves the pressure into the second texture
+        dispatch(compute_pass, &pipelines.prolong, prolong, &level.params, level.size);
+        smooth(compute_pass, POST_SMOOTHING, 1);
+    }
+
+    /// Copies what the last `measure` summed into a buffer the CPU can read,
+    /// unless the last copy is still being read.
+    pub fn copy_residual(&mut self, encoder: &mut CommandEncoder) {
+        if let Readback::Idle = self.readback {
+            encoder.copy_buffer_to_buffer(&self.partial_sums, 0, &self.readback_buffer, 0, self.partial_sums.size());
+            self.readback = Readback::Copied;
+        }
+    }
+
+    /// Moves the readback along without waiting for the GPU, once the commands
+    /// `copy_residual` was given have been submitted. Reading a copy takes two
+    /// calls at least, the second after the device has been polled.
+    pub fn poll_residual(&mut self) {
+        match &self.readback {
+            Readback::Idle => {}
+            Readback::Copied => {
+                let (sender, receiver) = mpsc::channel();
+                self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                    let _ = sender.send(result);
+                });
+                self.readback = Readback::Mapping(receiver);
+            }
+            Readback::Mapping(receiver) => match receiver.try_recv() {
+                Ok(Ok(())) => {
+                    let (residual, rhs) = bytemuck::cast_slice::<u8, [f32; 2]>(&self.readback_buffer.slice(..).get_mapped_range())
+                        .iter()
+                        .fold((0.0, 0.0), |(residual, rhs), sums| (residual + sums[0] as f64, rhs + sums[1] as f64));
+                    self.readback_buffer.unmap();
+                    self.last_residual = Some((residual/rhs.max(f64::MIN_POSITIVE)).sqrt() as f32);
+                    self.readback = Readback::Idle;
+                }
+                Ok(Err(e)) => {
+                    log::warn!("couldn't read back the pressure residual: {}", e);
+                    self.readback = Readback::Idle;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => self.readback = Readback::Idle,
+            },
+        }
+    }
+
+    /// The last residual read back: the root of the summed squared residuals
+    /// over the summed squared divergence, as `FluidGrid::pressure_residual`
+    /// works it out.
+    pub fn residual(&self) -> Option<f32> {
+        self.last_residual
+    }
+}
+
+fn dispatch<'a>(
+    compute_pass: &mut ComputePass<'a>,
+    pipeline: &'a ComputePipeline,
+    bind_group: &'a BindGroup,
+    params: &'a BindGroup,
+    size: (u32, u32, u32),
+) {
+    compute_pass.set_pipeline(pipeline);
+    compute_pass.set_bind_group(0, bind_group, &[]);
+    compute_pass.set_bind_group(1, params, &[]);
+    let (x, y, z) = compute_work_group_count(size);
+    compute_pass.dispatch_workgroups(x, y, z);
+}
@@ -0,0 +1,27 @@
+use wgpu::{BindGroup, CommandEncoder};
+
+use super::{RenderTargets, Scene, Shared};
+
+/// Just a slide, `number` being its index.
+pub struct Slide;
+
+impl Scene for Slide {
+    fn render(&self, _number: i32, _shared: &Shared, _targets: &RenderTargets, _encoder: &mut CommandEncoder) {}
+
+    fn output<'a>(&'a self, number: i32, shared: &'a Shared, _targets: &'a RenderTargets) -> &'a BindGroup {
+        &shared.slide_texture_bindgroups[number as usize]
+    }
+
+    fn still<'a>(&self, number: i32, shared: &'a Shared) -> Option<&'a BindGroup> {
+        Some(&shared.slide_texture_bindgroups[number as usize])
+    }
+
+    // The first two slides have their own animation in the final pass
+    fn final_x(&self, number: i32, since_transition: f32) -> f32 {
+        match number {
+            0 => -(1.0/since_transition),
+            1 => (1.0/(since_transition+1.)-0.2).max(0.0),
+            _ => 0.0
+        }
+    }
+}
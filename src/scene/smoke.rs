@@ -0,0 +1,532 @@
+use std::f32::consts::PI;
+
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+use wgpu::{util::DeviceExt, BindGroup, Buffer, CommandEncoder};
+
+use super::{clear_pass, compute_work_group_count, pressure::Multigrid, Frame, Init, Layouts, RenderTargets, Scene, Shared};
+use crate::{config::PressureSolver, music::{EventKind, TrackerEvent}, rocket::Rocket, texture::Texture, timeline::MAX_LASERS};
+
+const COMPUTE_PASSES: i32 = 6;
+const COMPUTE_EXTRAS: i32 = 2;
+// The steps that take the pressure's gradient off the velocity
+const PROJECTIONS: [i32; 2] = [3, 5];
+/// The smallest the grid can be in any direction. The emitters shrink with the
+/// grid, but no further than a cell, and the hearts only fit from this size.
+pub const MIN_FLUID_SIZE: u32 = 16;
+// Seconds for a laser flash to fade to 1/e
+const FLASH_FADE: f32 = 0.15;
+/// How bright the lasers are without a `smoke:intensity` track.
+pub const DEFAULT_INTENSITY: f32 = 3.0;
+/// How fast a cone widens along its beam. The render shader has the same value.
+pub const CONE_SPREAD: f32 = 0.25;
+
+/// The largest grid no bigger than `size` in any direction that `limits` allow,
+/// shrunk evenly if it has to be.
+pub fn fit_fluid_size(size: (u32, u32, u32), limits: &wgpu::Limits) -> (u32, u32, u32) {
+    let largest = limits.max_texture_dimension_3d;
+    let (x, y, z) = (size.0.min(largest), size.1.min(largest), size.2.min(largest));
+    // The biggest of the textures has 16 bytes a cell, which has to fit in a
+    // buffer to be read back
+    let bytes = x as f64*y as f64*z as f64*16.0;
+    let scale = (limits.max_buffer_size as f64/bytes).cbrt().min(1.0);
+    let fit = |n: u32| ((n as f64*scale).floor() as u32).max(MIN_FLUID_SIZE);
+    (fit(x), fit(y), fit(z))
+}
+
+/// The simulation's 3D textures and the bind groups reading them, which all
+/// have the size of the grid.
+struct Grid {
+    size: (u32, u32, u32),
+    compute_bindgroup1: BindGroup,
+    compute_bindgroup2: BindGroup,
+    render_bind_group: BindGroup,
+    // The textures the projections read, which the multigrid works on
+    velocity: Texture,
+    pressure: Texture,
+    // Only made once something needs it, solving or measuring
+    multigrid: Option<Multigrid>,
+}
+
+impl Grid {
+    fn new(device: &wgpu::Device, layouts: &Layouts, size: (u32, u32, u32)) -> Self {
+        let create_texture = |label, format| Texture::from_texture(
+            device,
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: size.2,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format,
+                usage: wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            }),
+            wgpu::FilterMode::Linear,
+        );
+        let smoke_texture1 = create_texture("smoke texture 1", wgpu::TextureFormat::Rgba32Float);
+        let smoke_texture2 = create_texture("smoke texture 2", wgpu::TextureFormat::Rgba32Float);
+        let packed_smoke_texture = create_texture("packed smoke texture", wgpu::TextureFormat::Rgba32Uint);
+        let poisson_texture1 = create_texture("poisson texture 1", wgpu::TextureFormat::R32Float);
+        let poisson_texture2 = create_texture("poisson texture 2", wgpu::TextureFormat::R32Float);
+
+        // Each step reads one pair of textures and writes the other, so there's a
+        // bind group for each direction
+        let compute_bindgroup = |label, from: [&Texture; 2], to: [&Texture; 2]| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: &layouts.smoke_texture,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&from[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&from[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&to[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&to[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&packed_smoke_texture.view),
+                },
+            ],
+        });
+        let compute_bindgroup1 = compute_bindgroup(
+            "Smoke compute bind group 1",
+            [&smoke_texture1, &poisson_texture1],
+            [&smoke_texture2, &poisson_texture2],
+        );
+        let compute_bindgroup2 = compute_bindgroup(
+            "Smoke compute bind group 2",
+            [&smoke_texture2, &poisson_texture2],
+            [&smoke_texture1, &poisson_texture1],
+        );
+
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Smoke render bind group"),
+            layout: &layouts.smoke_render,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&packed_smoke_texture.view),
+            }],
+        });
+
+        Grid {
+            size,
+            compute_bindgroup1,
+            compute_bindgroup2,
+            render_bind_group,
+            velocity: smoke_texture2,
+            pressure: poisson_texture2,
+            multigrid: None,
+        }
+    }
+
+    fn prepare_multigrid(&mut self, device: &wgpu::Device, layouts: &Layouts) {
+        if self.multigrid.is_none() {
+            self.multigrid = Some(Multigrid::new(device, layouts, self.size, &self.velocity.view, &self.pressure.view));
+        }
+    }
+}
+
+/// Smoke simulated on the GPU, lit by `number` lasers.
+pub struct Smoke {
+    grid: Grid,
+    pressure: PressureSolver,
+    // Measure the residual with the Jacobi sweeps too
+    report_residual: bool,
+    shader_params: Vec<ComputeParamsUniform>,
+    shader_params_buffer: Vec<Buffer>,
+    shader_params_bindgroup: Vec<BindGroup>,
+    choreography: Choreography,
+    lasers_buffer: Buffer,
+    lasers_bindgroup: BindGroup,
+}
+
+impl Smoke {
+    pub fn new(init: &Init) -> Self {
+        let device = init.device;
+        let layouts = &init.shared.layouts;
+        let size = fit_fluid_size(init.config.fluid_size, &device.limits());
+        if size != init.config.fluid_size {
+            log::warn!("fluid grid reduced to {}x{}x{} to fit the device", size.0, size.1, size.2);
+        }
+
+        let shader_params: Vec<ComputeParamsUniform> = (0..COMPUTE_PASSES + COMPUTE_EXTRAS)
+            .map(|i| ComputeParamsUniform {
+                step: i,
+                delta_time: 0.0,
+                time: 0.0,
+                x: 0.0,
+            })
+            .collect();
+        let shader_params_buffer: Vec<wgpu::Buffer> = shader_params
+            .iter()
+            .map(|p| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Smoke shader params buffer"),
+                    contents: bytemuck::cast_slice(&[*p]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        let shader_params_bindgroup: Vec<BindGroup> = shader_params_buffer
+            .iter()
+            .map(|b| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Smoke shader params bind group"),
+                    layout: &layouts.smoke_params,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: b.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect();
+
+        // Room for the count and every laser there can be, so the buffer never
+        // has to grow
+        let lasers_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Smoke lasers buffer"),
+            size: (std::mem::size_of::<LasersHeader>() + MAX_LASERS*std::mem::size_of::<Laser>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let lasers_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.lasers,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: lasers_buffer.as_entire_binding(),
+            }],
+            label: Some("Lasers bind group")
+        });
+
+        let mut smoke = Smoke {
+            grid: Grid::new(device, layouts, size),
+            pressure: init.config.pressure,
+            report_residual: init.config.pressure_residual,
+            shader_params,
+            shader_params_buffer,
+            shader_params_bindgroup,
+            choreography: Choreography::new(size),
+            lasers_buffer,
+            lasers_bindgroup,
+        };
+        if smoke.measures_residual() {
+            smoke.grid.prepare_multigrid(device, layouts);
+        }
+        smoke
+    }
+
+    // Whether the residual is measured, which takes the multigrid's finest
+    // level even when it isn't solving. It is whenever the multigrid runs.
+    fn measures_residual(&self) -> bool {
+        self.report_residual || matches!(self.pressure, PressureSolver::Multigrid(_))
+    }
+}
+
+impl Scene for Smoke {
+    fn update(&mut self, number: i32, frame: &mut Frame, shared: &Shared, queue: &wgpu::Queue, encoder: &mut CommandEncoder) {
+        let (pattern, row) = (frame.pattern, frame.row);
+        for (i, params) in self.shader_params.iter_mut().enumerate() {
+            params.delta_time = frame.delta_time as f32;
+            params.time = frame.time as f32;
+            params.x = match (row/16)%2 {
+                0 => 0.0,
+                1 => 1.0,
+                _ => panic!()
+            };
+            queue.write_buffer(
+                &self.shader_params_buffer[i],
+                0,
+                bytemuck::cast_slice(&[*params]),
+            );
+        }
+
+        let lasers = self.choreography.update(number, frame.time, frame.delta_time, frame.events, frame.rocket, frame.laser_colors);
+        let header = LasersHeader {
+            count: lasers.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.lasers_buffer, 0, bytemuck::cast_slice(&[header]));
+        if !lasers.is_empty() {
+            queue.write_buffer(&self.lasers_buffer, std::mem::size_of::<LasersHeader>() as u64, bytemuck::cast_slice(lasers));
+        }
+
+        if let Some(multigrid) = &mut self.grid.multigrid {
+            multigrid.poll_residual();
+        }
+        // Only as often as the demo logs it, since reading it back takes a
+        // round trip to the GPU
+        let measure = frame.measure_pressure && self.measures_residual();
+        let (dispatch_width, dispatch_height, dispatch_depth) = compute_work_group_count(self.grid.size);
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Smoke pass"),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(&shared.compute_pipelines["smoke_compute.wgsl"]);
+
+        let cube_time = (0x14..=0x17).contains(&pattern) && (row%16 <= 2);
+        if cube_time {
+            compute_pass.set_bind_group(0, &self.grid.compute_bindgroup1, &[]);
+            compute_pass.set_bind_group(1, &self.shader_params_bindgroup[6], &[]);
+            compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
+            compute_pass.set_bind_group(0, &self.grid.compute_bindgroup2, &[]);
+            compute_pass.set_bind_group(1, &self.shader_params_bindgroup[7], &[]);
+            compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
+        }
+        for i in 0..COMPUTE_PASSES {
+            let texture_bindgroup = match i % 2 {
+                1 => &self.grid.compute_bindgroup2,
+                _ => &self.grid.compute_bindgroup1,
+            };
+            let multigrid = self.grid.multigrid.as_ref().filter(|_| PROJECTIONS.contains(&i));
+            if let Some(multigrid) = multigrid {
+                if let PressureSolver::Multigrid(cycles) = self.pressure {
+                    multigrid.solve(&mut compute_pass, &shared.pressure_pipelines, cycles);
+                }
+                // Whichever solver is running, how well the last projection
+                // will do
+                if measure && i == PROJECTIONS[1] {
+                    multigrid.measure(&mut compute_pass, &shared.pressure_pipelines);
+                }
+                compute_pass.set_pipeline(&shared.compute_pipelines["smoke_compute.wgsl"]);
+            }
+            compute_pass.set_bind_group(0, texture_bindgroup, &[]);
+            compute_pass.set_bind_group(
+                1,
+                &self.shader_params_bindgroup[i as usize],
+                &[],
+            );
+            compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, dispatch_depth);
+        }
+        drop(compute_pass);
+        if let Some(multigrid) = self.grid.multigrid.as_mut().filter(|_| measure) {
+            multigrid.copy_residual(encoder);
+        }
+    }
+
+    fn render(&self, _number: i32, shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder) {
+        let mut render_pass = clear_pass(encoder, "Render Pass 1", &targets.texture_pass1.view, Some(&targets.depth_texture.view));
+        render_pass.set_pipeline(shared.pipeline("shaderpass_smokerender.wgsl"));
+        shared.set_quad(&mut render_pass);
+        render_pass.set_bind_group(1, &shared.bg_uniform_bind_group, &[]);
+        render_pass.set_bind_group(0, &self.grid.render_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.lasers_bindgroup, &[]);
+        render_pass.draw_indexed(0..6, 0, 0..1);
+    }
+
+    fn lasers(&self) -> &[Laser] {
+        self.choreography.lasers()
+    }
+
+    fn resize_fluid(&mut self, device: &wgpu::Device, layouts: &Layouts, size: (u32, u32, u32)) {
+        self.grid = Grid::new(device, layouts, size);
+        if self.measures_residual() {
+            self.grid.prepare_multigrid(device, layouts);
+        }
+        self.choreography.set_grid_size(size);
+    }
+
+    fn set_pressure_solver(&mut self, device: &wgpu::Device, layouts: &Layouts, solver: PressureSolver) {
+        self.pressure = solver;
+        if self.measures_residual() {
+            self.grid.prepare_multigrid(device, layouts);
+        }
+    }
+
+    fn pressure_residual(&self) -> Option<f32> {
+        self.grid.multigrid.as_ref()
+            .filter(|_| self.measures_residual())
+            .and_then(Multigrid::residual)
+    }
+}
+
+/// Where the smoke scene's lasers point and what colour they are, worked out
+/// on the CPU so the same show can be exported for real projectors.
+pub struct Choreography {
+    lasers: Vec<Laser>,
+    flashes: Vec<f32>,
+    // The grid's width and depth, which the lasers stand around
+    floor: (f32, f32),
+}
+
+impl Choreography {
+    pub fn new(size: (u32, u32, u32)) -> Self {
+        Choreography {
+            lasers: Vec::with_capacity(MAX_LASERS),
+            flashes: vec![0.0; MAX_LASERS],
+            floor: (size.0 as f32, size.1 as f32),
+        }
+    }
+
+    pub fn set_grid_size(&mut self, size: (u32, u32, u32)) {
+        self.floor = (size.0 as f32, size.1 as f32);
+    }
+
+    /// Moves on to `time`, `delta_time` seconds after the last call, and returns
+    /// `number` lasers. `laser_colors` override the built-in colours by index.
+    pub fn update(
+        &mut self,
+        number: i32,
+        time: f64,
+        delta_time: f64,
+        events: &[TrackerEvent],
+        rocket: &mut Rocket,
+        laser_colors: &[Option<[f32; 4]>],
+    ) -> &[Laser] {
+        let t = time as f32*rocket.value("smoke:speed").unwrap_or(7.);
+        // How far the lasers sway around, and how much they tilt
+        let sway = rocket.value("smoke:sway").unwrap_or(0.2);
+        let tilt = rocket.value("smoke:tilt").unwrap_or(10.);
+        let width = rocket.value("smoke:width").unwrap_or(0.1);
+        let intensity = rocket.value("smoke:intensity").unwrap_or(DEFAULT_INTENSITY);
+        let shape = BeamShape::from_track(rocket.value("smoke:shape").unwrap_or(0.));
+        let count = (number.max(0) as usize).min(MAX_LASERS);
+        self.lasers.clear();
+        self.lasers.extend((0..count).map(|i| {
+            let (position, transform) = choreography(i, count, self.floor, t, sway, tilt);
+            Laser {
+                transform: (Matrix4::from_translation(position)*transform).invert().unwrap().into(),
+                color: laser_colors.get(i).copied().flatten().unwrap_or_else(|| default_color(i)),
+                width,
+                intensity,
+                shape: shape as u32,
+                _padding: 0,
+            }
+        }));
+
+        // E8x in the effect column of any channel flashes laser x to white
+        let fade = (-delta_time as f32/FLASH_FADE).exp();
+        for flash in self.flashes.iter_mut() {
+            *flash *= fade;
+        }
+        for event in events {
+            if let EventKind::Effect { effect: 0xE, parameter: parameter @ 0x81..=0x8F } = event.kind {
+                self.flashes[(parameter & 0xF) as usize - 1] = 1.0;
+            }
+        }
+        for (laser, flash) in self.lasers.iter_mut().zip(&self.flashes) {
+            for channel in &mut laser.color[..3] {
+                *channel += (1.0 - *channel)*flash;
+            }
+        }
+        &self.lasers
+    }
+
+    /// What the last `update` returned.
+    pub fn lasers(&self) -> &[Laser] {
+        &self.lasers
+    }
+}
+
+/// How a laser's light spreads around its axis, the beam space z axis.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum BeamShape {
+    /// A round beam `width` across
+    Beam = 0,
+    /// A flat sheet of light in the beam's xz plane
+    Sheet = 1,
+    /// A hollow cone opening up along the beam
+    Cone = 2,
+}
+
+impl BeamShape {
+    pub fn from_u32(shape: u32) -> Self {
+        match shape {
+            1 => BeamShape::Sheet,
+            2 => BeamShape::Cone,
+            _ => BeamShape::Beam,
+        }
+    }
+
+    // Sync tracks hold floats, rounded to the nearest shape
+    fn from_track(value: f32) -> Self {
+        Self::from_u32(value.round().max(0.0) as u32)
+    }
+}
+
+/// One laser as the render shader reads it. `transform` takes the smoke grid
+/// into beam space, where the beam starts at the origin and points along z.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Laser {
+    pub transform: [[f32;4];4],
+    pub color: [f32;4],
+    pub width: f32,
+    pub intensity: f32,
+    pub shape: u32,
+    _padding: u32,
+}
+
+// Comes before the lasers in the storage buffer, padded to their alignment
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LasersHeader {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+// Where laser `i` of `count` stands on the floor of a grid `width` by `depth`
+// and how it's aimed. The first four stand in the corners, the rest in a ring
+// around the middle tilting in towards it.
+fn choreography(i: usize, count: usize, (width, depth): (f32, f32), t: f32, sway: f32, tilt: f32) -> (Vector3<f32>, Matrix4<f32>) {
+    let aim = |base: f32, tilt: f32| {
+        let angle = base+sway*t.sin();
+        let axis = Vector3::new(angle.cos(),angle.sin(),0.0);
+        Matrix4::from(Quaternion::from_axis_angle(axis, Deg(tilt)))
+    };
+    match i {
+        0 => (Vector3::new(0.0,0.0,0.0), aim(PI*0.75, 50.+tilt*t.cos())),
+        1 => (Vector3::new(0.0,depth,0.0), aim(PI*0.25, 50.+tilt*t.sin())),
+        2 => (Vector3::new(width,0.0,0.0), aim(PI*1.25, 50.-tilt*t.sin())),
+        3 => (Vector3::new(width,depth,0.0), aim(PI*1.75, 50.-tilt*t.cos())),
+        _ => {
+            let phase = (i - 4) as f32/(count - 4) as f32*2.0*PI;
+            let radius = 0.4*width.min(depth);
+            let position = Vector3::new(width*0.5+radius*phase.cos(), depth*0.5+radius*phase.sin(), 0.0);
+            // Tilting about this axis leans the beam back over the middle
+            (position, aim(phase+PI*0.5, 30.+tilt*(t+phase).sin()))
+        }
+    }
+}
+
+fn default_color(i: usize) -> [f32; 4] {
+    match i {
+        0 => [1.0,0.3,0.3,0.0],
+        1 => [0.3,1.0,0.3,0.0],
+        2 => [0.3,0.3,1.0,0.0],
+        3 => [1.0,1.0,0.3,0.0],
+        // The rest spread round the colour wheel, as pale as the first four
+        _ => {
+            let hue = ((i - 4) as f32*0.618034).fract()*6.0;
+            let r = ((hue-3.0).abs()-1.0).clamp(0.0, 1.0);
+            let g = (2.0-(hue-2.0).abs()).clamp(0.0, 1.0);
+            let b = (2.0-(hue-4.0).abs()).clamp(0.0, 1.0);
+            [0.3+0.7*r, 0.3+0.7*g, 0.3+0.7*b, 0.0]
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ComputeParamsUniform {
+    pub step: i32,
+    pub delta_time: f32,
+    pub time: f32,
+    pub x: f32,
+}
@@ -0,0 +1,115 @@
+use cgmath::Zero;
+use rand::{rngs::SmallRng, Rng};
+use wgpu::{util::DeviceExt, BindGroup, Buffer, CommandEncoder};
+
+use super::{clear_pass, Frame, Init, RenderTargets, Scene, Shared};
+use crate::{
+    model::{DrawModel, Model},
+    resources,
+    Instance,
+};
+
+const NUM_STARWARS: usize = 100;
+
+/// Two fleets shooting lasers past each other, in a window on the slide `number`.
+pub struct StarWars {
+    instances: Vec<Instance>,
+    instance_buffer: Buffer,
+    pewpew_model: Model,
+}
+
+impl StarWars {
+    pub async fn new(init: &Init<'_>, rng: &mut SmallRng) -> Self {
+        let mut instances = vec![];
+        for i in 0..NUM_STARWARS*2 {
+            let side = i as i32%2*2-1;
+            instances.push(Instance {
+                position: cgmath::Vector3::new(
+                    side as f32*40.0+rng.gen_range(-20.0..20.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..0.0)
+                ),
+                rotation: cgmath::Quaternion::zero(),
+                scale: cgmath::Vector3::new(1.0,1.0,1.0),
+                tex_offset: cgmath::Vector2::new(0.0,0.0)
+            });
+        }
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_buffer = init.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Starwars instances Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        StarWars {
+            instances,
+            instance_buffer,
+            pewpew_model: resources::load_model("pewpew.obj", init.device, 0.15).await.unwrap(),
+        }
+    }
+}
+
+impl Scene for StarWars {
+    fn update(&mut self, _number: i32, frame: &mut Frame, _shared: &Shared, queue: &wgpu::Queue, _encoder: &mut CommandEncoder) {
+        for i in 0..NUM_STARWARS*2 {
+            let side = i as i32%2*2-1;
+            self.instances[i].position.x += side as f32*-10.0*frame.delta_time as f32;
+            if side as f32*self.instances[i].position.x < -20.0 {
+                self.instances[i].position = cgmath::Vector3::new(
+                    side as f32*30.0+frame.rng.gen_range(-10.0..10.0),
+                    frame.rng.gen_range(-10.0..10.0),
+                    frame.rng.gen_range(-10.0..0.0)
+                );
+            }
+        }
+        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+        
+        frame.camera.eye = (
+            frame.rocket.value("starwars:eye.x").unwrap_or(-6.0),
+            frame.rocket.value("starwars:eye.y").unwrap_or(-6.0),
+            frame.rocket.value("starwars:eye.z").unwrap_or(10.0),
+        ).into();
+        frame.camera.target = (
+            frame.rocket.value("starwars:target.x").unwrap_or(-5.0),
+            frame.rocket.value("starwars:target.y").unwrap_or(-1.0),
+            frame.rocket.value("starwars:target.z").unwrap_or(0.0),
+        ).into();
+    }
+
+    fn render(&self, number: i32, shared: &Shared, targets: &RenderTargets, encoder: &mut CommandEncoder) {
+        { // Pass 1: the ships
+            let mut render_pass = clear_pass(encoder, "Render Pass 1", &targets.texture_pass2.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_starwars1.wgsl"));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_bind_group(0, &shared.object_uniform_bind_group, &[]);
+            render_pass.draw_model_instanced(&self.pewpew_model, 0..NUM_STARWARS as u32*2);
+        }
+        { // Pass 2: blur it
+            let mut render_pass = clear_pass(encoder, "Render Pass 2", &targets.texture_pass_window.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_starwars2.wgsl"));
+            shared.set_quad(&mut render_pass);
+            render_pass.set_bind_group(0, &targets.texture_pass2_bindgroup, &[]);
+            render_pass.set_bind_group(1, &shared.final_function_bindgroup, &[]);
+            render_pass.set_bind_group(2, &targets.texture_pass2_bindgroup, &[]);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+        }
+        { // Pass 3: render the window
+            let mut render_pass = clear_pass(encoder, "Render Pass 3", &targets.texture_pass1.view, Some(&targets.depth_texture.view));
+            render_pass.set_pipeline(shared.pipeline("shaderpass_simple.wgsl"));
+            shared.set_quad(&mut render_pass);
+            // render background (slide)
+            render_pass.set_bind_group(1, &shared.final_function_bindgroup, &[]);
+            render_pass.set_bind_group(0, &shared.slide_texture_bindgroups[number as usize], &[]);
+            render_pass.set_bind_group(2, &shared.slide_texture_bindgroups[(number as usize-1).max(7)], &[]);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+            // render window (lasers)
+            render_pass.set_bind_group(0, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.set_bind_group(2, &targets.texture_pass_window_bindgroup, &[]);
+            render_pass.draw_indexed(0..6, 0, 2..3);
+        }
+    }
+
+    fn still<'a>(&self, number: i32, shared: &'a Shared) -> Option<&'a BindGroup> {
+        Some(&shared.slide_texture_bindgroups[number as usize])
+    }
+}
@@ -0,0 +1,32 @@
+// Draws a texture stretched over the whole viewport: scales the finished frame
+// to the window, and copies slides into render targets of another size.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = (model.tex_coords+vec2(-1.,1.))*vec2(-.5,.5);
+    out.clip_position = vec4(model.position*vec3(-1.0,-1.0,1.0), 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t: texture_2d<f32>;
+@group(0) @binding(1)
+var s: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t, s, in.tex_coords);
+}
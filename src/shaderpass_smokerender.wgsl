@@ -17,19 +17,33 @@ struct ShaderParams {
 @group(1) @binding(1)
 var<uniform> shader_params: ShaderParams;
 
+// Bands go from bass up, four to a vec4
+struct Audio {
+    energy: array<vec4<f32>, 2>,
+    onset: array<vec4<f32>, 2>,
+    level: f32,
+}
+
+@group(1) @binding(2)
+var<uniform> audio: Audio;
+
+// transform takes the grid into beam space, where the beam points along z.
+// The colour's alpha is unused, it's a vec4 to keep the layout the Rust side's.
+struct Laser {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    width: f32,
+    intensity: f32,
+    shape: u32,
+}
+
 struct Lasers {
-    laser1_transform: mat4x4<f32>,
-    laser2_transform: mat4x4<f32>,
-    laser3_transform: mat4x4<f32>,
-    laser4_transform: mat4x4<f32>,
-    laser1_color: vec3<f32>,
-    laser2_color: vec3<f32>,
-    laser3_color: vec3<f32>,
-    laser4_color: vec3<f32>
+    count: u32,
+    lasers: array<Laser>,
 }
 
 @group(2) @binding(0)
-var<uniform> lasers: Lasers;
+var<storage, read> lasers: Lasers;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -53,7 +67,12 @@ const STEPS_END: i32 = 300;
 const STEPS_START: i32 = 50;
 const STEP: f32 = 0.5;
 const ALPHA: f32 = 0.02;
-const LASER_STRENGTH: f32 = 3.0;
+// Same as MAX_LASERS in timeline.rs
+const MAX_LASERS: u32 = 64u;
+const SHAPE_SHEET: u32 = 1u;
+const SHAPE_CONE: u32 = 2u;
+// Same as CONE_SPREAD in scene/smoke.rs
+const CONE_SPREAD: f32 = 0.25;
 
 @vertex
 fn vs_main(
@@ -80,11 +99,11 @@ var smoke: texture_3d<u32>;
 
 fn sample(coords: vec3<f32>) -> f32 {
     let c0 = vec3<i32>(floor(coords));
-    let packed = textureLoad(smoke, c0, 0);
-    let u00 = unpack2x16float(packed.x);
-    let u01 = unpack2x16float(packed.y);
-    let u10 = unpack2x16float(packed.z);
-    let u11 = unpack2x16float(packed.w);
+    let packed_smoke = textureLoad(smoke, c0, 0);
+    let u00 = unpack2x16float(packed_smoke.x);
+    let u01 = unpack2x16float(packed_smoke.y);
+    let u10 = unpack2x16float(packed_smoke.z);
+    let u11 = unpack2x16float(packed_smoke.w);
     let c1 = vec3<i32>(floor(coords))+vec3<i32>(1,1,1);
     let cd: vec3<f32> = fract(coords);
 
@@ -106,8 +125,15 @@ fn rotation_y(theta: f32) -> mat3x3<f32> {
     return mat3x3f(cos(theta), 0., -sin(theta), 0., 1., 0., sin(theta), 0., cos(theta));
 }
 
-fn laser(position: vec3<f32>) -> f32 {
-    return abs(1.0/max(0.11, (length(position.xy)-(0.1))))*LASER_STRENGTH;
+fn laser(l: Laser, grid_position: vec3<f32>) -> f32 {
+    let position = (l.transform*vec4(grid_position, 1.0)).xyz;
+    var distance = length(position.xy);
+    if l.shape == SHAPE_SHEET {
+        distance = abs(position.y);
+    } else if l.shape == SHAPE_CONE {
+        distance = abs(distance-max(position.z, 0.0)*CONE_SPREAD);
+    }
+    return abs(1.0/max(l.width+0.01, distance-l.width))*l.intensity;
 }
 
 // fn laser2(position: vec3<f32>, offset: vec2<f32>) -> f32 {
@@ -123,10 +149,8 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     var s_ambient: f32 = 0.0;
     //var s_laser: f32 = 0.0;
-    var laser1: f32 = 0.0;
-    var laser2: f32 = 0.0;
-    var laser3: f32 = 0.0;
-    var laser4: f32 = 0.0;
+    let count = min(lasers.count, MAX_LASERS);
+    var glow: array<f32, MAX_LASERS>;
 
     let t = shader_params.time*1.;
     
@@ -143,10 +167,9 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         
         let center = vec2(f32(dimensions.x)/2.0, f32(dimensions.z)/2.0);
         
-        laser1 += s0*laser((lasers.laser1_transform*vec4(p,1.0)).xyz);
-        laser2 += s0*laser((lasers.laser2_transform*vec4(p,1.0)).xyz);
-        laser3 += s0*laser((lasers.laser3_transform*vec4(p,1.0)).xyz);
-        laser4 += s0*laser((lasers.laser4_transform*vec4(p,1.0)).xyz);
+        for (var l=0u; l<count; l++) {
+            glow[l] += s0*laser(lasers.lasers[l], p);
+        }
         
         // var l0 = 0.0;
         // l0 = max(l0, laser(r1*(p-vec3(0.0,0.0,10.0)), vec2(0.0)));
@@ -157,11 +180,12 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         //s_laser += l1*s0;
     }
     
-    return vec4(
-        s_ambient*vec3(1.0,1.0,1.0)
-        +max(vec3(0.0,0.0,0.0), lasers.laser1_color-(1.0-laser1))
-        +max(vec3(0.0,0.0,0.0), lasers.laser2_color-(1.0-laser2))
-        +max(vec3(0.0,0.0,0.0), lasers.laser3_color-(1.0-laser3))
-        +max(vec3(0.0,0.0,0.0), lasers.laser4_color-(1.0-laser4))
-    , 1.0);
+    // The lasers flare on kicks
+    let punch = 1.0 + 0.6*max(audio.onset[0].x, audio.onset[0].y);
+
+    var color = s_ambient*vec3(1.0,1.0,1.0);
+    for (var l=0u; l<count; l++) {
+        color += max(vec3(0.0,0.0,0.0), lasers.lasers[l].color.rgb-(1.0-glow[l]*punch));
+    }
+    return vec4(color, 1.0);
 }
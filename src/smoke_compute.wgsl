@@ -111,6 +111,12 @@ fn load_poisson(coords: vec3<i32>) -> f32 {
     return border*textureLoad(input_poisson, coords.xyz, 0).r;
 }
 
+// Emitters are laid out for a grid 100 cells across. This scales a length in
+// cells to a grid `size` cells across, keeping it at least a cell.
+fn scaled(cells: i32, size: i32) -> i32 {
+    return max(cells*size/100, 1);
+}
+
 fn rotation(theta: f32) -> mat3x3f {
     return mat3x3f(cos(theta), sin(theta), 0., -sin(theta), cos(theta), 0., 0., 0., 1.);
 }
@@ -163,10 +169,12 @@ fn fluid_main(
         // add smoke and velocity
         case 0: {
             let center: vec3<f32> = vec3(f32(dimensions.x)/2.0, f32(dimensions.y)/2.0, 1.0);
-            let point_1: vec3<f32> = center+(rotation(           2.)*vec3(30.0, 0.0, 0.0));
-            let point_2: vec3<f32> = center+(rotation(2.0/3.0*pi+2.)*vec3(30.0, 0.0, 0.0));
-            let point_3: vec3<f32> = center+(rotation(4.0/3.0*pi+2.)*vec3(30.0, 0.0, 0.0));
-            let d: f32 = step(3.0, min(min(distance(vec3<f32>(coords), point_1), distance(vec3<f32>(coords), point_2)), distance(vec3<f32>(coords), point_3)));
+            let floor_size: i32 = min(dimensions.x, dimensions.y);
+            let offset: vec3<f32> = vec3(f32(scaled(30, floor_size)), 0.0, 0.0);
+            let point_1: vec3<f32> = center+(rotation(           2.)*offset);
+            let point_2: vec3<f32> = center+(rotation(2.0/3.0*pi+2.)*offset);
+            let point_3: vec3<f32> = center+(rotation(4.0/3.0*pi+2.)*offset);
+            let d: f32 = step(f32(scaled(3, floor_size)), min(min(distance(vec3<f32>(coords), point_1), distance(vec3<f32>(coords), point_2)), distance(vec3<f32>(coords), point_3)));
             let dir: vec3<f32> = 
                 ((center-vec3<f32>(coords))*10.0+vec3(0.0,0.0,150.0))
                 +10.0*sin(10.*shader_params.time);
@@ -201,7 +209,9 @@ fn fluid_main(
             let center = dimensions/2;
             var p: f32 = 0.;
             if shader_params.x < 0.5 {
-                if cube(coords-center+vec3(0,0,10), vec3(7,7,7),3) < 0 {
+                let half = vec3(scaled(7, dimensions.x), scaled(7, dimensions.y), scaled(7, dimensions.z));
+                let smallest = min(min(dimensions.x, dimensions.y), dimensions.z);
+                if cube(coords-center+vec3(0,0,scaled(10, dimensions.z)), half, scaled(3, smallest)) < 0 {
                     p = 30.*shader_params.delta_time;
                 }
             } else {
@@ -217,7 +227,9 @@ fn fluid_main(
                     0,0,0,1,1,1,0,0,0,
                     0,0,0,0,1,0,0,0,0,
                 );
-                let c: vec3<i32> = (vec3(abs(coords.x),coords.y,coords.z)-center+vec3(-30,0,30))/2;
+                let offset = vec3(-scaled(30, dimensions.x), 0, scaled(30, dimensions.z));
+                let cell = scaled(2, min(min(dimensions.x, dimensions.y), dimensions.z));
+                let c: vec3<i32> = (vec3(abs(coords.x),coords.y,coords.z)-center+offset)/cell;
                 if (c.x>=0&&c.x<9)&&(c.z>=0&&c.z<10)&&(c.y>=0&&c.y<1) {
                     p = f32(letter[(9-c.z)*9+c.x])*0.3;
                 }
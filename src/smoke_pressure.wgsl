@@ -0,0 +1,239 @@
+// Multigrid V-cycles for the smoke's pressure, the other way to solve it than
+// the Jacobi iteration folded into smoke_compute.wgsl. FluidGrid::solve_pressure
+// does the same on the CPU.
+//
+// The finest level is the grid itself, with the walls held at zero pressure. The
+// coarser ones cover what's inside the walls with cells 2^level grid cells
+// across, and keep the pressure at zero on their outer faces by reading a mirror
+// image beyond them.
+
+@group(0) @binding(0) var input_a : texture_3d<f32>;
+@group(0) @binding(1) var input_b : texture_3d<f32>;
+@group(0) @binding(2) var output_a : texture_storage_3d<r32float, write>;
+@group(0) @binding(3) var output_b : texture_storage_3d<r32float, write>;
+// Each residual_main workgroup's summed squared residual and right hand side
+@group(0) @binding(4) var<storage, read_write> partial_sums : array<vec2<f32>>;
+
+struct PressureParams {
+    level: u32,
+    spacing_squared: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> pressure_params: PressureParams;
+
+// Weighted Jacobi damps the shortest waves fastest with this in three dimensions
+const OMEGA: f32 = 0.857142857;
+const MIN_RISE: f32 = 5.0;
+
+var<workgroup> sums: array<vec2<f32>, 64>;
+
+fn is_unknown(coords: vec3<i32>, size: vec3<i32>, level: u32) -> bool {
+    if level == 0u {
+        return all(coords >= vec3(1)) && all(coords < size - vec3(1));
+    }
+    return all(coords >= vec3(0)) && all(coords < size);
+}
+
+// A pressure one cell away at most, as the solve sees it
+fn load_pressure(pressure: texture_3d<f32>, coords: vec3<i32>, level: u32) -> f32 {
+    let size = vec3<i32>(textureDimensions(pressure));
+    if level == 0u {
+        if is_unknown(coords, size, 0u) {
+            return textureLoad(pressure, coords, 0).r;
+        }
+        return 0.0;
+    }
+    let outside = select(vec3(0), vec3(1), (coords < vec3(0)) | (coords >= size));
+    let value = textureLoad(pressure, clamp(coords, vec3(0), size - vec3(1)), 0).r;
+    return select(value, -value, (outside.x + outside.y + outside.z) % 2 == 1);
+}
+
+// What smoke_compute.wgsl's load() returns
+fn load_velocity(coords: vec3<i32>) -> vec3<f32> {
+    let size = vec3<i32>(textureDimensions(input_a));
+    var velocity = vec3(0.0);
+    if is_unknown(coords, size, 0u) {
+        velocity = textureLoad(input_a, coords, 0).xyz;
+    }
+    return vec3(velocity.xy, max(velocity.z, MIN_RISE));
+}
+
+fn neighbour(i: i32) -> vec3<i32> {
+    switch i {
+        case 0: { return vec3( 1, 0, 0); }
+        case 1: { return vec3(-1, 0, 0); }
+        case 2: { return vec3( 0, 1, 0); }
+        case 3: { return vec3( 0,-1, 0); }
+        case 4: { return vec3( 0, 0, 1); }
+        default: { return vec3( 0, 0,-1); }
+    }
+}
+
+// input_a holds the level's pressure and input_b its right hand side
+fn residual(coords: vec3<i32>) -> f32 {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let level = pressure_params.level;
+    if !is_unknown(coords, size, level) {
+        return 0.0;
+    }
+    var sum = 0.0;
+    for (var i = 0; i < 6; i++) {
+        sum += load_pressure(input_a, coords + neighbour(i), level);
+    }
+    let laplacian = (sum - 6.0*load_pressure(input_a, coords, level))/pressure_params.spacing_squared;
+    return textureLoad(input_b, coords, 0).r - laplacian;
+}
+
+// The finest level's cells start inside its walls
+fn offset() -> i32 {
+    return select(0, 1, pressure_params.level == 0u);
+}
+
+// Reads the velocity from input_a and the pressure to start from from input_b.
+// Writes the right hand side to output_a, the divergence as the projection sees
+// it, and the starting pressure to output_b.
+@compute @workgroup_size(8,8,4)
+fn divergence_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coords = vec3<i32>(global_id);
+    if any(coords >= size) {
+        return;
+    }
+    var rhs = 0.0;
+    var pressure = 0.0;
+    if is_unknown(coords, size, 0u) {
+        rhs = (
+             load_velocity(coords+vec3( 1, 0, 0)).x
+            -load_velocity(coords+vec3(-1, 0, 0)).x
+            +load_velocity(coords+vec3( 0, 1, 0)).y
+            -load_velocity(coords+vec3( 0,-1, 0)).y
+            +load_velocity(coords+vec3( 0, 0, 1)).z
+            -load_velocity(coords+vec3( 0, 0,-1)).z
+        )*0.5;
+        pressure = textureLoad(input_b, coords, 0).r;
+    }
+    textureStore(output_a, coords, vec4(rhs));
+    textureStore(output_b, coords, vec4(pressure));
+}
+
+// One weighted Jacobi sweep from input_a to output_a. Mirrored neighbours are
+// minus the cell itself, so they count towards the diagonal.
+@compute @workgroup_size(8,8,4)
+fn smooth_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coords = vec3<i32>(global_id);
+    let level = pressure_params.level;
+    if any(coords >= size) {
+        return;
+    }
+    if !is_unknown(coords, size, level) {
+        textureStore(output_a, coords, vec4(0.0));
+        return;
+    }
+    var sum = 0.0;
+    var mirrored = 0;
+    for (var i = 0; i < 6; i++) {
+        let n = coords + neighbour(i);
+        if level > 0u && !is_unknown(n, size, level) {
+            mirrored += 1;
+        } else {
+            sum += load_pressure(input_a, n, level);
+        }
+    }
+    let p = load_pressure(input_a, coords, level);
+    let jacobi = (sum - pressure_params.spacing_squared*textureLoad(input_b, coords, 0).r)/f32(6 + mirrored);
+    textureStore(output_a, coords, vec4((1.0 - OMEGA)*p + OMEGA*jacobi));
+}
+
+// Averages the residual of the level in input_a and input_b over each cell of
+// the next coarser one, as its right hand side in output_a. Its pressure in
+// output_b starts from zero.
+@compute @workgroup_size(8,8,4)
+fn restrict_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coarse_size = vec3<i32>(textureDimensions(output_a));
+    let coords = vec3<i32>(global_id);
+    if any(coords >= coarse_size) {
+        return;
+    }
+    var sum = 0.0;
+    var count = 0;
+    for (var i = 0; i < 8; i++) {
+        let child = offset() + 2*coords + vec3(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        if is_unknown(child, size, pressure_params.level) {
+            sum += residual(child);
+            count += 1;
+        }
+    }
+    textureStore(output_a, coords, vec4(sum/f32(max(count, 1))));
+    textureStore(output_b, coords, vec4(0.0));
+}
+
+// Adds the next coarser level's pressure in input_b, interpolated between cell
+// centres, to the pressure in input_a, into output_a.
+@compute @workgroup_size(8,8,4)
+fn prolong_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coords = vec3<i32>(global_id);
+    let level = pressure_params.level;
+    if any(coords >= size) {
+        return;
+    }
+    if !is_unknown(coords, size, level) {
+        textureStore(output_a, coords, vec4(0.0));
+        return;
+    }
+    let position = vec3<f32>(coords - vec3(offset()))*0.5 - 0.25;
+    let c0 = vec3<i32>(floor(position));
+    let f = position - floor(position);
+    let coarse = level + 1u;
+    let s00 = mix(load_pressure(input_b, c0+vec3(0,0,0), coarse), load_pressure(input_b, c0+vec3(1,0,0), coarse), f.x);
+    let s01 = mix(load_pressure(input_b, c0+vec3(0,0,1), coarse), load_pressure(input_b, c0+vec3(1,0,1), coarse), f.x);
+    let s10 = mix(load_pressure(input_b, c0+vec3(0,1,0), coarse), load_pressure(input_b, c0+vec3(1,1,0), coarse), f.x);
+    let s11 = mix(load_pressure(input_b, c0+vec3(0,1,1), coarse), load_pressure(input_b, c0+vec3(1,1,1), coarse), f.x);
+    let correction = mix(mix(s00, s10, f.y), mix(s01, s11, f.y), f.z);
+    textureStore(output_a, coords, vec4(load_pressure(input_a, coords, level) + correction));
+}
+
+// Copies the finest level's pressure in input_a to the smoke's pressure texture
+// in output_a.
+@compute @workgroup_size(8,8,4)
+fn output_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coords = vec3<i32>(global_id);
+    if any(coords >= size) {
+        return;
+    }
+    textureStore(output_a, coords, vec4(load_pressure(input_a, coords, 0u)));
+}
+
+// Sums the squared residual and right hand side of the finest level over each
+// workgroup, for the CPU to add up.
+@compute @workgroup_size(4,4,4)
+fn residual_main(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+    @builtin(local_invocation_index) local_index : u32,
+    @builtin(workgroup_id) workgroup_id : vec3<u32>,
+    @builtin(num_workgroups) workgroups : vec3<u32>,
+) {
+    let size = vec3<i32>(textureDimensions(input_a));
+    let coords = vec3<i32>(global_id);
+    var squares = vec2(0.0);
+    if all(coords < size) {
+        let r = residual(coords);
+        let rhs = textureLoad(input_b, coords, 0).r;
+        squares = vec2(r*r, rhs*rhs);
+    }
+    sums[local_index] = squares;
+    for (var stride = 32u; stride > 0u; stride >>= 1u) {
+        workgroupBarrier();
+        if local_index < stride {
+            sums[local_index] += sums[local_index + stride];
+        }
+    }
+    if local_index == 0u {
+        let workgroup = workgroup_id.x + workgroups.x*(workgroup_id.y + workgroups.y*workgroup_id.z);
+        partial_sums[workgroup] = sums[0];
+    }
+}
@@ -13,12 +13,12 @@ impl Texture {
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        (width, height): (u32, u32),
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: 1920,//config.width,
-            height: 1080,//config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
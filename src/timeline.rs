@@ -0,0 +1,202 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::resources;
+
+pub const TIMELINE_FILE: &str = "timeline.ron";
+/// The most lasers the smoke scene can light. The render shader has the same limit.
+pub const MAX_LASERS: usize = 64;
+
+/// One cue: at tracker position (pattern, row), switch to `Scene` using `Transition`.
+pub type Step = ((usize, usize), Scene, Transition);
+
+#[derive(Copy,Clone,Debug,Deserialize)]
+pub enum Transition {
+    None,
+    Fade(f32),
+    Slide,
+    Blink,
+    Blink2
+}
+
+#[derive(Copy,Clone,Debug,Deserialize)]
+pub enum Scene {
+    Slide(i32),
+    Black,
+    CDs(i32),
+    StarWars(i32),
+    Ocean(i32),
+    Smoke(i32),
+}
+
+impl Scene {
+    /// Checks that the scene exists with `slide_count` slides loaded.
+    pub fn check(&self, slide_count: usize) -> Result<(), String> {
+        match *self {
+            Scene::Slide(number) | Scene::CDs(number) | Scene::StarWars(number) | Scene::Ocean(number) => {
+                if number < 0 || number as usize >= slide_count {
+                    return Err(format!("unknown slide index {} ({} slides)", number, slide_count));
+                }
+            }
+            Scene::Smoke(number) => {
+                if !(1..=MAX_LASERS as i32).contains(&number) {
+                    return Err(format!("smoke scene needs 1 to {} lasers, got {}", MAX_LASERS, number));
+                }
+            }
+            Scene::Black => {}
+        }
+        Ok(())
+    }
+}
+
+impl Transition {
+    pub fn check(&self) -> Result<(), String> {
+        if let Transition::Fade(duration) = *self {
+            if duration.is_nan() || duration <= 0.0 {
+                return Err(format!("fade duration must be positive, got {}", duration));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Timeline {
+    pub steps: Vec<Step>,
+    name: String,
+    // Source line of each entry in `steps`, for error messages
+    lines: Vec<usize>,
+}
+
+impl Timeline {
+    /// Loads the timeline from `path` if given, otherwise the one embedded in `ASSETS`.
+    pub async fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => {
+                log::info!("loading timeline from {}", path.display());
+                let source = std::fs::read_to_string(path)?;
+                Self::parse(&path.display().to_string(), &source)
+            }
+            None => {
+                let source = resources::load_string(TIMELINE_FILE).await?;
+                Self::parse(TIMELINE_FILE, &source)
+            }
+        }
+    }
+
+    pub fn parse(name: &str, source: &str) -> anyhow::Result<Self> {
+        let steps: Vec<Step> = ron::from_str(source)
+            .map_err(|e| anyhow::anyhow!("{}:{}", name, e))?;
+        if steps.is_empty() {
+            anyhow::bail!("{}: timeline has no steps", name);
+        }
+        Ok(Timeline {
+            steps,
+            name: name.to_owned(),
+            lines: entry_lines(source),
+        })
+    }
+
+    /// Index of the step that is active at tracker position (pattern, row).
+    pub fn step_index_at(&self, position: (usize, usize)) -> usize {
+        self.steps
+            .iter()
+            .rposition(|step| step.0 <= position)
+            .unwrap_or(0)
+    }
+
+    /// Position of the step after `index`, or (0xff,0xff) if it is the last one.
+    pub fn next_position(&self, index: usize) -> (usize, usize) {
+        self.steps.get(index+1)
+            .map(|step| step.0)
+            .unwrap_or((0xff,0xff))
+    }
+
+    /// Checks the cues against the loaded assets. `pattern_rows` holds the number of
+    /// rows of each entry in the module's order table.
+    pub fn validate(&self, slide_count: usize, pattern_rows: &[usize]) -> anyhow::Result<()> {
+        let mut previous: Option<(usize, usize)> = None;
+        for (i, &((pattern, row), scene, transition)) in self.steps.iter().enumerate() {
+            let line = self.lines.get(i).copied().unwrap_or(0);
+            let fail = |message: String| anyhow::anyhow!("{}:{}: {}", self.name, line, message);
+
+            if let Some(previous) = previous {
+                if (pattern, row) <= previous {
+                    return Err(fail(format!(
+                        "step at {:02x}/{:02x} is not after the previous step at {:02x}/{:02x}",
+                        pattern, row, previous.0, previous.1)));
+                }
+            }
+            previous = Some((pattern, row));
+
+            let rows = *pattern_rows.get(pattern).ok_or_else(|| fail(format!(
+                "pattern {:02x} is beyond the module's order table ({} entries)",
+                pattern, pattern_rows.len())))?;
+            if row >= rows {
+                return Err(fail(format!(
+                    "row {:02x} is beyond the end of pattern {:02x} ({} rows)",
+                    row, pattern, rows)));
+            }
+
+            scene.check(slide_count).map_err(&fail)?;
+            transition.check().map_err(&fail)?;
+        }
+        Ok(())
+    }
+}
+
+// Finds the line each top-level list entry starts on. ron doesn't give us spans
+// after deserializing, so track the bracket depth by hand.
+fn entry_lines(source: &str) -> Vec<usize> {
+    let mut lines = vec![];
+    let mut line = 1;
+    let mut depth = 0;
+    let mut expecting_entry = false;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            _ => {}
+        }
+        if depth == 1 && expecting_entry && c != ']' && c != ',' {
+            lines.push(line);
+            expecting_entry = false;
+        }
+        match c {
+            '[' | '(' | '{' => {
+                depth += 1;
+                if depth == 1 {
+                    expecting_entry = true;
+                }
+            }
+            ']' | ')' | '}' => depth -= 1,
+            ',' if depth == 1 => expecting_entry = true,
+            _ => {}
+        }
+    }
+    lines
+}
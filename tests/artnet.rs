@@ -0,0 +1,106 @@
+// Sends Art-Net to a stand-in for a lighting node listening on a local socket.
+
+use std::{net::UdpSocket, thread};
+
+use laser_demo::{ArtNet, DmxMapping, LaserLight, Scene, ShowState};
+use web_time::Duration;
+
+const MAPPING: &str = "
+(
+    universe: 0x123,
+    channels: [
+        (1, Red(1)),
+        (2, Green(1)),
+        (3, Blue(1)),
+        (4, Pan(1)),
+        (5, Tilt(1)),
+        (6, Red(2)),
+        (10, Scene),
+        (11, SceneNumber),
+        (12, Beat),
+        (13, Transition),
+        (15, Fixed(42)),
+    ],
+)";
+
+fn receiver() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket
+}
+
+fn state() -> ShowState {
+    ShowState {
+        lasers: vec![LaserLight { color: [1.0, 0.5, 0.0], pan: 180.0, tilt: 45.0 }],
+        scene: Scene::Smoke(3),
+        since_beat: 0.0,
+        transition: 0.25,
+    }
+}
+
+fn receive(socket: &UdpSocket) -> Vec<u8> {
+    let mut buffer = [0; 1024];
+    let length = socket.recv(&mut buffer).unwrap();
+    buffer[..length].to_vec()
+}
+
+#[test]
+fn dmx_packet() {
+    let node = receiver();
+    let mapping = DmxMapping::parse("mapping.ron", MAPPING).unwrap();
+    let mut artnet = ArtNet::connect(&node.local_addr().unwrap().to_string(), mapping).unwrap();
+    assert!(artnet.update(&state()));
+
+    let packet = receive(&node);
+    assert_eq!(&packet[0..8], b"Art-Net\0");
+    assert_eq!(&packet[8..10], &[0x00, 0x50], "OpDmx, little endian");
+    assert_eq!(&packet[10..12], &[0, 14], "protocol version");
+    assert_eq!(packet[12], 1, "sequence");
+    assert_eq!(&packet[14..16], &[0x23, 0x01], "SubUni and Net");
+    // Up to channel 15, rounded up to even
+    assert_eq!(&packet[16..18], &[0, 16], "length");
+    assert_eq!(packet.len(), 18 + 16);
+
+    let dmx = &packet[18..];
+    assert_eq!(&dmx[0..5], &[255, 128, 0, 128, 128], "laser 1");
+    assert_eq!(dmx[5], 0, "laser 2 isn't on");
+    assert_eq!(dmx[9], 5, "scene");
+    assert_eq!(dmx[10], 3, "scene number");
+    assert_eq!(dmx[11], 255, "beat");
+    assert_eq!(dmx[12], 64, "transition");
+    assert_eq!(dmx[13], 0, "unmapped");
+    assert_eq!(dmx[14], 42, "fixed");
+}
+
+#[test]
+fn rate_limited() {
+    let node = receiver();
+    let mut artnet = ArtNet::connect(&node.local_addr().unwrap().to_string(), DmxMapping::default()).unwrap();
+    assert!(artnet.update(&state()));
+    // Well within a DMX frame of the first
+    assert!(!artnet.update(&state()));
+    assert!(!artnet.update(&state()));
+    assert_eq!(receive(&node)[12], 1);
+
+    thread::sleep(Duration::from_millis(30));
+    let mut later = state();
+    later.since_beat = 1.0;
+    assert!(artnet.update(&later));
+    let packet = receive(&node);
+    assert_eq!(packet[12], 2, "sequence");
+    // The default mapping has the beat on channel 23
+    assert_eq!(packet[18 + 22], 0);
+
+    // Nothing else was sent
+    node.set_nonblocking(true).unwrap();
+    assert!(node.recv(&mut [0; 1024]).is_err());
+}
+
+#[test]
+fn bad_mappings() {
+    assert!(DmxMapping::parse("mapping.ron", "(channels: [(0, Beat)])").is_err());
+    assert!(DmxMapping::parse("mapping.ron", "(channels: [(513, Beat)])").is_err());
+    assert!(DmxMapping::parse("mapping.ron", "(channels: [(1, Red(0))])").is_err());
+    assert!(DmxMapping::parse("mapping.ron", "(universe: 0x8000, channels: [])").is_err());
+    assert!(DmxMapping::parse("mapping.ron", "(channels: [(512, Beat)])").is_ok());
+}
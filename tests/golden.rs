@@ -0,0 +1,219 @@
+// Renders each scene and transition headlessly at fixed times and compares the
+// frames against the reference images in tests/golden/.
+//
+// After an intended visual change, regenerate the references with
+//     GOLDEN_BLESS=1 cargo test --test golden
+// and look them over before committing. On machines without a GPU, set
+// FALLBACK_ADAPTER=1 to render with a software adapter such as lavapipe, or
+// WGPU_BACKEND=gl for one such as llvmpipe. Without any adapter at all the
+// tests fail, since nothing would be checked. wgpu's GL backend binds a 3D
+// storage texture's first layer only, so the smoke never builds up there and
+// the smoke tests need another backend.
+//
+// Every frame checked has to show something, so a scene that renders nothing
+// can't pass against a blank reference. Black is the one scene that's meant to be
+// a single colour.
+
+use std::path::{Path, PathBuf};
+
+use image::{imageops::FilterType, Rgba, RgbaImage};
+use laser_demo::{Config, Headless};
+use web_time::Duration;
+
+const FPS: u32 = 30;
+// Frames to compare unless a test picks its own, counted at FPS. Everything in
+// between is still rendered, since the smoke and the transitions build on previous
+// frames.
+const CHECKPOINTS: [u32; 3] = [3, 15, 30];
+// References are stored downscaled, which keeps them small and smooths over the
+// noise of different GPUs rounding differently.
+const COMPARE_SIZE: (u32, u32) = (480, 270);
+// A pixel counts as different if any channel is off by more than this...
+const PIXEL_TOLERANCE: u8 = 24;
+// ...and a frame fails if more than this fraction of its pixels differ.
+const MAX_DIFFERENT_PIXELS: f64 = 0.005;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+/// Plays a timeline of just `scene` with `transition`, starting at `position` in
+/// the music, and checks the frames at `checkpoints`.
+fn check(name: &str, position: (usize, usize), scene: &str, transition: &str, checkpoints: &[u32]) {
+    std::fs::create_dir_all(output_dir()).unwrap();
+    let timeline = output_dir().join(format!("{}.ron", name));
+    std::fs::write(
+        &timeline,
+        format!("[(({:#04x},{:#04x}), {}, {})]", position.0, position.1, scene, transition),
+    )
+    .unwrap();
+
+    let config = Config {
+        timeline: Some(timeline),
+        fluid_size: (50, 50, 50),
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(Config::default().backends),
+        fallback_adapter: std::env::var_os("FALLBACK_ADAPTER").is_some(),
+        ..Default::default()
+    };
+    let mut headless = pollster::block_on(Headless::new(&config))
+        .unwrap_or_else(|e| panic!("{}: {:#}, see the top of tests/golden.rs for running without a GPU", name, e));
+
+    let bless = std::env::var_os("GOLDEN_BLESS").is_some();
+    let mut failures = vec![];
+    for frame in 0..=checkpoints[checkpoints.len() - 1] {
+        let image = headless
+            .render_frame(Duration::from_secs_f64(frame as f64 / FPS as f64))
+            .unwrap();
+        if !checkpoints.contains(&frame) {
+            continue;
+        }
+
+        let file_name = format!("{}_{:03}.png", name, frame);
+        let actual = image::imageops::resize(&image, COMPARE_SIZE.0, COMPARE_SIZE.1, FilterType::Triangle);
+        let reference_path = golden_dir().join(&file_name);
+        if bless {
+            if scene != "Black" && is_flat(&actual) {
+                failures.push(format!("{}: only one colour, not saving it as a reference", file_name));
+                continue;
+            }
+            std::fs::create_dir_all(golden_dir()).unwrap();
+            actual.save(&reference_path).unwrap();
+            continue;
+        }
+
+        let actual_path = output_dir().join(&file_name);
+        actual.save(&actual_path).unwrap();
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.into_rgba8(),
+            Err(_) => {
+                failures.push(format!(
+                    "{}: no reference image, rendered {}",
+                    file_name, actual_path.display()
+                ));
+                continue;
+            }
+        };
+
+        if scene != "Black" && is_flat(&reference) {
+            failures.push(format!("{}: the reference is only one colour, so it checks nothing", file_name));
+            continue;
+        }
+
+        let (different, diff) = compare(&reference, &actual);
+        if different > MAX_DIFFERENT_PIXELS {
+            let diff_path = output_dir().join(format!("{}_{:03}_diff.png", name, frame));
+            diff.save(&diff_path).unwrap();
+            failures.push(format!(
+                "{}: {:.2}% of pixels differ, see {} and {}",
+                file_name,
+                different * 100.0,
+                actual_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn is_flat(image: &RgbaImage) -> bool {
+    image.pixels().all(|pixel| pixel == image.get_pixel(0, 0))
+}
+
+/// Returns the fraction of pixels that differ by more than PIXEL_TOLERANCE, and an
+/// image with those pixels in red over a dimmed copy of the reference.
+fn compare(reference: &RgbaImage, actual: &RgbaImage) -> (f64, RgbaImage) {
+    if reference.dimensions() != actual.dimensions() {
+        return (1.0, actual.clone());
+    }
+    let mut different = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, b) = (reference.get_pixel(x, y), actual.get_pixel(x, y));
+        let distance = a.0.iter().zip(b.0.iter()).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+        if distance > PIXEL_TOLERANCE {
+            different += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (a[0] as u32 + a[1] as u32 + a[2] as u32) / 12;
+            Rgba([luma as u8, luma as u8, luma as u8, 255])
+        }
+    });
+    (different as f64 / (actual.width() * actual.height()) as f64, diff)
+}
+
+#[test]
+fn scene_black() {
+    check("scene_black", (0x00, 0x00), "Black", "None", &CHECKPOINTS);
+}
+
+// A still picture, so every checkpoint has the same frame
+#[test]
+fn scene_slide() {
+    check("scene_slide", (0x02, 0x0f), "Slide(3)", "None", &CHECKPOINTS);
+}
+
+// Slides 0 and 1 have their own animation in the final pass. Slide 0 fades in
+// from black over the first few seconds.
+#[test]
+fn scene_slide_0() {
+    check("scene_slide_0", (0x00, 0x18), "Slide(0)", "None", &[45, 90, 300]);
+}
+
+#[test]
+fn scene_slide_1() {
+    check("scene_slide_1", (0x01, 0x1b), "Slide(1)", "None", &CHECKPOINTS);
+}
+
+#[test]
+fn scene_cds() {
+    check("scene_cds", (0x04, 0x30), "CDs(9)", "None", &CHECKPOINTS);
+}
+
+#[test]
+fn scene_starwars() {
+    check("scene_starwars", (0x07, 0x30), "StarWars(12)", "None", &CHECKPOINTS);
+}
+
+#[test]
+fn scene_ocean() {
+    check("scene_ocean", (0x0b, 0x00), "Ocean(15)", "None", &CHECKPOINTS);
+}
+
+// Late enough for the smoke to have risen from the emitters
+const SMOKE_CHECKPOINTS: [u32; 3] = [60, 90, 120];
+
+#[test]
+fn scene_smoke_1() {
+    check("scene_smoke_1", (0x10, 0x08), "Smoke(1)", "None", &SMOKE_CHECKPOINTS);
+}
+
+#[test]
+fn scene_smoke_4() {
+    check("scene_smoke_4", (0x13, 0x00), "Smoke(4)", "None", &SMOKE_CHECKPOINTS);
+}
+
+#[test]
+fn transition_fade() {
+    check("transition_fade", (0x02, 0x1b), "Slide(4)", "Fade(1.0)", &CHECKPOINTS);
+}
+
+#[test]
+fn transition_slide() {
+    check("transition_slide", (0x01, 0x3b), "Slide(2)", "Slide", &CHECKPOINTS);
+}
+
+// The new scene only blinks in at the end, here after about 4.8 seconds
+#[test]
+fn transition_blink() {
+    check("transition_blink", (0x03, 0x2d), "CDs(8)", "Blink", &[144, 147, 180]);
+}
+
+// Blink2 is over in half a second
+#[test]
+fn transition_blink2() {
+    check("transition_blink2", (0x19, 0x3d), "Slide(23)", "Blink2", &[9, 12, 30]);
+}
@@ -0,0 +1,107 @@
+// Writes ILDA files and reads them back, checking the layout against the
+// ILDA Image Data Transfer Format along the way.
+
+use laser_demo::{parse_ilda, write_ilda, IldaFormat, IldaFrame, IldaPoint};
+
+fn point(x: i16, y: i16, z: i16, color: [u8; 3], blanked: bool) -> IldaPoint {
+    IldaPoint { x, y, z, color, blanked }
+}
+
+fn frames(z: i16) -> Vec<IldaFrame> {
+    vec![
+        IldaFrame {
+            name: "00000000".to_owned(),
+            company: "demo".to_owned(),
+            projector: 0,
+            points: vec![
+                point(0, 0, 0, [0, 0, 0], true),
+                point(i16::MIN, i16::MAX, z, [255, 77, 77], false),
+                point(1234, -4321, -z, [77, 255, 77], false),
+            ],
+        },
+        IldaFrame {
+            name: "00000001".to_owned(),
+            company: String::new(),
+            projector: 3,
+            points: vec![point(-1, 1, z, [1, 2, 3], false)],
+        },
+    ]
+}
+
+fn write(format: IldaFormat, frames: &[IldaFrame]) -> Vec<u8> {
+    let mut data = vec![];
+    write_ilda(&mut data, format, frames).unwrap();
+    data
+}
+
+#[test]
+fn round_trip_3d() {
+    let frames = frames(-20000);
+    let data = write(IldaFormat::ThreeD, &frames);
+    assert_eq!(parse_ilda(&data).unwrap(), frames);
+}
+
+#[test]
+fn round_trip_2d() {
+    // Format 5 has no z, which reads back as 0
+    let frames = frames(0);
+    let data = write(IldaFormat::TwoD, &frames);
+    assert_eq!(parse_ilda(&data).unwrap(), frames);
+    assert_eq!(parse_ilda(&write(IldaFormat::TwoD, &self::frames(500))).unwrap(), frames);
+}
+
+#[test]
+fn layout() {
+    let data = write(IldaFormat::TwoD, &frames(0));
+    // Two headers and an end header, 3 + 1 records of 8 bytes
+    assert_eq!(data.len(), 3 * 32 + 4 * 8);
+
+    let header = &data[..32];
+    assert_eq!(&header[0..4], b"ILDA");
+    assert_eq!(header[7], 5);
+    assert_eq!(&header[8..16], b"00000000");
+    assert_eq!(&header[16..24], b"demo\0\0\0\0");
+    assert_eq!(&header[24..26], &[0, 3], "records");
+    assert_eq!(&header[26..28], &[0, 0], "frame number");
+    assert_eq!(&header[28..30], &[0, 2], "total frames");
+
+    let records = &data[32..56];
+    // x, y, status, blue, green, red
+    assert_eq!(&records[0..8], &[0, 0, 0, 0, 0x40, 0, 0, 0], "blanked");
+    assert_eq!(&records[8..16], &[0x80, 0x00, 0x7f, 0xff, 0, 77, 77, 255]);
+    assert_eq!(records[16 + 4], 0x80, "last point");
+
+    let second = &data[56..88];
+    assert_eq!(&second[26..28], &[0, 1], "frame number");
+    assert_eq!(second[30], 3, "projector");
+
+    let end = &data[data.len() - 32..];
+    assert_eq!(&end[0..4], b"ILDA");
+    assert_eq!(&end[24..26], &[0, 0], "records");
+}
+
+#[test]
+fn empty_frames_are_refused() {
+    let frame = IldaFrame {
+        name: String::new(),
+        company: String::new(),
+        projector: 0,
+        points: vec![],
+    };
+    assert!(write_ilda(&mut vec![], IldaFormat::ThreeD, &[frame]).is_err());
+}
+
+#[test]
+fn bad_files_are_errors() {
+    let data = write(IldaFormat::ThreeD, &frames(1));
+    assert!(parse_ilda(&data[..40]).is_err(), "truncated points");
+    assert!(parse_ilda(&data[..20]).is_err(), "truncated header");
+
+    let mut palette = data.clone();
+    palette[7] = 1;
+    assert!(parse_ilda(&palette).is_err(), "indexed colour");
+
+    let mut garbage = data;
+    garbage[0] = b'X';
+    assert!(parse_ilda(&garbage).is_err(), "no magic");
+}
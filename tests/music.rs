@@ -0,0 +1,466 @@
+// Loads Impulse Tracker modules laid out here byte by byte, the way the
+// tracker saves them, and recognises each format by its header. Recordings
+// are WAVs written here with hound, played against a tempo, and the events a
+// tracker reports come from a small XM written here too.
+
+use std::io::Cursor;
+
+use laser_demo::{it, load_module, EventKind, Format, Player, Stream, Tempo, Tracker, TrackerEvent};
+
+// An 8-bit sample, either plain signed bytes or IT214 compressed
+enum Sample {
+    Plain(Vec<i8>),
+    Compressed(Vec<i8>),
+}
+
+#[derive(Default)]
+struct Cell {
+    note: Option<u8>,
+    instrument: Option<u8>,
+    volume: Option<u8>,
+    effect: Option<(u8, u8)>,
+}
+
+// Packs rows of (channel, cell) as IT does, with every field given in full
+fn pattern(rows: &[&[(u8, Cell)]]) -> Vec<u8> {
+    let mut packed = vec![];
+    for row in rows {
+        for (channel, cell) in row.iter() {
+            packed.push((channel + 1) | 0x80);
+            let mask = cell.note.map_or(0, |_| 1)
+                | cell.instrument.map_or(0, |_| 2)
+                | cell.volume.map_or(0, |_| 4)
+                | cell.effect.map_or(0, |_| 8);
+            packed.push(mask);
+            packed.extend(cell.note);
+            packed.extend(cell.instrument);
+            packed.extend(cell.volume);
+            packed.extend(cell.effect.into_iter().flat_map(|(command, parameter)| [command, parameter]));
+        }
+        packed.push(0);
+    }
+    packed
+}
+
+// IT214 with every delta at the starting width of 9 bits
+fn compress(frames: &[i8]) -> Vec<u8> {
+    let mut block = vec![0; (frames.len() * 9).div_ceil(8)];
+    let mut previous = 0i8;
+    for (i, &frame) in frames.iter().enumerate() {
+        let delta = frame.wrapping_sub(previous) as u8;
+        previous = frame;
+        for bit in 0..8 {
+            block[(i * 9 + bit) / 8] |= (delta >> bit & 1) << ((i * 9 + bit) % 8);
+        }
+    }
+    let mut compressed = (block.len() as u16).to_le_bytes().to_vec();
+    compressed.extend(block);
+    compressed
+}
+
+// Each instrument's 8-bit samples in an XM, with the delta encoding undone
+fn xm_samples(xm: &[u8]) -> Vec<Vec<Vec<i8>>> {
+    let u16_at = |at: usize| u16::from_le_bytes([xm[at], xm[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes([xm[at], xm[at + 1], xm[at + 2], xm[at + 3]]) as usize;
+    let mut at = 60 + u32_at(60);
+    for _ in 0..u16_at(70) {
+        at += u32_at(at) + u16_at(at + 7);
+    }
+    let mut instruments = vec![];
+    for _ in 0..u16_at(72) {
+        let count = u16_at(at + 27);
+        at += u32_at(at);
+        let lengths = (0..count).map(|i| u32_at(at + 40 * i)).collect::<Vec<_>>();
+        at += 40 * count;
+        let mut samples = vec![];
+        for length in lengths {
+            let mut frame = 0i8;
+            samples.push(xm[at..at + length].iter().map(|&delta| {
+                frame = frame.wrapping_add(delta as i8);
+                frame
+            }).collect());
+            at += length;
+        }
+        instruments.push(samples);
+    }
+    instruments
+}
+
+// A module without instruments, so patterns play the samples directly.
+// Patterns are (rows, packed data), or None for one that was never saved.
+fn module(orders: &[u8], samples: &[Sample], patterns: &[Option<(u16, Vec<u8>)>]) -> Vec<u8> {
+    let mut it = b"IMPM".to_vec();
+    it.extend(b"test\0".iter().copied().chain(std::iter::repeat(0)).take(26));
+    it.extend_from_slice(&[4, 16]);
+    for count in [orders.len(), 0, samples.len(), patterns.len()] {
+        it.extend_from_slice(&(count as u16).to_le_bytes());
+    }
+    // Made with and compatible with 2.14, stereo with linear slides
+    it.extend_from_slice(&[0x14, 0x02, 0x14, 0x02, 0x09, 0x00, 0x00, 0x00]);
+    it.extend_from_slice(&[128, 48, 6, 125, 128, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    it.extend_from_slice(&[32; 64]);
+    it.extend_from_slice(&[64; 64]);
+    it.extend_from_slice(orders);
+
+    let samples_at = it.len() + 4 * (samples.len() + patterns.len());
+    let patterns_at = samples_at + 0x50 * samples.len();
+    let mut offset = patterns_at;
+    for i in 0..samples.len() {
+        it.extend_from_slice(&((samples_at + 0x50 * i) as u32).to_le_bytes());
+    }
+    let mut data = vec![];
+    for pattern in patterns {
+        match pattern {
+            Some((rows, packed)) => {
+                it.extend_from_slice(&(offset as u32).to_le_bytes());
+                data.extend_from_slice(&(packed.len() as u16).to_le_bytes());
+                data.extend_from_slice(&rows.to_le_bytes());
+                data.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(packed);
+                offset += 8 + packed.len();
+            }
+            None => it.extend_from_slice(&0u32.to_le_bytes()),
+        }
+    }
+
+    for sample in samples {
+        let (flags, frames) = match sample {
+            Sample::Plain(frames) => (0x01, frames),
+            Sample::Compressed(frames) => (0x09, frames),
+        };
+        let stored = match sample {
+            Sample::Plain(frames) => frames.iter().map(|&frame| frame as u8).collect(),
+            Sample::Compressed(frames) => compress(frames),
+        };
+        it.extend_from_slice(b"IMPS");
+        it.extend_from_slice(&[0; 12]);
+        it.extend_from_slice(&[0, 64, flags, 64]);
+        it.extend_from_slice(&[0; 26]);
+        // Signed, panned to the middle
+        it.extend_from_slice(&[1, 0x80 | 32]);
+        for value in [frames.len(), 0, 0, 8363, 0, 0, offset] {
+            it.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        it.extend_from_slice(&[0; 4]);
+        data.extend(stored);
+        offset = patterns_at + data.len();
+    }
+    it.extend(data);
+    it
+}
+
+#[test]
+fn it_orders() {
+    let it = module(
+        &[0, 254, 1, 0, 255, 1],
+        &[],
+        &[Some((32, pattern(&[&[][..]; 32]))), None],
+    );
+    let module = load_module(&it).unwrap();
+
+    // The +++ separator is dropped and nothing after the end mark is played
+    assert_eq!(module.pattern_order, vec![0, 1, 0]);
+    assert_eq!(module.pattern.len(), 2);
+    assert_eq!(module.pattern[0].len(), 32);
+    assert_eq!(module.pattern[1].len(), 64);
+}
+
+#[test]
+fn it_cells() {
+    let rows: [&[(u8, Cell)]; 5] = [
+        &[
+            (0, Cell { note: Some(60), instrument: Some(1), volume: Some(32), effect: Some((1, 6)) }),
+            (2, Cell { note: Some(255), ..Default::default() }),
+        ],
+        &[
+            (0, Cell { note: Some(254), ..Default::default() }),
+            (1, Cell { effect: Some((3, 16)), ..Default::default() }),
+        ],
+        &[(0, Cell { volume: Some(110), ..Default::default() })],
+        &[(1, Cell { effect: Some((4, 0xF3)), ..Default::default() })],
+        &[(1, Cell { note: Some(0), effect: Some((19, 0xB2)), ..Default::default() })],
+    ];
+    let it = module(&[0], &[Sample::Plain(vec![0, 64, 127, 64, 0, -64, -128, -64])], &[Some((5, pattern(&rows)))]);
+    let module = load_module(&it).unwrap();
+    let pattern = &module.pattern[0];
+    let slot = |row: usize, channel: usize| {
+        let slot = &pattern[row][channel];
+        (u8::from(slot.note), slot.volume, slot.effect_type, slot.effect_parameter)
+    };
+
+    // Up to the last channel used, rounded up to an even number
+    assert!(pattern.iter().all(|row| row.len() == 4));
+    // IT's C-5 is XM's C-4, and the volume column volume is moved up by 0x10
+    assert_eq!(slot(0, 0), (49, 0x30, 0xF, 6));
+    assert_eq!(slot(0, 2), (97, 0, 0, 0));
+    // A note cut becomes EC0
+    assert_eq!(slot(1, 0), (0, 0, 0xE, 0xC0));
+    // Pattern break rows are decimal in XM
+    assert_eq!(slot(1, 1), (0, 0, 0xD, 0x16));
+    // A volume column pitch slide goes in the effect column
+    assert_eq!(slot(2, 0), (0, 0, 0x2, 20));
+    assert_eq!(slot(3, 1), (0, 0, 0xE, 0xB3));
+    // Notes below XM's range are moved up an octave, and SBx is E6x
+    assert_eq!(slot(4, 1), (1, 0, 0xE, 0x62));
+}
+
+#[test]
+fn it_samples() {
+    let frames = (0..300).map(|i| ((i * 7) % 256) as u8 as i8).collect::<Vec<_>>();
+    let it = module(
+        &[0],
+        &[Sample::Compressed(frames.clone()), Sample::Plain(frames.clone())],
+        &[Some((1, pattern(&[&[(0, Cell { note: Some(60), instrument: Some(1), ..Default::default() })]])))],
+    );
+    load_module(&it).unwrap();
+
+    // Compressed or not, each sample becomes an instrument of its own with the
+    // same frames
+    assert_eq!(xm_samples(&it::to_xm(&it).unwrap()), vec![vec![frames.clone()], vec![frames]]);
+}
+
+// However the file is cut short, it's an error rather than a panic
+#[test]
+fn it_truncated() {
+    let it = module(
+        &[0],
+        &[Sample::Compressed(vec![1, 2, 3, 4]), Sample::Plain(vec![1, 2, 3, 4])],
+        &[Some((1, pattern(&[&[(0, Cell { note: Some(60), instrument: Some(1), ..Default::default() })]])))],
+    );
+    load_module(&it).unwrap();
+    for length in 0..it.len() {
+        assert!(load_module(&it[..length]).is_err(), "loaded {} of {} bytes", length, it.len());
+    }
+}
+
+// `data` long enough to hold a MOD's tag, with `tag` at `offset`
+fn header(offset: usize, tag: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; 1084 + 64];
+    data[offset..offset + tag.len()].copy_from_slice(tag);
+    data
+}
+
+#[test]
+fn detect_formats() {
+    assert_eq!(Format::detect(&header(0, b"Extended Module: test")), Some(Format::Xm));
+    assert_eq!(Format::detect(&header(0, b"IMPM")), Some(Format::It));
+    assert_eq!(Format::detect(&header(44, b"SCRM")), Some(Format::S3m));
+    // MOD tags say how many channels there are, in several ways
+    for tag in [b"M.K.", b"M!K!", b"FLT8", b"6CHN", b"8CHN", b"12CH", b"32CH"] {
+        assert_eq!(Format::detect(&header(1080, tag)), Some(Format::Mod), "{}", String::from_utf8_lossy(tag));
+    }
+
+    // Tags anywhere else, or that only look like one, aren't recognised
+    assert_eq!(Format::detect(&header(40, b"SCRM")), None);
+    assert_eq!(Format::detect(&header(1076, b"M.K.")), None);
+    for tag in [b"XCHN", b"1XCH", b"M.K!", b"\0\0\0\0"] {
+        assert_eq!(Format::detect(&header(1080, tag)), None, "{}", String::from_utf8_lossy(tag));
+    }
+    // Nor is a file cut off before the tag
+    assert_eq!(Format::detect(&header(1080, b"M.K.")[..1082]), None);
+    assert_eq!(Format::detect(&header(44, b"SCRM")[..46]), None);
+    assert_eq!(Format::detect(b""), None);
+}
+
+#[test]
+fn tempo_parse() {
+    let tempo = Tempo::parse("tempo.ron", "(bpm: 120, rows_per_beat: 4)").unwrap();
+    assert_eq!((tempo.bpm, tempo.rows_per_beat, tempo.rows_per_pattern, tempo.offset), (120.0, 4, 64, 0.0));
+    let tempo = Tempo::parse("tempo.ron", "(bpm: 90.5, rows_per_beat: 3, rows_per_pattern: 48, offset: 1.25)").unwrap();
+    assert_eq!((tempo.bpm, tempo.rows_per_beat, tempo.rows_per_pattern, tempo.offset), (90.5, 3, 48, 1.25));
+
+    for source in [
+        "(bpm: 0, rows_per_beat: 4)",
+        "(bpm: -120, rows_per_beat: 4)",
+        "(bpm: 120, rows_per_beat: 0)",
+        "(bpm: 120, rows_per_beat: 4, rows_per_pattern: 0)",
+        "(rows_per_beat: 4)",
+        "(bpm: 120, rows_per_beat: 4",
+    ] {
+        let e = Tempo::parse("tempo.ron", source).unwrap_err();
+        assert!(e.to_string().starts_with("tempo.ron:"), "{}: {}", source, e);
+    }
+}
+
+#[test]
+fn tempo_rows() {
+    // Rows an eighth of a second long, starting half a second in
+    let tempo = Tempo::parse("tempo.ron", "(bpm: 120, rows_per_beat: 4, rows_per_pattern: 16, offset: 0.5)").unwrap();
+    assert_eq!(tempo.time(0, 0), 0.5);
+    assert_eq!(tempo.time(0, 3), 0.875);
+    assert_eq!(tempo.time(2, 5), 0.5 + 37.0 * 0.125);
+    assert_eq!(tempo.song_row(0.0), 0);
+    assert_eq!(tempo.song_row(0.5), 0);
+    assert_eq!(tempo.song_row(0.6), 0);
+    assert_eq!(tempo.song_row(0.625), 1);
+    for (pattern, row) in [(0, 0), (0, 15), (1, 0), (7, 9)] {
+        assert_eq!(tempo.song_row(tempo.time(pattern, row)), pattern * 16 + row);
+    }
+}
+
+// The frame rate of the recordings
+const RATE: u32 = 8000;
+
+// Two seconds of mono whose frame n is n/32768, so the value says which frame
+// of the file is playing
+fn recording() -> &'static [u8] {
+    let mut data = vec![];
+    let spec = hound::WavSpec { channels: 1, sample_rate: RATE, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::new(Cursor::new(&mut data), spec).unwrap();
+    for frame in 0..2 * RATE {
+        writer.write_sample(frame as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    Box::leak(data.into_boxed_slice())
+}
+
+// Rows an eighth of a second, or 1000 frames, long, starting 2000 frames in
+fn stream(sample_rate: u32) -> Stream {
+    let tempo = Tempo::parse("tempo.ron", "(bpm: 120, rows_per_beat: 4, rows_per_pattern: 4, offset: 0.25)").unwrap();
+    Stream::new(recording(), Some("wav"), tempo, sample_rate).unwrap()
+}
+
+// The next `count` frames, as the file frames they play
+fn frames(stream: &mut Stream, count: usize) -> Vec<f32> {
+    (0..count).map(|_| {
+        let left = stream.next().unwrap();
+        let right = stream.next().unwrap();
+        assert_eq!(left, right);
+        left * 32768.0
+    }).collect()
+}
+
+#[test]
+fn stream_position() {
+    let mut stream = stream(RATE);
+    // The 1.75 seconds after the offset are 14 rows and the start of a 15th
+    assert_eq!(stream.pattern_rows(), vec![4; 4]);
+    assert_eq!(stream.position(), (0, 0));
+    assert_eq!(frames(&mut stream, 3), [0.0, 1.0, 2.0]);
+
+    stream.goto(1, 2);
+    assert_eq!(stream.position(), (1, 2));
+    assert_eq!(frames(&mut stream, 3), [8000.0, 8001.0, 8002.0]);
+    frames(&mut stream, 997);
+    assert_eq!(stream.position(), (1, 3));
+
+    // Back again
+    stream.goto(0, 1);
+    assert_eq!(stream.position(), (0, 1));
+    assert_eq!(frames(&mut stream, 2), [3000.0, 3001.0]);
+    stream.goto(0, 0);
+    assert_eq!(frames(&mut stream, 1), [2000.0]);
+
+    // Playing on from the last full row runs out at the end of the file, whose
+    // very last frame is only there to be interpolated towards
+    stream.goto(3, 1);
+    assert_eq!(frames(&mut stream, 999), (15000..15999).map(|frame| frame as f32).collect::<Vec<_>>());
+    assert_eq!(stream.next(), None);
+}
+
+#[test]
+fn stream_resampling() {
+    // Twice the file's rate plays every frame and one halfway to the next
+    let mut faster = stream(2 * RATE);
+    faster.goto(1, 2);
+    assert_eq!(faster.position(), (1, 2));
+    assert_eq!(frames(&mut faster, 5), [8000.0, 8000.5, 8001.0, 8001.5, 8002.0]);
+    frames(&mut faster, 1995);
+    assert_eq!(faster.position(), (1, 3));
+
+    // Half of it skips every other frame
+    let mut slower = stream(RATE / 2);
+    slower.goto(1, 2);
+    assert_eq!(frames(&mut slower, 3), [8000.0, 8002.0, 8004.0]);
+    frames(&mut slower, 497);
+    assert_eq!(slower.position(), (1, 3));
+}
+
+// A one-pattern XM whose rows hold (note, instrument, volume, effect, parameter)
+// for each channel, with one instrument that has no samples
+fn xm(rows: &[&[[u8; 5]]]) -> Vec<u8> {
+    let mut xm = b"Extended Module: ".to_vec();
+    xm.extend_from_slice(&[0; 20]);
+    xm.push(0x1A);
+    xm.extend_from_slice(&[0; 20]);
+    xm.extend_from_slice(&0x0104u16.to_le_bytes());
+    xm.extend_from_slice(&276u32.to_le_bytes());
+    // Order length, restart, channels, patterns, instruments, linear
+    // frequencies, speed and bpm
+    for field in [1, 0, rows[0].len() as u16, 1, 1, 1, 6, 125] {
+        xm.extend_from_slice(&field.to_le_bytes());
+    }
+    xm.extend_from_slice(&[0; 256]);
+
+    let cells = rows.iter().flat_map(|row| row.iter().flatten()).copied().collect::<Vec<_>>();
+    xm.extend_from_slice(&9u32.to_le_bytes());
+    xm.push(0);
+    xm.extend_from_slice(&(rows.len() as u16).to_le_bytes());
+    xm.extend_from_slice(&(cells.len() as u16).to_le_bytes());
+    xm.extend_from_slice(&cells);
+
+    xm.extend_from_slice(&263u32.to_le_bytes());
+    xm.extend_from_slice(&[0; 25]);
+    xm.extend_from_slice(&40u32.to_le_bytes());
+    xm.extend_from_slice(&[0; 230]);
+    xm
+}
+
+// Jumps to `row` and plays a few samples of it
+fn play_row(tracker: &mut Tracker, row: usize) {
+    tracker.goto(0, row);
+    for _ in 0..16 {
+        tracker.next();
+    }
+}
+
+#[test]
+fn tracker_events() {
+    let xm = xm(&[
+        &[[49, 1, 0x40, 0, 0], [0; 5]],
+        // 0x65 in the volume column slides the volume down
+        &[[97, 0, 0, 0, 0], [61, 1, 0x65, 0xE, 0x85]],
+        &[[0; 5], [0; 5]],
+        &[[50, 1, 0, 0xC, 0x20], [0; 5]],
+    ]);
+    let mut tracker = Tracker::new(load_module(&xm).unwrap(), 48000);
+    let event = |row, channel, kind| TrackerEvent { pattern: 0, row, channel, kind };
+    let row_0 = vec![event(0, 0, EventKind::Note { note: 49, instrument: Some(0), volume: Some(0x30) })];
+    let row_1 = vec![
+        event(1, 0, EventKind::NoteOff),
+        event(1, 1, EventKind::Note { note: 61, instrument: Some(0), volume: None }),
+        event(1, 1, EventKind::Effect { effect: 0xE, parameter: 0x85 }),
+    ];
+    let row_3 = vec![
+        event(3, 0, EventKind::Note { note: 50, instrument: Some(0), volume: None }),
+        event(3, 0, EventKind::Effect { effect: 0xC, parameter: 0x20 }),
+    ];
+
+    // Nothing before anything was played
+    assert_eq!(tracker.take_events(), []);
+    for _ in 0..16 {
+        tracker.next();
+    }
+    assert_eq!(tracker.take_events(), row_0);
+    // Each row's events are only reported once, however long it plays
+    for _ in 0..16 {
+        tracker.next();
+    }
+    assert_eq!(tracker.take_events(), []);
+
+    play_row(&mut tracker, 1);
+    assert_eq!(tracker.take_events(), row_1);
+    play_row(&mut tracker, 2);
+    assert_eq!(tracker.take_events(), []);
+    play_row(&mut tracker, 3);
+    assert_eq!(tracker.take_events(), row_3);
+
+    // Jumping drops what wasn't taken yet, and the row jumped to is reported
+    // again even if it's the one that was playing
+    play_row(&mut tracker, 1);
+    tracker.goto(0, 3);
+    assert_eq!(tracker.take_events(), []);
+    play_row(&mut tracker, 1);
+    play_row(&mut tracker, 1);
+    assert_eq!(tracker.take_events(), row_1);
+}
@@ -0,0 +1,188 @@
+// Sends OSC packets over a local socket to a listening remote, as a controller
+// would.
+
+use std::net::UdpSocket;
+
+use laser_demo::{Command, FinalParam, PressureSolver, Remote, Scene, Transition};
+use web_time::{Duration, Instant};
+
+enum Argument<'a> {
+    Int(i32),
+    Float(f32),
+    String(&'a str),
+    Bool(bool),
+}
+
+fn osc_string(packet: &mut Vec<u8>, string: &str) {
+    packet.extend_from_slice(string.as_bytes());
+    packet.push(0);
+    packet.resize(packet.len().next_multiple_of(4), 0);
+}
+
+fn message(address: &str, arguments: &[Argument]) -> Vec<u8> {
+    let mut packet = vec![];
+    osc_string(&mut packet, address);
+    let tags = std::iter::once(',')
+        .chain(arguments.iter().map(|argument| match argument {
+            Argument::Int(_) => 'i',
+            Argument::Float(_) => 'f',
+            Argument::String(_) => 's',
+            Argument::Bool(true) => 'T',
+            Argument::Bool(false) => 'F',
+        }))
+        .collect::<String>();
+    osc_string(&mut packet, &tags);
+    for argument in arguments {
+        match argument {
+            Argument::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::String(value) => osc_string(&mut packet, value),
+            Argument::Bool(_) => {}
+        }
+    }
+    packet
+}
+
+// With the "immediately" time tag
+fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+    let mut packet = b"#bundle\0".to_vec();
+    packet.extend_from_slice(&1u64.to_be_bytes());
+    for element in elements {
+        packet.extend_from_slice(&(element.len() as u32).to_be_bytes());
+        packet.extend_from_slice(element);
+    }
+    packet
+}
+
+fn remote() -> (Remote, UdpSocket) {
+    let remote = Remote::bind("127.0.0.1:0").unwrap();
+    let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+    controller.connect(remote.local_addr().unwrap()).unwrap();
+    (remote, controller)
+}
+
+// Sends `packets` and receives until `count` commands came out of them
+fn exchange(packets: &[Vec<u8>], count: usize) -> Vec<Command> {
+    let (mut remote, controller) = remote();
+    for packet in packets {
+        controller.send(packet).unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut commands = vec![];
+    while commands.len() < count {
+        assert!(Instant::now() < deadline, "only got {:?}", commands);
+        commands.extend(remote.receive());
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(commands.len(), count, "{:?}", commands);
+    commands
+}
+
+#[test]
+fn messages() {
+    let commands = exchange(&[
+        message("/scene", &[Argument::String("Smoke(3)")]),
+        message("/scene", &[Argument::String("CDs(9)"), Argument::String("Fade(0.5)")]),
+        message("/transition", &[Argument::String("Blink")]),
+        message("/laser/2/color", &[Argument::Float(1.0), Argument::Float(0.5), Argument::Float(0.0)]),
+        message("/laser/64/color", &[]),
+        message("/final/x2", &[Argument::Float(0.25)]),
+        message("/final/transition", &[]),
+        message("/pause", &[]),
+        message("/pause", &[Argument::Bool(true)]),
+        message("/pressure", &[Argument::String("multigrid"), Argument::Int(3)]),
+        message("/pressure", &[Argument::String("jacobi")]),
+        message("/release", &[]),
+    ], 12);
+
+    assert!(matches!(commands[0], Command::Scene(Scene::Smoke(3), Transition::None)), "{:?}", commands[0]);
+    assert!(matches!(commands[1], Command::Scene(Scene::CDs(9), Transition::Fade(time)) if time == 0.5), "{:?}", commands[1]);
+    assert!(matches!(commands[2], Command::Transition(Transition::Blink)), "{:?}", commands[2]);
+    assert!(matches!(commands[3], Command::LaserColor(1, Some([1.0, 0.5, 0.0, 0.0]))), "{:?}", commands[3]);
+    assert!(matches!(commands[4], Command::LaserColor(63, None)), "{:?}", commands[4]);
+    assert!(matches!(commands[5], Command::FinalParam(FinalParam::X2, Some(value)) if value == 0.25), "{:?}", commands[5]);
+    assert!(matches!(commands[6], Command::FinalParam(FinalParam::Transition, None)), "{:?}", commands[6]);
+    assert!(matches!(commands[7], Command::Pause(None)), "{:?}", commands[7]);
+    assert!(matches!(commands[8], Command::Pause(Some(true))), "{:?}", commands[8]);
+    assert!(matches!(commands[9], Command::Pressure(PressureSolver::Multigrid(3))), "{:?}", commands[9]);
+    assert!(matches!(commands[10], Command::Pressure(PressureSolver::Jacobi)), "{:?}", commands[10]);
+    assert!(matches!(commands[11], Command::Release), "{:?}", commands[11]);
+}
+
+// Many controllers can only send floats
+#[test]
+fn floats_as_integers() {
+    let commands = exchange(&[
+        message("/seek", &[Argument::Float(3.0), Argument::Float(16.0)]),
+        message("/step", &[Argument::Float(5.0)]),
+        message("/fluid", &[Argument::Float(64.0)]),
+        message("/fluid", &[Argument::Float(32.0), Argument::Int(48), Argument::Float(64.0)]),
+        message("/pause", &[Argument::Float(0.0)]),
+        message("/laser/1/color", &[Argument::Int(1), Argument::Int(0), Argument::Int(0), Argument::Int(1)]),
+        // Not whole, so not an index
+        message("/seek", &[Argument::Float(1.5), Argument::Int(2)]),
+        message("/step", &[Argument::Float(-1.0)]),
+        message("/release", &[]),
+    ], 7);
+
+    assert!(matches!(commands[0], Command::Seek(3, 16)), "{:?}", commands[0]);
+    assert!(matches!(commands[1], Command::Step(5)), "{:?}", commands[1]);
+    assert!(matches!(commands[2], Command::FluidSize((64, 64, 64))), "{:?}", commands[2]);
+    assert!(matches!(commands[3], Command::FluidSize((32, 48, 64))), "{:?}", commands[3]);
+    assert!(matches!(commands[4], Command::Pause(Some(false))), "{:?}", commands[4]);
+    assert!(matches!(commands[5], Command::LaserColor(0, Some([1.0, 0.0, 0.0, 1.0]))), "{:?}", commands[5]);
+    assert!(matches!(commands[6], Command::Release), "{:?}", commands[6]);
+}
+
+#[test]
+fn bundles() {
+    let commands = exchange(&[
+        bundle(&[
+            message("/step", &[Argument::Int(1)]),
+            bundle(&[
+                message("/step", &[Argument::Int(2)]),
+                message("/step", &[Argument::Int(3)]),
+            ]),
+            message("/step", &[Argument::Int(4)]),
+        ]),
+        bundle(&[]),
+        message("/step", &[Argument::Int(5)]),
+    ], 5);
+
+    for (step, command) in commands.iter().enumerate() {
+        assert!(matches!(command, Command::Step(n) if *n == step + 1), "{:?}", commands);
+    }
+}
+
+// Whatever is wrong with a packet, it's dropped and the remote carries on
+#[test]
+fn malformed_packets() {
+    let mut truncated = message("/seek", &[Argument::Int(1), Argument::Int(2)]);
+    truncated.truncate(truncated.len() - 2);
+    let mut unterminated = message("/release", &[]);
+    unterminated.truncate(3);
+    let mut overlong = bundle(&[message("/release", &[])]);
+    overlong.truncate(overlong.len() - 4);
+
+    let commands = exchange(&[
+        message("scene", &[Argument::String("Black")]),
+        truncated,
+        unterminated,
+        overlong,
+        b"#bundle\0".to_vec(),
+        b"/step\0\0\0,b\0\0\0\0\0\x01".to_vec(),
+        b"/step\0\0\0i\0\0\0\0\0\0\x01".to_vec(),
+        vec![0xff; 8],
+        message("/nothing", &[]),
+        message("/scene", &[Argument::String("Smoke(")]),
+        message("/scene", &[Argument::Int(1)]),
+        message("/laser/65/color", &[]),
+        message("/laser/1/color", &[Argument::Float(1.0), Argument::Float(f32::NAN), Argument::Float(0.0)]),
+        message("/final/y", &[Argument::Float(1.0)]),
+        message("/pressure", &[Argument::String("multigrid"), Argument::Int(0)]),
+        message("/pressure", &[Argument::String("gauss-seidel")]),
+        message("/release", &[]),
+    ], 1);
+
+    assert!(matches!(commands[0], Command::Release), "{:?}", commands);
+}
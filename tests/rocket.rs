@@ -0,0 +1,146 @@
+// Talks to a stand-in for a GNU Rocket editor over a local socket.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use laser_demo::{EditorEvent, Rocket};
+use web_time::{Duration, Instant};
+
+fn read_u32(stream: &mut TcpStream) -> u32 {
+    let mut bytes = [0; 4];
+    stream.read_exact(&mut bytes).unwrap();
+    u32::from_be_bytes(bytes)
+}
+
+fn read_command(stream: &mut TcpStream) -> u8 {
+    let mut command = [0];
+    stream.read_exact(&mut command).unwrap();
+    command[0]
+}
+
+fn set_key(stream: &mut TcpStream, track: u32, row: u32, value: f32, interpolation: u8) {
+    let mut message = vec![0];
+    message.extend_from_slice(&track.to_be_bytes());
+    message.extend_from_slice(&row.to_be_bytes());
+    message.extend_from_slice(&value.to_bits().to_be_bytes());
+    message.push(interpolation);
+    stream.write_all(&message).unwrap();
+}
+
+// Keeps updating until `done` holds, as the editor's messages arrive on another thread
+fn update_until(rocket: &mut Rocket, row: u32, mut done: impl FnMut(&mut Rocket, &[EditorEvent]) -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let events = rocket.update(row, Duration::ZERO);
+        if done(rocket, &events) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "timed out waiting for the editor");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn editor_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let editor = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut greeting = [0; 19];
+        stream.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting, b"hello, synctracker!");
+        stream.write_all(b"hello, demo!").unwrap();
+
+        // The demo asks for its track by name...
+        assert_eq!(read_command(&mut stream), 2);
+        let length = read_u32(&mut stream) as usize;
+        let mut name = vec![0; length];
+        stream.read_exact(&mut name).unwrap();
+        assert_eq!(name, b"camera:zoom");
+
+        // ...and gets its keys: 1.0 at row 0 going linearly to 3.0 at row 8,
+        // which then steps to 5.0 at row 16
+        set_key(&mut stream, 0, 0, 1.0, 1);
+        set_key(&mut stream, 0, 8, 3.0, 0);
+        set_key(&mut stream, 0, 16, 5.0, 0);
+
+        // The demo reports its row as it plays
+        for row in [0, 4] {
+            assert_eq!(read_command(&mut stream), 3);
+            assert_eq!(read_u32(&mut stream), row);
+        }
+
+        // Pause and scrub
+        stream.write_all(&[4, 1]).unwrap();
+        let mut message = vec![3];
+        message.extend_from_slice(&32u32.to_be_bytes());
+        stream.write_all(&message).unwrap();
+
+        // Stay connected until the demo hangs up
+        let _ = stream.read_to_end(&mut vec![]);
+    });
+
+    let mut rocket = Rocket::connect(&address).unwrap();
+    assert!(rocket.is_connected());
+    assert_eq!(rocket.value("camera:zoom"), None);
+
+    update_until(&mut rocket, 0, |rocket, _| {
+        rocket.tracks()[0].keys.len() == 3
+    });
+    assert_eq!(rocket.value("camera:zoom"), Some(1.0));
+
+    let mut events = rocket.update(4, Duration::ZERO);
+    assert_eq!(rocket.value("camera:zoom"), Some(2.0));
+
+    update_until(&mut rocket, 4, |_, new_events| {
+        events.extend_from_slice(new_events);
+        events.len() == 2
+    });
+    assert_eq!(events, [EditorEvent::Pause(true), EditorEvent::Seek(32)]);
+
+    rocket.update(12, Duration::ZERO);
+    assert_eq!(rocket.value("camera:zoom"), Some(3.0));
+    rocket.update(40, Duration::ZERO);
+    assert_eq!(rocket.value("camera:zoom"), Some(5.0));
+
+    drop(rocket);
+    editor.join().unwrap();
+}
+
+#[test]
+fn saved_tracks() {
+    let mut rocket = Rocket::from_source("sync.ron", r#"[
+        (name: "fade", keys: [
+            (row: 10, value: 0.0, interpolation: Smooth),
+            (row: 20, value: 1.0, interpolation: Step),
+        ]),
+    ]"#).unwrap();
+
+    rocket.update(0, Duration::ZERO);
+    assert_eq!(rocket.value("fade"), Some(0.0));
+    rocket.update(15, Duration::ZERO);
+    assert_eq!(rocket.value("fade"), Some(0.5));
+    rocket.update(25, Duration::ZERO);
+    assert_eq!(rocket.value("fade"), Some(1.0));
+    assert_eq!(rocket.value("missing"), None);
+}
+
+#[test]
+fn rows_between_steps() {
+    let mut rocket = Rocket::from_source("sync.ron", r#"[
+        (name: "x", keys: [
+            (row: 0, value: 0.0, interpolation: Linear),
+            (row: 2, value: 2.0, interpolation: Step),
+        ]),
+    ]"#).unwrap();
+
+    // One row per 100ms, so halfway to the next row is 50ms in
+    rocket.update(0, Duration::from_millis(0));
+    rocket.update(1, Duration::from_millis(100));
+    rocket.update(1, Duration::from_millis(150));
+    assert!((rocket.value("x").unwrap() - 1.5).abs() < 1e-4);
+}